@@ -0,0 +1,58 @@
+//! Times full-drive imports and comparisons. Run with `cargo bench`.
+//! 
+//! Uses a plain timing loop rather than a bench framework, so the numbers are rough but there's nothing extra to build.
+
+use std::hint::black_box;
+use std::time::Instant;
+use avdrive::{Avd, BLOCK_COUNT};
+
+fn time(name: &str, iters: u32, mut f: impl FnMut()) {
+    f(); // warm up
+    let start = Instant::now();
+    for _ in 0..iters {
+        f()
+    }
+    let per = start.elapsed() / iters;
+    println!("{name:<40} {per:>12?}");
+}
+
+/// The byte-at-a-time check the drive used before, for comparison.
+fn is_zero_bytes(data: &[u8; 256]) -> bool {
+    data.iter().all(|b| *b == 0)
+}
+fn is_zero_words(data: &[u8; 256]) -> bool {
+    data.chunks_exact(8).map(|c| u64::from_ne_bytes(c.try_into().unwrap())).fold(0, |acc, w| acc | w) == 0
+}
+
+fn main() {
+    // mostly empty, like a real image: one block in 16 has data, right at the end where the byte loop finds it last
+    let image: Vec<[u8; 256]> = (0..BLOCK_COUNT).map(|i| {
+        let mut b = [0; 256];
+        if i % 16 == 0 {
+            b[255] = 1
+        }
+        b
+    }).collect();
+
+    time("zero check, byte loop (full drive)", 20, || {
+        black_box(image.iter().filter(|b| is_zero_bytes(black_box(b))).count());
+    });
+    time("zero check, word fold (full drive)", 20, || {
+        black_box(image.iter().filter(|b| is_zero_words(black_box(b))).count());
+    });
+
+    let mut drive = Avd::new();
+    time("set_blocks import (full drive)", 20, || {
+        drive.clear();
+        drive.set_blocks(0, black_box(&image)).unwrap();
+    });
+
+    let full: Vec<[u8; 256]> = (0..BLOCK_COUNT).map(|i| [i as u8 | 1; 256]).collect();
+    let mut a = Avd::new();
+    a.set_blocks(0, &full).unwrap();
+    let mut b = Avd::new();
+    b.set_blocks(0, &full).unwrap();
+    time("drive comparison (full drive)", 20, || {
+        assert!(black_box(&a) == black_box(&b));
+    });
+}
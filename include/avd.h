@@ -0,0 +1,52 @@
+/* C interface to the avdrive crate. Build the library with
+ *   cargo rustc --release --features ffi --crate-type cdylib
+ * Keep this in sync with src/ffi.rs. */
+
+#ifndef AVD_H
+#define AVD_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* An emulated 16mb drive, made of 65536 blocks of 256 bytes. */
+typedef struct Avd Avd;
+
+/* Return codes. 0 is success, errors are negative. */
+#define AVD_OK 0
+#define AVD_ERR_ARG (-1)         /* a pointer was null, or a path wasn't valid UTF-8 */
+#define AVD_ERR_IO (-2)
+#define AVD_ERR_FORMAT (-3)      /* not an archive, or damaged */
+#define AVD_ERR_CHECKSUM (-4)
+#define AVD_ERR_UNSUPPORTED (-5)
+#define AVD_ERR_OTHER (-6)
+#define AVD_ERR_WRITE_PROTECTED (-7)
+#define AVD_ERR_BAD_SECTOR (-8)      /* the block was marked bad */
+
+/* Create a new, blank drive. Free it with avd_free. */
+Avd *avd_new(void);
+/* Free a drive from avd_new or avd_load. Null is ignored. */
+void avd_free(Avd *avd);
+/* Load an archive file into a new drive, stored in *out (null on failure). */
+int avd_load(const char *path, Avd **out);
+/* Save a drive to an archive file. */
+int avd_save(const Avd *avd, const char *path);
+/* Copy a block into out. Returns 1 if present, 0 if absent (out is zeroed). */
+int avd_get_block(const Avd *avd, uint16_t idx, uint8_t out[256]);
+/* Set a block from data. */
+int avd_set_block(Avd *avd, uint16_t idx, const uint8_t data[256]);
+/* Remove a block. */
+int avd_delete_block(Avd *avd, uint16_t idx);
+/* The number of blocks stored on the drive. */
+size_t avd_used_blocks(const Avd *avd);
+/* A static description of a return code. */
+const char *avd_error_string(int code);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,110 @@
+//! Guessing what kind of data is on the drive.
+
+use std::collections::BTreeMap;
+use crate::Avd;
+
+/// A guess at what a block holds, from `BlockKind::classify`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BlockKind {
+    /// Every byte is the same, like a filled or formatted block.
+    Uniform,
+    /// Printable ASCII and whitespace, maybe padded out with zeros.
+    Text,
+    /// Looks random, so probably compressed or encrypted.
+    HighEntropy,
+    /// Anything else, like code or filesystem structures.
+    Binary,
+}
+
+/// Shannon entropy of a block, in bits per byte.
+fn entropy(data: &[u8; 256]) -> f64 {
+    let mut counts = [0u32; 256];
+    for b in data {
+        counts[*b as usize] += 1
+    }
+    counts.iter().filter(|c| **c != 0).map(|c| {
+        let p = *c as f64 / 256.0;
+        -p * p.log2()
+    }).sum()
+}
+
+/// Blocks with more entropy than this count as `HighEntropy`. 256 random bytes come in around 7.2.
+const HIGH_ENTROPY: f64 = 6.5;
+
+impl BlockKind {
+    /// Guess what kind of data a block holds.
+    pub fn classify(data: &[u8; 256]) -> BlockKind {
+        if data.iter().all(|b| *b == data[0]) {
+            return BlockKind::Uniform
+        }
+        let text_len = data.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+        if data[..text_len].iter().all(|b| b.is_ascii_graphic() || matches!(b, b' ' | b'\t' | b'\n' | b'\r')) {
+            return BlockKind::Text
+        }
+        if entropy(data) > HIGH_ENTROPY {
+            BlockKind::HighEntropy
+        }
+        else {
+            BlockKind::Binary
+        }
+    }
+}
+
+/// What's on a drive, from `Avd::analyze`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContentReport {
+    /// The kind of every block on the drive, in index order. Absent blocks aren't included.
+    pub blocks: Vec<(u16, BlockKind)>,
+    /// How many blocks there are of each kind. Kinds with no blocks are left out.
+    pub totals: BTreeMap<BlockKind, usize>,
+}
+impl ContentReport {
+    /// How many blocks there are of a kind.
+    pub fn count(&self, kind: BlockKind) -> usize {
+        self.totals.get(&kind).copied().unwrap_or(0)
+    }
+}
+
+impl Avd {
+    /// Guess what kind of data a block holds, or `None` if it's absent.
+    pub fn block_kind(&self, idx: u16) -> Option<BlockKind> {
+        self.blocks.get(&idx).map(BlockKind::classify)
+    }
+    /// The Shannon entropy of a block, in bits per byte, or `None` if it's absent. 0 means every byte is the same, and 8 is as high as it goes.
+    pub fn block_entropy(&self, idx: u16) -> Option<f64> {
+        self.blocks.get(&idx).map(entropy)
+    }
+    /// Classify every block on the drive, with `BlockKind::classify`.
+    pub fn analyze(&self) -> ContentReport {
+        let mut report = ContentReport::default();
+        for (idx, data) in self.blocks() {
+            let kind = BlockKind::classify(data);
+            report.blocks.push((idx, kind));
+            *report.totals.entry(kind).or_default() += 1
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn classify() {
+        let mut drive = Avd::new();
+        drive.set_block(0, &[0xe5; 256]).unwrap();
+        drive.write_bytes(0x100, b"Hello, world!\r\n\tgoodbye\n");
+        drive.write_bytes(0x200, &[0x4c, 0x00, 0x10, 0x60, 0x00]);
+        let mut x = 1u32;
+        drive.modify_block(3, |b| for v in b.iter_mut() {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345);
+            *v = (x >> 16) as u8
+        });
+        assert_eq!(drive.block_entropy(0), Some(0.0));
+        assert!(drive.block_entropy(3).unwrap() > 7.0);
+        let report = drive.analyze();
+        assert_eq!(report.blocks, [(0, BlockKind::Uniform), (1, BlockKind::Text), (2, BlockKind::Binary), (3, BlockKind::HighEntropy)]);
+        assert_eq!((report.count(BlockKind::Text), report.count(BlockKind::Binary)), (1, 1));
+        assert_eq!(drive.block_kind(4), None);
+    }
+}
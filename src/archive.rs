@@ -0,0 +1,880 @@
+//! The archive format that drives are saved in.
+//! 
+//! Every archive starts with the magic bytes `AVD`, followed by a version byte.
+//! 
+//! - Version 0: the header, then any number of 258-byte block records, each a big-endian `u16` index followed by the 256 bytes of data.
+//! - Version 1: the header, then a flags byte saying which optional features are in use, then the block records.
+//! 
+//! Flags in version 1:
+//! 
+//! - bit 0: each record has a big-endian CRC32 of its index and data tacked on the end, making it 262 bytes long.
+//! - bit 1: the archive ends with a big-endian CRC32 of everything between the header and itself.
+//! - bit 2: the block records are zstd-compressed. The checksum trailer, if there is one, covers the compressed data.
+//! - bit 3: the flags byte is followed by a metadata section (label, timestamps and comment): a big-endian `u32` length, then that many bytes. The metadata counts as part of the header, so the checksum trailer doesn't cover it.
+//! 
+//! Version 2 is made of chunks, so new things can be added without breaking older readers. The header is followed by any number of chunks, each a 4-byte tag, a big-endian `u32` payload length, the payload, and a big-endian CRC32 of the tag and payload. If the first letter of the tag is lowercase, the chunk is optional and readers that don't know it skip it. Otherwise it's required, and readers that don't know it have to give up. Chunks so far:
+//! 
+//! - `BLKS` (required, exactly one): a flags byte, using bits 0 and 2 as in version 1, then the block records.
+//! - `meta` (optional): the metadata section, as in version 1 but without the length.
+//! - `badb` (optional): the indices of blocks marked bad, each a big-endian `u16`.
+//! - `snap` (optional, any number): a named snapshot, stored as the changes that turn the blocks in `BLKS` into it. A big-endian `u16` name length, the name, a big-endian `u32` count of blocks the snapshot doesn't have, their indices as big-endian `u16`s, then 258-byte block records for the blocks that differ. Readers that skip these still get the drive itself.
+//! - `DONE` (required, last): empty, marks the end of the archive so truncation gets caught.
+//! 
+//! Blocks can appear in any order. If an index shows up more than once, the last record wins.
+
+use alloc::borrow::Cow;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use alloc::vec;
+#[cfg(feature = "std")]
+use std::io::{self, Read, Write};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+use crate::{AvdError, FrozenAvd, Result, meta::Metadata};
+
+/// A block record: the index and the data.
+pub(crate) type Record = (u16, [u8; 256]);
+
+/// The magic number at the start of every archive.
+pub(crate) const MAGIC: [u8; 3] = *b"AVD";
+
+const FLAG_BLOCK_CRC: u8 = 1;
+const FLAG_CHECKSUM: u8 = 2;
+const FLAG_COMPRESSED: u8 = 4;
+const FLAG_METADATA: u8 = 8;
+const KNOWN_FLAGS: u8 = FLAG_BLOCK_CRC | FLAG_CHECKSUM | FLAG_COMPRESSED | FLAG_METADATA;
+/// Flags that go in the `BLKS` chunk of a version 2 archive.
+const BLOCK_FLAGS: u8 = FLAG_BLOCK_CRC | FLAG_COMPRESSED;
+
+const TAG_BLKS: [u8; 4] = *b"BLKS";
+const TAG_META: [u8; 4] = *b"meta";
+const TAG_BAD: [u8; 4] = *b"badb";
+const TAG_SNAP: [u8; 4] = *b"snap";
+const TAG_DONE: [u8; 4] = *b"DONE";
+
+/// A version of the archive format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum ArchiveVersion {
+    /// The original format: a header and raw block records.
+    V0,
+    /// Adds a flags byte after the header, for optional features.
+    V1,
+    /// Splits the archive into tagged chunks, so older readers can skip parts they don't understand.
+    V2,
+}
+impl ArchiveVersion {
+    /// The newest version this crate can write.
+    pub const LATEST: ArchiveVersion = ArchiveVersion::V2;
+    /// The version byte written in the header.
+    pub fn to_u8(self) -> u8 {
+        match self {
+            ArchiveVersion::V0 => 0,
+            ArchiveVersion::V1 => 1,
+            ArchiveVersion::V2 => 2,
+        }
+    }
+    /// Get the version for a header version byte, if it's one this crate understands.
+    pub fn from_u8(v: u8) -> Option<ArchiveVersion> {
+        match v {
+            0 => Some(ArchiveVersion::V0),
+            1 => Some(ArchiveVersion::V1),
+            2 => Some(ArchiveVersion::V2),
+            _ => None
+        }
+    }
+}
+
+/// Everything about how a drive is laid out in its archive, apart from the blocks themselves.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Format {
+    /// The version asked for. Features might need a newer one, see `version()`.
+    pub version: ArchiveVersion,
+    pub block_crc: bool,
+    /// Add a checksum trailer. On by default, but only version 1 has one, and it doesn't force a newer version.
+    pub checksum: bool,
+    pub compressed: bool,
+    pub meta: Metadata,
+    /// Blocks marked bad. Only saved in version 2.
+    pub bad: BTreeSet<u16>,
+    /// Named snapshots. Having any needs version 2.
+    pub snapshots: BTreeMap<String, Arc<FrozenAvd>>,
+}
+impl Format {
+    pub fn new() -> Format {
+        Format {
+            version: ArchiveVersion::V0,
+            block_crc: false,
+            checksum: true,
+            compressed: false,
+            meta: Metadata::default(),
+            bad: BTreeSet::new(),
+            snapshots: BTreeMap::new(),
+        }
+    }
+    /// The version that will actually be written: what was asked for, or whatever the enabled features need if that's newer.
+    pub fn version(&self) -> ArchiveVersion {
+        if !self.snapshots.is_empty() {
+            self.version.max(ArchiveVersion::V2)
+        }
+        else if self.block_crc || self.compressed || !self.meta.is_empty() {
+            self.version.max(ArchiveVersion::V1)
+        }
+        else {
+            self.version
+        }
+    }
+    /// Which flags need setting in a v1 header.
+    fn flags(&self) -> u8 {
+        let mut flags = 0;
+        if self.block_crc {
+            flags |= FLAG_BLOCK_CRC
+        }
+        if self.has_checksum() {
+            flags |= FLAG_CHECKSUM
+        }
+        if self.compressed {
+            flags |= FLAG_COMPRESSED
+        }
+        if !self.meta.is_empty() {
+            flags |= FLAG_METADATA
+        }
+        flags
+    }
+    /// Whether the block records sit in the file as-is, so they can be found and changed without reading the whole archive. Not true for chunked archives, since changing a record would mean fixing up its chunk.
+    pub fn is_plain(&self) -> bool {
+        !self.compressed && self.version() < ArchiveVersion::V2
+    }
+    /// Whether a checksum trailer will actually be written.
+    pub fn has_checksum(&self) -> bool {
+        self.checksum && self.version() == ArchiveVersion::V1
+    }
+    /// The length of the trailer, in bytes.
+    #[cfg(feature = "std")]
+    pub fn trailer_len(&self) -> usize {
+        if self.has_checksum() { 4 } else { 0 }
+    }
+    /// The length of the header, in bytes. Block records start right after it, unless the archive is chunked.
+    pub fn header_len(&self) -> usize {
+        match self.version() {
+            ArchiveVersion::V0 | ArchiveVersion::V2 => 4,
+            ArchiveVersion::V1 if self.meta.is_empty() => 5,
+            ArchiveVersion::V1 => 9 + self.meta.encode().len(),
+        }
+    }
+    /// The length of one block record, in bytes.
+    pub fn record_len(&self) -> usize {
+        if self.block_crc { 262 } else { 258 }
+    }
+    /// Write out the header.
+    pub fn header(&self) -> Vec<u8> {
+        let mut ret = MAGIC.to_vec();
+        ret.push(self.version().to_u8());
+        if self.version() == ArchiveVersion::V1 {
+            ret.push(self.flags());
+            if !self.meta.is_empty() {
+                let meta = self.meta.encode();
+                ret.extend((meta.len() as u32).to_be_bytes());
+                ret.extend(meta)
+            }
+        }
+        ret
+    }
+    /// Write a block record into `rec`, which must be `record_len()` long.
+    pub fn write_record(&self, rec: &mut [u8], idx: u16, data: &[u8; 256]) {
+        rec[..2].copy_from_slice(&idx.to_be_bytes());
+        rec[2..258].copy_from_slice(data);
+        if self.block_crc {
+            let crc = crc32fast::hash(&rec[..258]);
+            rec[258..].copy_from_slice(&crc.to_be_bytes())
+        }
+    }
+    /// Read a block record out of `rec`, which must be `record_len()` long, checking its CRC if there is one.
+    pub fn read_record(&self, rec: &[u8]) -> Result<Record> {
+        let idx = u16::from_be_bytes(rec[..2].try_into().unwrap()); // SHOULD NEVER PANIC
+        if self.block_crc && crc32fast::hash(&rec[..258]).to_be_bytes() != rec[258..262] {
+            return Err(AvdError::BadBlockChecksum(idx))
+        }
+        Ok((idx, rec[2..258].try_into().unwrap()))
+    }
+    /// Parse a header from the start of `archive`. Only looks at the header, so it's fine to pass a partial archive as long as all of that is there.
+    /// 
+    /// For chunked archives, the flags and metadata are in the chunks, so they aren't filled in here.
+    pub fn parse_header(archive: &[u8]) -> Result<Format> {
+        let header = archive.get(..4).ok_or(AvdError::MalformedArchive)?;
+        if header[..3] != MAGIC {
+            return Err(AvdError::BadHeader(header[0], header[1], header[2], header[3]))
+        }
+        let version = ArchiveVersion::from_u8(header[3]).ok_or(AvdError::UnsupportedVersion(header[3]))?;
+        let mut format = Format {
+            version, ..Format::new()
+        };
+        if version == ArchiveVersion::V1 {
+            let flags = *archive.get(4).ok_or(AvdError::MalformedArchive)?;
+            if flags & !KNOWN_FLAGS != 0 {
+                return Err(AvdError::UnknownFlags(flags & !KNOWN_FLAGS))
+            }
+            format.block_crc = flags & FLAG_BLOCK_CRC != 0;
+            format.checksum = flags & FLAG_CHECKSUM != 0;
+            format.compressed = flags & FLAG_COMPRESSED != 0;
+            if flags & FLAG_METADATA != 0 {
+                let len = archive.get(5..9).ok_or(AvdError::MalformedArchive)?;
+                let len = u32::from_be_bytes(len.try_into().unwrap()) as usize; // SHOULD NEVER PANIC
+                let meta = archive.get(9..).and_then(|m| m.get(..len)).ok_or(AvdError::MalformedArchive)?;
+                format.meta = Metadata::decode(meta)?;
+            }
+        }
+        Ok(format)
+    }
+    /// Fill in the flags from a `BLKS` chunk.
+    fn set_block_flags(&mut self, flags: u8) -> Result<()> {
+        if flags & !BLOCK_FLAGS != 0 {
+            return Err(AvdError::UnknownFlags(flags & !BLOCK_FLAGS))
+        }
+        self.block_crc = flags & FLAG_BLOCK_CRC != 0;
+        self.compressed = flags & FLAG_COMPRESSED != 0;
+        Ok(())
+    }
+}
+
+/// Read just the header from the start of a stream, leaving it at the first block record.
+#[cfg(feature = "std")]
+pub(crate) fn read_header(mut r: impl Read) -> Result<Format> {
+    let mut header = vec![0; 4];
+    if read_full(&mut r, &mut header)? < 4 {
+        return Err(AvdError::MalformedArchive)
+    }
+    if header[..3] == MAGIC && header[3] == 1 {
+        let mut flags = [0];
+        if read_full(&mut r, &mut flags)? < 1 {
+            return Err(AvdError::MalformedArchive)
+        }
+        header.push(flags[0]);
+        if flags[0] & FLAG_METADATA != 0 {
+            let mut len = [0; 4];
+            if read_full(&mut r, &mut len)? < 4 {
+                return Err(AvdError::MalformedArchive)
+            }
+            header.extend(len);
+            let len = u32::from_be_bytes(len) as u64;
+            if (&mut r).take(len).read_to_end(&mut header)? as u64 != len {
+                return Err(AvdError::MalformedArchive)
+            }
+        }
+    }
+    Format::parse_header(&header)
+}
+
+#[cfg(feature = "compression")]
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// How many records get encoded at a time when streaming an archive out.
+const CHUNK_BLOCKS: usize = 1024;
+
+/// Just enough of `std::io` to write archives into a `Vec` without std.
+#[cfg(not(feature = "std"))]
+mod io {
+    /// Never actually made, since writing to a `Vec` can't fail.
+    #[derive(Debug)]
+    pub struct Error;
+    pub type Result<T> = core::result::Result<T, Error>;
+    pub trait Write {
+        fn write(&mut self, buf: &[u8]) -> Result<usize>;
+        fn flush(&mut self) -> Result<()>;
+        fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+            while !buf.is_empty() {
+                let n = self.write(buf)?;
+                buf = &buf[n..]
+            }
+            Ok(())
+        }
+    }
+    impl Write for alloc::vec::Vec<u8> {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+    impl<W: Write + ?Sized> Write for &mut W {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            (**self).write(buf)
+        }
+        fn flush(&mut self) -> Result<()> {
+            (**self).flush()
+        }
+    }
+}
+#[cfg(not(feature = "std"))]
+use io::Write;
+
+/// Turn a drive's blocks into an archive.
+pub(crate) fn encode(format: &Format, blocks: &BTreeMap<u16, [u8; 256]>) -> Vec<u8> {
+    let mut ret = Vec::new();
+    write_archive(format, blocks, &mut ret).expect("writing to a vec can't fail"); // SHOULD NEVER PANIC
+    ret
+}
+
+/// Stream a drive's blocks out as an archive. Only a chunk of records is held in memory at once.
+pub(crate) fn write_archive(format: &Format, blocks: &BTreeMap<u16, [u8; 256]>, mut w: impl Write) -> io::Result<()> {
+    w.write_all(&format.header())?;
+    if format.version() >= ArchiveVersion::V2 {
+        return write_chunks(format, blocks, w)
+    }
+    let mut body = CrcWriter {
+        inner: &mut w, crc: crc32fast::Hasher::new()
+    };
+    #[cfg(feature = "compression")]
+    if format.compressed {
+        let mut enc = zstd::Encoder::new(&mut body, COMPRESSION_LEVEL)?;
+        write_records(format, blocks, &mut enc)?;
+        enc.finish()?;
+    }
+    if format.is_plain() {
+        write_records(format, blocks, &mut body)?;
+    }
+    let crc = body.crc.finalize();
+    if format.has_checksum() {
+        w.write_all(&crc.to_be_bytes())?;
+    }
+    w.flush()
+}
+
+fn write_chunks(format: &Format, blocks: &BTreeMap<u16, [u8; 256]>, mut w: impl Write) -> io::Result<()> {
+    if !format.meta.is_empty() {
+        write_chunk(&mut w, TAG_META, &format.meta.encode())?;
+    }
+    if !format.bad.is_empty() {
+        let payload: Vec<u8> = format.bad.iter().flat_map(|idx| idx.to_be_bytes()).collect();
+        write_chunk(&mut w, TAG_BAD, &payload)?;
+    }
+    let flags = format.flags() & BLOCK_FLAGS;
+    #[cfg(feature = "compression")]
+    if format.compressed {
+        // the length has to come first, so the compressed data gets built up in memory
+        let mut payload = vec![flags];
+        let mut enc = zstd::Encoder::new(&mut payload, COMPRESSION_LEVEL)?;
+        write_records(format, blocks, &mut enc)?;
+        enc.finish()?;
+        write_chunk(&mut w, TAG_BLKS, &payload)?;
+    }
+    if !format.compressed {
+        let len = 1 + blocks.len() * format.record_len();
+        w.write_all(&TAG_BLKS)?;
+        w.write_all(&(len as u32).to_be_bytes())?;
+        let mut body = CrcWriter {
+            inner: &mut w, crc: crc32fast::Hasher::new()
+        };
+        body.crc.update(&TAG_BLKS);
+        body.write_all(&[flags])?;
+        write_records(format, blocks, &mut body)?;
+        let crc = body.crc.finalize();
+        w.write_all(&crc.to_be_bytes())?;
+    }
+    for (name, snap) in &format.snapshots {
+        write_chunk(&mut w, TAG_SNAP, &encode_snapshot(name, snap, blocks))?;
+    }
+    write_chunk(&mut w, TAG_DONE, &[])?;
+    w.flush()
+}
+
+fn write_chunk(mut w: impl Write, tag: [u8; 4], payload: &[u8]) -> io::Result<()> {
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(&tag);
+    crc.update(payload);
+    w.write_all(&tag)?;
+    w.write_all(&(payload.len() as u32).to_be_bytes())?;
+    w.write_all(payload)?;
+    w.write_all(&crc.finalize().to_be_bytes())
+}
+
+/// Encode a named snapshot as the changes from `base`.
+fn encode_snapshot(name: &str, snap: &FrozenAvd, base: &BTreeMap<u16, [u8; 256]>) -> Vec<u8> {
+    let mut ret = Vec::new();
+    ret.extend((name.len() as u16).to_be_bytes());
+    ret.extend(name.as_bytes());
+    let removed: Vec<u16> = base.keys().copied().filter(|idx| !snap.is_block_used(*idx)).collect();
+    ret.extend((removed.len() as u32).to_be_bytes());
+    ret.extend(removed.iter().flat_map(|idx| idx.to_be_bytes()));
+    for (idx, data) in snap.blocks() {
+        if base.get(&idx) != Some(data) {
+            ret.extend(idx.to_be_bytes());
+            ret.extend(data)
+        }
+    }
+    ret
+}
+
+/// Rebuild the named snapshots from their chunks, once the drive's own blocks are known.
+fn decode_snapshots<'a>(format: &mut Format, records: &[Record], chunks: impl IntoIterator<Item = &'a [u8]>) -> Result<()> {
+    let mut base: Option<BTreeMap<u16, [u8; 256]>> = None;
+    for payload in chunks {
+        let base = base.get_or_insert_with(|| records.iter().copied().collect());
+        let name_len = u16::from_be_bytes(payload.get(..2).ok_or(AvdError::MalformedArchive)?.try_into().unwrap()) as usize; // SHOULD NEVER PANIC
+        let name = payload.get(2..2 + name_len).ok_or(AvdError::MalformedArchive)?;
+        let name = String::from_utf8(name.to_vec()).map_err(|_| AvdError::MalformedArchive)?;
+        let rest = &payload[2 + name_len..];
+        let count = u32::from_be_bytes(rest.get(..4).ok_or(AvdError::MalformedArchive)?.try_into().unwrap()) as usize; // SHOULD NEVER PANIC
+        let end = count.checked_mul(2).and_then(|n| n.checked_add(4)).ok_or(AvdError::MalformedArchive)?;
+        let removed = rest.get(4..end).ok_or(AvdError::MalformedArchive)?;
+        let changed = &rest[end..];
+        if !changed.len().is_multiple_of(258) {
+            return Err(AvdError::MalformedArchive)
+        }
+        let mut blocks = base.clone();
+        for idx in removed.chunks_exact(2) {
+            blocks.remove(&u16::from_be_bytes([idx[0], idx[1]]));
+        }
+        for rec in changed.chunks_exact(258) {
+            blocks.insert(u16::from_be_bytes([rec[0], rec[1]]), rec[2..].try_into().unwrap()); // SHOULD NEVER PANIC
+        }
+        format.snapshots.insert(name, Arc::new(FrozenAvd::from_map(&blocks)));
+    }
+    Ok(())
+}
+
+fn write_records(format: &Format, blocks: &BTreeMap<u16, [u8; 256]>, mut w: impl Write) -> io::Result<()> {
+    let len = format.record_len();
+    let mut buf = vec![0; len * CHUNK_BLOCKS];
+    let blocks: Vec<_> = blocks.iter().collect();
+    for chunk in blocks.chunks(CHUNK_BLOCKS) {
+        let buf = &mut buf[..len * chunk.len()];
+        let write = |(rec, (idx, data)): (&mut [u8], &(&u16, &[u8; 256]))| format.write_record(rec, **idx, data);
+        #[cfg(feature = "rayon")]
+        buf.par_chunks_mut(len).zip(chunk).for_each(write);
+        #[cfg(not(feature = "rayon"))]
+        buf.chunks_mut(len).zip(chunk).for_each(write);
+        w.write_all(buf)?;
+    }
+    Ok(())
+}
+
+/// Passes writes through, keeping a CRC32 of everything written.
+struct CrcWriter<W> {
+    inner: W,
+    crc: crc32fast::Hasher,
+}
+impl<W: Write> Write for CrcWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.crc.update(&buf[..n]);
+        Ok(n)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Parse an archive into its format and its block records, in the order they appear.
+pub(crate) fn decode(archive: &[u8]) -> Result<(Format, Vec<Record>)> {
+    let format = Format::parse_header(archive)?;
+    if format.version() >= ArchiveVersion::V2 {
+        return decode_chunks(format, &archive[4..])
+    }
+    let mut data_seg = &archive[format.header_len()..];
+    if format.has_checksum() {
+        let split = data_seg.len().checked_sub(4).ok_or(AvdError::MalformedArchive)?;
+        let (data, trailer) = data_seg.split_at(split);
+        if crc32fast::hash(data).to_be_bytes() != trailer {
+            return Err(AvdError::BadArchiveChecksum)
+        }
+        data_seg = data;
+    }
+    let records = decode_records(&format, data_seg)?;
+    Ok((format, records))
+}
+
+fn decode_chunks(mut format: Format, mut rest: &[u8]) -> Result<(Format, Vec<Record>)> {
+    let mut records = None;
+    let mut snaps = Vec::new();
+    loop {
+        let head = rest.get(..8).ok_or(AvdError::MalformedArchive)?;
+        let tag: [u8; 4] = head[..4].try_into().unwrap(); // SHOULD NEVER PANIC
+        let len = u32::from_be_bytes(head[4..].try_into().unwrap()) as usize; // SHOULD NEVER PANIC
+        // the length comes straight from the file, so it could overflow on 32-bit targets
+        let end = 12usize.checked_add(len).ok_or(AvdError::MalformedArchive)?;
+        let payload = rest.get(8..end - 4).ok_or(AvdError::MalformedArchive)?;
+        let stored = rest.get(end - 4..end).ok_or(AvdError::MalformedArchive)?;
+        let mut crc = crc32fast::Hasher::new();
+        crc.update(&tag);
+        crc.update(payload);
+        if crc.finalize().to_be_bytes() != stored {
+            return Err(AvdError::BadArchiveChecksum)
+        }
+        rest = &rest[end..];
+        match tag {
+            TAG_BLKS if records.is_none() => {
+                let (flags, data_seg) = payload.split_first().ok_or(AvdError::MalformedArchive)?;
+                format.set_block_flags(*flags)?;
+                records = Some(decode_records(&format, data_seg)?)
+            }
+            TAG_BLKS => return Err(AvdError::MalformedArchive),
+            TAG_META => format.meta = Metadata::decode(payload)?,
+            TAG_BAD => format.bad = decode_bad(payload)?,
+            TAG_SNAP => snaps.push(payload),
+            TAG_DONE if payload.is_empty() => break,
+            TAG_DONE => return Err(AvdError::MalformedArchive),
+            t if t[0].is_ascii_lowercase() => {}
+            t => return Err(AvdError::UnknownChunk(t))
+        }
+    }
+    if !rest.is_empty() {
+        return Err(AvdError::MalformedArchive)
+    }
+    let records = records.ok_or(AvdError::MalformedArchive)?;
+    decode_snapshots(&mut format, &records, snaps)?;
+    Ok((format, records))
+}
+
+fn decode_bad(payload: &[u8]) -> Result<BTreeSet<u16>> {
+    if !payload.len().is_multiple_of(2) {
+        return Err(AvdError::MalformedArchive)
+    }
+    Ok(payload.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect())
+}
+
+/// Parse the block records out of a data segment, decompressing it first if need be.
+fn decode_records(format: &Format, data_seg: &[u8]) -> Result<Vec<Record>> {
+    let data_seg: Cow<[u8]> = if format.compressed {
+        #[cfg(feature = "compression")] {
+            Cow::Owned(zstd::decode_all(data_seg).map_err(|_| AvdError::MalformedArchive)?)
+        }
+        #[cfg(not(feature = "compression"))]
+        return Err(AvdError::Unsupported("compressed archives need the `compression` feature"))
+    }
+    else {
+        Cow::Borrowed(data_seg)
+    };
+    let len = format.record_len();
+    if !data_seg.len().is_multiple_of(len) {
+        return Err(AvdError::MalformedArchive)
+    }
+    let read = |rec: &[u8]| format.read_record(rec);
+    #[cfg(feature = "rayon")]
+    let records = data_seg.par_chunks_exact(len).map(read).collect::<Result<_>>()?;
+    #[cfg(not(feature = "rayon"))]
+    let records = data_seg.chunks_exact(len).map(read).collect::<Result<_>>()?;
+
+    Ok(records)
+}
+
+/// Stream an archive in, parsing records as they arrive rather than reading the whole thing first.
+#[cfg(feature = "std")]
+pub(crate) fn read_archive(mut r: impl Read) -> Result<(Format, Vec<Record>)> {
+    let format = read_header(&mut r)?;
+    if format.version() >= ArchiveVersion::V2 {
+        return read_chunks(format, r)
+    }
+    let mut body = TrailerReader {
+        inner: r, held: Vec::new(), hold: format.trailer_len(), crc: crc32fast::Hasher::new(), eof: false
+    };
+    let records = read_data_seg(&format, &mut body)?;
+    if body.held.len() != body.hold {
+        return Err(AvdError::MalformedArchive)
+    }
+    if format.has_checksum() && body.crc.finalize().to_be_bytes() != body.held[..] {
+        return Err(AvdError::BadArchiveChecksum)
+    }
+    Ok((format, records))
+}
+
+#[cfg(feature = "std")]
+fn read_chunks(mut format: Format, mut r: impl Read) -> Result<(Format, Vec<Record>)> {
+    let mut records = None;
+    let mut snaps = Vec::new();
+    loop {
+        let mut head = [0; 8];
+        if read_full(&mut r, &mut head)? < 8 {
+            return Err(AvdError::MalformedArchive)
+        }
+        let tag: [u8; 4] = head[..4].try_into().unwrap(); // SHOULD NEVER PANIC
+        let len = u32::from_be_bytes(head[4..].try_into().unwrap()); // SHOULD NEVER PANIC
+        let mut body = CrcReader {
+            inner: (&mut r).take(len as u64), crc: crc32fast::Hasher::new()
+        };
+        body.crc.update(&tag);
+        match tag {
+            TAG_BLKS if records.is_none() => {
+                let mut flags = [0];
+                if read_full(&mut body, &mut flags)? < 1 {
+                    return Err(AvdError::MalformedArchive)
+                }
+                format.set_block_flags(flags[0])?;
+                records = Some(read_data_seg(&format, &mut body)?)
+            }
+            TAG_META => {
+                let mut meta = Vec::new();
+                body.read_to_end(&mut meta)?;
+                format.meta = Metadata::decode(&meta)?
+            }
+            TAG_BAD => {
+                let mut bad = Vec::new();
+                body.read_to_end(&mut bad)?;
+                format.bad = decode_bad(&bad)?
+            }
+            TAG_SNAP => {
+                let mut snap = Vec::new();
+                body.read_to_end(&mut snap)?;
+                snaps.push(snap)
+            }
+            TAG_DONE => {}
+            t if t[0].is_ascii_lowercase() => {
+                io::copy(&mut body, &mut io::sink())?;
+            }
+            TAG_BLKS => return Err(AvdError::MalformedArchive),
+            t => return Err(AvdError::UnknownChunk(t))
+        }
+        if body.inner.limit() != 0 {
+            return Err(AvdError::MalformedArchive)
+        }
+        let crc = body.crc.finalize();
+        let mut stored = [0; 4];
+        if read_full(&mut r, &mut stored)? < 4 {
+            return Err(AvdError::MalformedArchive)
+        }
+        if crc.to_be_bytes() != stored {
+            return Err(AvdError::BadArchiveChecksum)
+        }
+        if tag == TAG_DONE {
+            break
+        }
+    }
+    if read_full(&mut r, &mut [0])? != 0 {
+        return Err(AvdError::MalformedArchive)
+    }
+    let records = records.ok_or(AvdError::MalformedArchive)?;
+    decode_snapshots(&mut format, &records, snaps.iter().map(Vec::as_slice))?;
+    Ok((format, records))
+}
+
+/// Read the block records from the rest of a stream, decompressing them if need be.
+#[cfg(feature = "std")]
+fn read_data_seg(format: &Format, r: impl Read) -> Result<Vec<Record>> {
+    if format.compressed {
+        #[cfg(feature = "compression")] {
+            let mut r = r;
+            let mut dec = zstd::Decoder::new(&mut r)?;
+            let records = read_records(format, &mut dec)?;
+            drop(dec);
+            io::copy(&mut r, &mut io::sink())?; // anything after the compressed data still counts for the checksum
+            Ok(records)
+        }
+        #[cfg(not(feature = "compression"))]
+        Err(AvdError::Unsupported("compressed archives need the `compression` feature"))
+    }
+    else {
+        read_records(format, r)
+    }
+}
+
+#[cfg(feature = "std")]
+fn read_records(format: &Format, mut r: impl Read) -> Result<Vec<Record>> {
+    let mut ret = Vec::new();
+    let mut rec = vec![0; format.record_len()];
+    loop {
+        match read_full(&mut r, &mut rec)? {
+            0 => break,
+            n if n < rec.len() => return Err(AvdError::MalformedArchive),
+            _ => ret.push(format.read_record(&rec)?)
+        }
+    }
+    Ok(ret)
+}
+
+/// Fill as much of `buf` as possible, stopping early only at the end of the stream.
+#[cfg(feature = "std")]
+pub(crate) fn read_full(mut r: impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match r.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(v) => n += v,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e)
+        }
+    }
+    Ok(n)
+}
+
+/// Passes reads through, keeping a CRC32 of everything read.
+#[cfg(feature = "std")]
+struct CrcReader<R> {
+    inner: R,
+    crc: crc32fast::Hasher,
+}
+#[cfg(feature = "std")]
+impl<R: Read> Read for CrcReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(out)?;
+        self.crc.update(&out[..n]);
+        Ok(n)
+    }
+}
+
+/// Passes reads through, but always keeps the last `hold` bytes of the stream back, so a trailer can be picked off the end. Keeps a CRC32 of everything it passes on.
+#[cfg(feature = "std")]
+struct TrailerReader<R> {
+    inner: R,
+    held: Vec<u8>,
+    hold: usize,
+    crc: crc32fast::Hasher,
+    eof: bool,
+}
+#[cfg(feature = "std")]
+impl<R: Read> Read for TrailerReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.held.len() <= self.hold && !self.eof {
+            let mut tmp = [0; 4096];
+            match self.inner.read(&mut tmp)? {
+                0 => self.eof = true,
+                n => self.held.extend(&tmp[..n])
+            }
+        }
+        let n = self.held.len().saturating_sub(self.hold).min(out.len());
+        out[..n].copy_from_slice(&self.held[..n]);
+        self.crc.update(&out[..n]);
+        self.held.drain(..n);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn versions() {
+        let mut blocks = BTreeMap::new();
+        blocks.insert(7, [7; 256]);
+        for version in [ArchiveVersion::V0, ArchiveVersion::V1] {
+            let format = Format {
+                version, ..Format::new()
+            };
+            let a = encode(&format, &blocks);
+            assert_eq!(a[3], version.to_u8());
+            assert_eq!(a.len(), format.header_len() + 258 + 4 * format.has_checksum() as usize);
+            assert_eq!(decode(&a).unwrap(), (format, vec![(7, [7; 256])]));
+        }
+        assert!(matches!(decode(b"AVD"), Err(AvdError::MalformedArchive)));
+        assert!(matches!(decode(b"AVD\x01\x80"), Err(AvdError::UnknownFlags(0x80))));
+        assert!(matches!(decode(b"AVD\x01"), Err(AvdError::MalformedArchive)));
+        assert!(matches!(decode(b"AVD\x09"), Err(AvdError::UnsupportedVersion(9))));
+        assert!(matches!(decode(b"AVC\x00"), Err(AvdError::BadHeader(0x41, 0x56, 0x43, 0))));
+    }
+    #[test]
+    fn block_crc() {
+        let mut blocks = BTreeMap::new();
+        blocks.insert(7, [7; 256]);
+        let format = Format {
+            block_crc: true, ..Format::new()
+        };
+        assert_eq!(format.version(), ArchiveVersion::V1);
+        let mut a = encode(&Format {
+            checksum: false, ..format.clone()
+        }, &blocks);
+        assert_eq!(a.len(), 5 + 262);
+        assert_eq!(decode(&a).unwrap().1, [(7, [7; 256])]);
+        a[100] ^= 1;
+        assert!(matches!(decode(&a), Err(AvdError::BadBlockChecksum(7))));
+    }
+    #[test]
+    fn checksum() {
+        let mut blocks = BTreeMap::new();
+        blocks.insert(7, [7; 256]);
+        let format = Format {
+            version: ArchiveVersion::V1, ..Format::new()
+        };
+        let mut a = encode(&format, &blocks);
+        assert_eq!(a.len(), 5 + 258 + 4);
+        assert_eq!(decode(&a).unwrap().1, [(7, [7; 256])]);
+        a[100] ^= 1;
+        assert!(matches!(decode(&a), Err(AvdError::BadArchiveChecksum)));
+        assert!(matches!(decode(&a[..a.len() - 258]), Err(AvdError::BadArchiveChecksum)));
+        assert!(matches!(decode(b"AVD\x01\x02"), Err(AvdError::MalformedArchive)));
+        assert_eq!(encode(&Format::new(), &blocks).len(), 4 + 258); // no trailer in v0
+    }
+    #[cfg(feature = "std")]
+    #[test]
+    fn streaming_read() {
+        let blocks: BTreeMap<_, _> = (0..100).map(|i| (i * 7, [i as u8; 256])).collect();
+        for format in [Format::new(), Format {
+            version: ArchiveVersion::V1, block_crc: true, ..Format::new()
+        }] {
+            let a = encode(&format, &blocks);
+            // one byte at a time, to make sure the trailer handling copes
+            let (f, records) = read_archive(io::BufReader::with_capacity(1, &a[..])).unwrap();
+            assert_eq!((f, records), decode(&a).unwrap());
+            assert!(matches!(read_archive(&a[..a.len() - 1]), Err(AvdError::MalformedArchive | AvdError::BadArchiveChecksum)));
+        }
+        assert!(matches!(read_archive(&b"AV"[..]), Err(AvdError::MalformedArchive)));
+    }
+    #[test]
+    fn chunks() {
+        let blocks: BTreeMap<_, _> = (0..10).map(|i| (i * 3, [i as u8; 256])).collect();
+        let plain = Format {
+            version: ArchiveVersion::V2, ..Format::new()
+        };
+        let mut with_meta = Format {
+            block_crc: true, ..plain.clone()
+        };
+        with_meta.meta.label = "chunky".into();
+        for format in [plain.clone(), with_meta] {
+            let a = encode(&format, &blocks);
+            assert_eq!(a[..4], *b"AVD\x02");
+            assert_eq!(decode(&a).unwrap(), (format, blocks.clone().into_iter().collect()));
+            #[cfg(feature = "std")]
+            assert_eq!(read_archive(io::BufReader::with_capacity(1, &a[..])).unwrap(), decode(&a).unwrap());
+        }
+        let a = encode(&plain, &blocks);
+        assert_eq!(a.len(), 4 + 12 + 1 + 10 * 258 + 12);
+
+        // an unknown optional chunk gets skipped, an unknown required one doesn't
+        let mut extra = Vec::new();
+        write_chunk(&mut extra, *b"zzzz", b"from the future").unwrap();
+        let with_extra = [&a[..4], &extra, &a[4..]].concat();
+        assert_eq!(decode(&with_extra).unwrap().1, decode(&a).unwrap().1);
+        #[cfg(feature = "std")]
+        assert_eq!(read_archive(&with_extra[..]).unwrap().1, decode(&a).unwrap().1);
+        let mut required = a[..4].to_vec();
+        write_chunk(&mut required, *b"Zzzz", b"").unwrap();
+        required.extend(&a[4..]);
+        assert!(matches!(decode(&required), Err(AvdError::UnknownChunk(t)) if t == *b"Zzzz"));
+        #[cfg(feature = "std")]
+        assert!(matches!(read_archive(&required[..]), Err(AvdError::UnknownChunk(_))));
+
+        let mut bad = a.clone();
+        bad[100] ^= 1;
+        assert!(matches!(decode(&bad), Err(AvdError::BadArchiveChecksum)));
+        #[cfg(feature = "std")]
+        assert!(matches!(read_archive(&bad[..]), Err(AvdError::BadArchiveChecksum)));
+        let cut = &a[..a.len() - 12]; // no DONE chunk
+        assert!(matches!(decode(cut), Err(AvdError::MalformedArchive)));
+        assert!(matches!(decode(b"AVD\x02BLKS\xff\xff\xff\xff"), Err(AvdError::MalformedArchive)));
+        #[cfg(feature = "std")]
+        assert!(matches!(read_archive(cut), Err(AvdError::MalformedArchive)));
+    }
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compression() {
+        let blocks: BTreeMap<_, _> = (0..1000).map(|i| (i, [i as u8; 256])).collect();
+        let format = Format {
+            compressed: true, ..Format::new()
+        };
+        let a = encode(&format, &blocks);
+        assert!(a.len() < 1000 * 258 / 10);
+        let (f, records) = decode(&a).unwrap();
+        assert!(f.compressed && f.has_checksum());
+        assert_eq!(records.into_iter().collect::<BTreeMap<_, _>>(), blocks);
+        assert_eq!(read_archive(&a[..]).unwrap(), decode(&a).unwrap());
+        let v2 = Format {
+            version: ArchiveVersion::V2, ..format
+        };
+        let a = encode(&v2, &blocks);
+        assert!(a.len() < 1000 * 258 / 10);
+        assert_eq!(read_archive(&a[..]).unwrap(), decode(&a).unwrap());
+        assert!(decode(&a).unwrap().0.compressed);
+    }
+}
@@ -0,0 +1,133 @@
+//! Archives wrapped in base64, for pasting into places that only take text.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::fs::read_to_string;
+#[cfg(feature = "std")]
+use std::io::Write;
+#[cfg(feature = "std")]
+use std::path::Path;
+use crate::{Avd, AvdError, Result};
+
+const BEGIN: &str = "-----BEGIN AVD ARCHIVE-----";
+const END: &str = "-----END AVD ARCHIVE-----";
+const LINE_LEN: usize = 64;
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn bad(msg: &str) -> AvdError {
+    AvdError::BadText("armored archive", msg.into())
+}
+
+fn encode_base64(data: &[u8]) -> String {
+    let mut ret = String::new();
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = u32::from_be_bytes([0, b[0], b[1], b[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                ret.push(ALPHABET[(n >> (18 - i * 6)) as usize & 63] as char)
+            }
+            else {
+                ret.push('=')
+            }
+        }
+    }
+    ret
+}
+
+fn decode_base64(text: &str) -> Result<Vec<u8>> {
+    let digits: Vec<u8> = text.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if !digits.len().is_multiple_of(4) {
+        return Err(bad("base64 isn't a multiple of 4 characters long"))
+    }
+    let mut ret = Vec::new();
+    for (i, quad) in digits.chunks(4).enumerate() {
+        let pad = quad.iter().rev().take_while(|b| **b == b'=').count();
+        if pad > 2 || (pad > 0 && i != digits.len() / 4 - 1) {
+            return Err(bad("misplaced padding"))
+        }
+        let mut n = 0;
+        for b in &quad[..4 - pad] {
+            let v = ALPHABET.iter().position(|a| a == b).ok_or_else(|| bad("not base64"))?;
+            n = n << 6 | v as u32
+        }
+        n <<= 6 * pad;
+        ret.extend(&n.to_be_bytes()[1..4 - pad])
+    }
+    Ok(ret)
+}
+
+/// Pull the archive out from between the `BEGIN` and `END` lines.
+fn unarmor(text: &str) -> Result<Vec<u8>> {
+    let start = text.find(BEGIN).ok_or_else(|| bad("no BEGIN line"))? + BEGIN.len();
+    let len = text[start..].find(END).ok_or_else(|| bad("no END line"))?;
+    decode_base64(&text[start..start + len])
+}
+
+impl Avd {
+    /// Get the AVD's archive wrapped in base64, between `BEGIN` and `END` lines, ready to paste somewhere.
+    pub fn to_armored(&self) -> String {
+        let b64 = encode_base64(&self.to_bytes());
+        let mut ret = String::from(BEGIN);
+        ret.push('\n');
+        for line in b64.as_bytes().chunks(LINE_LEN) {
+            ret.push_str(core::str::from_utf8(line).unwrap()); // SHOULD NEVER PANIC
+            ret.push('\n')
+        }
+        ret.push_str(END);
+        ret.push('\n');
+        ret
+    }
+    /// Load a new AVD from an armored archive. Anything before the `BEGIN` line or after the `END` line is ignored, so it's fine to pass a whole bug report.
+    pub fn from_armored(text: &str) -> Result<Avd> {
+        Avd::from_bytes(&unarmor(text)?)
+    }
+    /// Save the AVD to a file as an armored archive.
+    #[cfg(feature = "std")]
+    pub fn save_armored(&self, path: impl AsRef<Path>) -> Result<()> {
+        let len = crate::atomic::write_atomic(path.as_ref(), self.backups, |w| Ok(w.write_all(self.to_armored().as_bytes())?))?;
+        self.metrics.flushed(len);
+        Ok(())
+    }
+    /// Load an armored archive from a file. Like `load`, this overwrites the entire drive.
+    #[cfg(feature = "std")]
+    pub fn load_armored(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let text = read_to_string(path)?;
+        self.load_from(&unarmor(&text)?[..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn base64() {
+        for (plain, b64) in [(&b""[..], ""), (b"f", "Zg=="), (b"fo", "Zm8="), (b"foo", "Zm9v"), (b"foobar", "Zm9vYmFy")] {
+            assert_eq!(encode_base64(plain), b64);
+            assert_eq!(decode_base64(b64).unwrap(), plain);
+        }
+        assert!(decode_base64("Zg=a").is_err());
+        assert!(decode_base64("Zg").is_err());
+    }
+    #[test]
+    fn armored() {
+        let mut drive = Avd::new();
+        drive.set_block(4, &[4; 256]).unwrap();
+        let text = drive.to_armored();
+        assert!(text.starts_with("-----BEGIN AVD ARCHIVE-----\nQVZE"));
+        assert!(text.lines().all(|l| l.len() <= 64 || l.starts_with("-----")));
+        let report = format!("steps to reproduce:\n\n{}\nthanks!", text);
+        assert_eq!(Avd::from_armored(&report).unwrap(), drive);
+
+        #[cfg(feature = "std")]
+        {
+            let path = std::env::temp_dir().join(format!("avd-armor-{}.txt", std::process::id()));
+            drive.save_armored(&path).unwrap();
+            let mut loaded = Avd::new();
+            loaded.load_armored(&path).unwrap();
+            assert_eq!(loaded, drive);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! Saving and loading without blocking an async runtime.
+
+use std::path::Path;
+use tokio::io::AsyncWriteExt;
+use crate::{Avd, Result, atomic::{rotate_backups, sync_dir, temp_path}};
+
+impl Avd {
+    /// Save the AVD to a file like `save`, but write it with `tokio::fs` so the runtime isn't held up by the disk.
+    /// 
+    /// The archive is still encoded on the calling task, which is quick next to the write itself. Like `save`, it goes to a temporary file first, which is then renamed over `path`.
+    pub async fn save_async(&self, path: impl AsRef<Path>) -> Result<()> {
+        let archive = self.to_bytes();
+        let tmp = temp_path(path.as_ref());
+        if let Err(e) = write_temp(path.as_ref(), &tmp, self.backups, &archive).await {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            return Err(e)
+        }
+        self.metrics.flushed(archive.len() as u64);
+        self.tracking_ref().reset(path.as_ref(), &self.format, self.blocks.keys().copied().collect());
+        Ok(())
+    }
+    /// Load a file into the AVD like `load`, reading it with `tokio::fs`. This overwrites the entire drive!
+    pub async fn load_async(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let archive = tokio::fs::read(&path).await?;
+        self.load_archive(path.as_ref(), &archive)
+    }
+}
+
+async fn write_temp(path: &Path, tmp: &Path, backups: u32, data: &[u8]) -> Result<()> {
+    let mut f = tokio::fs::File::create(tmp).await?;
+    f.write_all(data).await?;
+    if let Ok(m) = tokio::fs::metadata(path).await {
+        f.set_permissions(m.permissions()).await?;
+    }
+    f.sync_all().await?;
+    drop(f);
+    rotate_backups(path, backups)?; // only a few renames and a link, not worth moving to another thread
+    tokio::fs::rename(tmp, path).await?;
+    sync_dir(path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn save_load_async() {
+        let path = std::env::temp_dir().join(format!("avd-async-{}.avd", std::process::id()));
+        let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let mut drive = Avd::new();
+        drive.set_block(4, &[4; 256]).unwrap();
+        drive.set_block(9000, &[9; 256]).unwrap();
+        rt.block_on(drive.save_async(&path)).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), drive.to_bytes());
+        let mut d2 = Avd::new();
+        d2.set_block(1, &[1; 256]).unwrap();
+        rt.block_on(d2.load_async(&path)).unwrap();
+        assert_eq!(d2, drive);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,129 @@
+//! Replacing files without ever leaving a half-written one behind.
+
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use crate::Result;
+
+/// Where the new contents of `path` are written before being moved over it. It has to be in the same directory, or the rename might not be atomic.
+pub(crate) fn temp_path(path: &Path) -> PathBuf {
+    let mut tmp = OsString::from(path);
+    tmp.push(format!(".{}.tmp", std::process::id()));
+    tmp.into()
+}
+
+/// Write a file by having `f` write a temporary file next to it, syncing that to disk, then renaming it over `path`. Either the old file or the whole new one is there afterwards, whenever the program dies. The new file gets the old one's permissions.
+/// 
+/// If `backups` isn't 0, the old file is kept as a backup (see `rotate_backups`). Returns the length of the new file.
+pub(crate) fn write_atomic(path: &Path, backups: u32, f: impl FnOnce(&mut BufWriter<File>) -> Result<()>) -> Result<u64> {
+    let tmp = temp_path(path);
+    let ret = write_temp(path, &tmp, backups, f);
+    if ret.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    ret
+}
+
+fn write_temp(path: &Path, tmp: &Path, backups: u32, f: impl FnOnce(&mut BufWriter<File>) -> Result<()>) -> Result<u64> {
+    let mut w = BufWriter::new(File::create(tmp)?);
+    f(&mut w)?;
+    w.flush()?;
+    let file = w.into_inner().map_err(|e| e.into_error())?;
+    if let Ok(m) = fs::metadata(path) {
+        file.set_permissions(m.permissions())?;
+    }
+    file.sync_all()?;
+    let len = file.metadata()?.len();
+    drop(file);
+    rotate_backups(path, backups)?;
+    fs::rename(tmp, path)?;
+    sync_dir(path);
+    Ok(len)
+}
+
+/// The path of the `n`th backup of `path`.
+pub(crate) fn backup_path(path: &Path, n: u32) -> PathBuf {
+    let mut p = OsString::from(path);
+    p.push(format!(".{n}"));
+    p.into()
+}
+
+/// Shift `path.1` to `path.2` and so on, dropping anything past `path.<keep>`, then make `path.1` another link to `path` (or a copy, where links aren't supported). Does nothing if `keep` is 0 or there's no file yet.
+/// 
+/// `path` itself is left alone, so there's always a file there until the new one is renamed over it.
+pub(crate) fn rotate_backups(path: &Path, keep: u32) -> Result<()> {
+    if keep == 0 || !path.exists() {
+        return Ok(())
+    }
+    match fs::remove_file(backup_path(path, keep)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    for n in (1..keep).rev() {
+        let from = backup_path(path, n);
+        if from.exists() {
+            fs::rename(from, backup_path(path, n + 1))?
+        }
+    }
+    let first = backup_path(path, 1);
+    if fs::hard_link(path, &first).is_err() {
+        fs::copy(path, &first)?;
+    }
+    Ok(())
+}
+
+/// Make sure the rename itself has hit the disk. This is only possible (and only needed) on unix, and failing doesn't lose anything, so errors are ignored.
+pub(crate) fn sync_dir(path: &Path) {
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        let _ = File::open(dir).and_then(|d| d.sync_all());
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Avd, AvdError};
+    #[test]
+    fn atomic() {
+        let path = std::env::temp_dir().join(format!("avd-atomic-{}.avd", std::process::id()));
+        let mut drive = Avd::new();
+        drive.set_block(1, &[1; 256]).unwrap();
+        drive.save(&path).unwrap();
+        let ret = write_atomic(&path, 0, |w| {
+            w.write_all(b"AVD\0half a block")?;
+            Err(AvdError::MalformedArchive)
+        });
+        assert!(ret.is_err());
+        assert!(!temp_path(&path).exists());
+        assert_eq!(Avd::from_host_drive(&path).unwrap(), drive);
+        std::fs::remove_file(&path).unwrap();
+    }
+    #[test]
+    fn backups() {
+        let path = std::env::temp_dir().join(format!("avd-backups-{}.avd", std::process::id()));
+        let mut drive = Avd::builder().backups(2).build().unwrap();
+        for i in 1..=4 {
+            drive.set_block(0, &[i; 256]).unwrap();
+            drive.save(&path).unwrap();
+            assert!(path.exists());
+        }
+        let version = |p: &Path| Avd::from_host_drive(p).unwrap().get_block(0).unwrap()[0];
+        assert_eq!(version(&path), 4);
+        assert_eq!(version(&backup_path(&path, 1)), 3);
+        assert_eq!(version(&backup_path(&path, 2)), 2);
+        assert!(!backup_path(&path, 3).exists());
+        // dying between rotating and renaming still leaves the file in place
+        rotate_backups(&path, 2).unwrap();
+        assert_eq!(version(&path), 4);
+        assert_eq!(version(&backup_path(&path, 1)), 4);
+        assert_eq!(version(&backup_path(&path, 2)), 3);
+        for p in [path.clone(), backup_path(&path, 1), backup_path(&path, 2)] {
+            std::fs::remove_file(p).unwrap()
+        }
+    }
+}
@@ -0,0 +1,221 @@
+//! Saving drives automatically, in the background or when they're dropped.
+
+use alloc::boxed::Box;
+use core::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use crate::{Avd, AvdError, Result, SharedAvd};
+
+enum Command {
+    Flush(Sender<Result<()>>),
+    Stop,
+}
+
+/// A background thread that saves a [`SharedAvd`] every so often. Made with `SharedAvd::enable_autosave`.
+/// 
+/// Each save goes through `Avd::save`, so a crash partway through leaves the last good file (and any backups) alone. It holds the drive's read lock while it writes, so the guest waits to write but not to read. Nothing is written if nothing has changed. If a save fails, it's tried again next time round; `flush_now` and `shutdown` report errors directly.
+/// 
+/// Dropping this stops the thread after one last save, but any error from that save is lost. Call `shutdown` to see it.
+#[derive(Debug)]
+pub struct Autosave {
+    commands: Sender<Command>,
+    thread: Option<JoinHandle<Result<()>>>,
+}
+impl Autosave {
+    fn start(drive: SharedAvd, path: PathBuf, interval: Duration) -> Autosave {
+        let (commands, rx) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("avd-autosave".into())
+            .spawn(move || run(drive, path, interval, rx))
+            .expect("failed to spawn autosave thread");
+        Autosave {
+            commands, thread: Some(thread)
+        }
+    }
+    /// Save any changes right away, and wait for the save to finish.
+    pub fn flush_now(&self) -> Result<()> {
+        let (reply, rx) = mpsc::channel();
+        self.commands.send(Command::Flush(reply)).map_err(|_| stopped())?;
+        rx.recv().map_err(|_| stopped())?
+    }
+    /// Stop the thread, saving any changes one last time.
+    pub fn shutdown(mut self) -> Result<()> {
+        self.stop()
+    }
+    fn stop(&mut self) -> Result<()> {
+        match self.thread.take() {
+            Some(t) => {
+                let _ = self.commands.send(Command::Stop);
+                t.join().map_err(|_| stopped())?
+            }
+            None => Ok(())
+        }
+    }
+}
+impl Drop for Autosave {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+fn stopped() -> io::Error {
+    io::Error::other("autosave thread has stopped")
+}
+
+fn run(drive: SharedAvd, path: PathBuf, interval: Duration, rx: Receiver<Command>) -> Result<()> {
+    loop {
+        match rx.recv_timeout(interval) {
+            Err(RecvTimeoutError::Timeout) => {
+                let _ = save(&drive, &path);
+            }
+            Ok(Command::Flush(reply)) => {
+                let _ = reply.send(save(&drive, &path));
+            }
+            Ok(Command::Stop) | Err(RecvTimeoutError::Disconnected) => return save(&drive, &path)
+        }
+    }
+}
+
+fn save(drive: &SharedAvd, path: &Path) -> Result<()> {
+    let d = drive.read();
+    if d.is_dirty() || !path.exists() {
+        d.save(path)?
+    }
+    Ok(())
+}
+
+type ErrorHook = Box<dyn Fn(&Path, AvdError) + Send + Sync>;
+
+/// Where a drive saves itself when it's dropped, and who hears about it going wrong.
+#[derive(Default)]
+pub(crate) struct DropSave {
+    path: Option<PathBuf>,
+    on_error: Option<ErrorHook>,
+}
+impl fmt::Debug for DropSave {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DropSave").field("path", &self.path).field("on_error", &self.on_error.is_some()).finish()
+    }
+}
+
+impl Avd {
+    /// Save the drive to `path` when it's dropped, so quick tools and emulators don't need to remember to. Nothing is written if the drive hasn't changed since it was last saved or loaded and the file is already there.
+    /// 
+    /// The save is a normal `save`, so it's atomic and keeps backups. `Drop` can't return an error, so if it fails, the error goes to the function given to `on_save_error`, or if there isn't one, it's logged with the `log` feature. Without either, errors are lost.
+    /// 
+    /// Turning the drive into an iterator of its blocks (or anything built from one, like a [`ShardedAvd`](crate::ShardedAvd)) cancels this, so the emptied drive doesn't get saved over the file.
+    pub fn save_on_drop(&mut self, path: impl Into<PathBuf>) {
+        self.drop_save.path = Some(path.into())
+    }
+    /// Stop saving when the drive is dropped. Returns the path it would have saved to.
+    pub fn cancel_save_on_drop(&mut self) -> Option<PathBuf> {
+        self.drop_save.path.take()
+    }
+    /// Where the drive saves to when it's dropped, if anywhere.
+    pub fn save_on_drop_path(&self) -> Option<&Path> {
+        self.drop_save.path.as_deref()
+    }
+    /// Call `f` with the path and the error if saving on drop fails, instead of logging it.
+    pub fn on_save_error(&mut self, f: impl Fn(&Path, AvdError) + Send + Sync + 'static) {
+        self.drop_save.on_error = Some(Box::new(f))
+    }
+    pub(crate) fn set_drop_save(&mut self, drop_save: DropSave) {
+        self.drop_save = drop_save
+    }
+}
+impl DropSave {
+    pub fn path(&mut self, path: PathBuf) {
+        self.path = Some(path)
+    }
+    pub fn on_error(&mut self, f: ErrorHook) {
+        self.on_error = Some(f)
+    }
+}
+impl Drop for Avd {
+    fn drop(&mut self) {
+        let Some(path) = self.drop_save.path.take() else {
+            return
+        };
+        if !self.is_dirty() && path.exists() {
+            return
+        }
+        if let Err(e) = self.save(&path) {
+            match &self.drop_save.on_error {
+                Some(f) => f(&path, e),
+                #[cfg(feature = "log")]
+                None => log::error!("failed to save drive to {} on drop: {}", path.display(), e),
+                #[cfg(not(feature = "log"))]
+                None => {} // nowhere to report it
+            }
+        }
+    }
+}
+
+impl SharedAvd {
+    /// Start saving the drive to `path` every `interval` on a background thread. See [`Autosave`].
+    pub fn enable_autosave(&self, path: impl AsRef<Path>, interval: Duration) -> Autosave {
+        Autosave::start(self.clone(), path.as_ref().to_owned(), interval)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Avd;
+    #[test]
+    fn autosave() {
+        let path = std::env::temp_dir().join(format!("avd-autosave-{}.avd", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let drive = Avd::new().into_shared();
+        let saver = drive.enable_autosave(&path, Duration::from_millis(10));
+        drive.set_block(1, &[1; 256]).unwrap();
+        for _ in 0..100 {
+            if !drive.read().is_dirty() {
+                break
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(Avd::from_host_drive(&path).unwrap().get_block(1), Some([1; 256]));
+
+        drive.set_block(2, &[2; 256]).unwrap();
+        saver.flush_now().unwrap();
+        assert_eq!(Avd::from_host_drive(&path).unwrap().used_blocks(), 2);
+        drive.delete_block(1);
+        saver.shutdown().unwrap();
+        assert_eq!(Avd::from_host_drive(&path).unwrap(), *drive.read());
+        std::fs::remove_file(&path).unwrap();
+    }
+    #[test]
+    fn save_on_drop() {
+        let path = std::env::temp_dir().join(format!("avd-dropsave-{}.avd", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut drive = Avd::new();
+        drive.set_block(1, &[1; 256]).unwrap();
+        drive.save_on_drop(&path);
+        drop(drive);
+        let mut drive = Avd::from_host_drive(&path).unwrap();
+        assert_eq!(drive.get_block(1), Some([1; 256]));
+        drive.save_on_drop(&path);
+        drive.set_block(2, &[2; 256]).unwrap();
+        assert_eq!(drive.cancel_save_on_drop().as_deref(), Some(path.as_path()));
+        drop(drive);
+        assert_eq!(Avd::from_host_drive(&path).unwrap().used_blocks(), 1);
+        let mut drive = Avd::from_host_drive(&path).unwrap();
+        drive.set_block(3, &[3; 256]).unwrap();
+        drive.save_on_drop(&path);
+        assert_eq!(drive.into_iter().count(), 2);
+        assert_eq!(Avd::from_host_drive(&path).unwrap().get_block(1), Some([1; 256]));
+
+        let failed = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let f = failed.clone();
+        let mut drive = Avd::new();
+        drive.save_on_drop(path.join("not-a-dir.avd"));
+        drive.on_save_error(move |_, e| f.store(matches!(e, AvdError::FsError(_)), std::sync::atomic::Ordering::Relaxed));
+        drop(drive);
+        assert!(failed.load(std::sync::atomic::Ordering::Relaxed));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,162 @@
+//! Pluggable places to keep a drive's archive.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+#[cfg(feature = "std")]
+use std::io::{ErrorKind, Write};
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+use crate::{Avd, AvdError, Result, archive, dirty::tracking_mut};
+
+/// Somewhere a drive's archive can be loaded from and saved to, like a file, a database row, or a server.
+/// 
+/// Backends deal in whole archives, as produced by `Avd::to_bytes`. Give one to a drive with `Avd::with_backend` or the builder, and save to it with `persist`.
+pub trait StorageBackend: Debug + Send + Sync {
+    /// Get the stored archive, or `None` if nothing has been stored yet.
+    fn load_bytes(&mut self) -> Result<Option<Vec<u8>>>;
+    /// Store an archive, replacing whatever was there before.
+    fn save_bytes(&mut self, archive: &[u8]) -> Result<()>;
+}
+impl<B: StorageBackend + ?Sized> StorageBackend for Box<B> {
+    fn load_bytes(&mut self) -> Result<Option<Vec<u8>>> {
+        (**self).load_bytes()
+    }
+    fn save_bytes(&mut self, archive: &[u8]) -> Result<()> {
+        (**self).save_bytes(archive)
+    }
+}
+
+/// Keeps the archive in a file. Saves are atomic, like `Avd::save`. A missing file counts as nothing stored yet.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct FileBackend {
+    path: PathBuf,
+}
+#[cfg(feature = "std")]
+impl FileBackend {
+    /// Keep the archive in the file at `path`.
+    pub fn new(path: impl Into<PathBuf>) -> FileBackend {
+        FileBackend {
+            path: path.into()
+        }
+    }
+    /// The file the archive is kept in.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+#[cfg(feature = "std")]
+impl StorageBackend for FileBackend {
+    fn load_bytes(&mut self) -> Result<Option<Vec<u8>>> {
+        match std::fs::read(&self.path) {
+            Ok(v) => Ok(Some(v)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into())
+        }
+    }
+    fn save_bytes(&mut self, archive: &[u8]) -> Result<()> {
+        crate::atomic::write_atomic(&self.path, 0, |w| Ok(w.write_all(archive)?))?;
+        Ok(())
+    }
+}
+
+/// Keeps the archive in memory, mostly for tests.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryBackend {
+    archive: Option<Vec<u8>>,
+}
+impl MemoryBackend {
+    /// Make an empty backend, with nothing stored.
+    pub fn new() -> MemoryBackend {
+        MemoryBackend::default()
+    }
+    /// Make a backend that already holds an archive.
+    pub fn from_bytes(archive: Vec<u8>) -> MemoryBackend {
+        MemoryBackend {
+            archive: Some(archive)
+        }
+    }
+    /// The stored archive, if anything has been stored.
+    pub fn bytes(&self) -> Option<&[u8]> {
+        self.archive.as_deref()
+    }
+}
+impl StorageBackend for MemoryBackend {
+    fn load_bytes(&mut self) -> Result<Option<Vec<u8>>> {
+        Ok(self.archive.clone())
+    }
+    fn save_bytes(&mut self, archive: &[u8]) -> Result<()> {
+        self.archive = Some(archive.to_vec());
+        Ok(())
+    }
+}
+
+impl Avd {
+    /// Make a drive that's kept in `backend`, loading whatever is stored there already. If nothing is, the drive starts blank.
+    pub fn with_backend(backend: impl StorageBackend + 'static) -> Result<Avd> {
+        let mut d = Avd::new();
+        d.set_backend(backend)?;
+        Ok(d)
+    }
+    /// Switch the drive over to `backend`, loading whatever is stored there. Like `load`, this overwrites the entire drive, unless nothing is stored yet, in which case the drive is left as it is.
+    pub fn set_backend(&mut self, backend: impl StorageBackend + 'static) -> Result<()> {
+        let mut backend: Box<dyn StorageBackend> = Box::new(backend);
+        if let Some(archive) = backend.load_bytes()? {
+            let (format, records) = archive::decode(&archive)?;
+            self.replace_contents(format, &records);
+            self.mark_clean();
+        }
+        self.backend = Some(backend);
+        Ok(())
+    }
+    /// Save the drive to its backend.
+    pub fn persist(&mut self) -> Result<()> {
+        let archive = self.to_bytes();
+        self.backend.as_mut().ok_or(AvdError::Unsupported("saving a drive with no storage backend"))?.save_bytes(&archive)?;
+        #[cfg(feature = "std")]
+        self.metrics.flushed(archive.len() as u64);
+        self.mark_clean();
+        Ok(())
+    }
+    /// Take the drive's backend away, leaving it with none.
+    pub fn take_backend(&mut self) -> Option<Box<dyn StorageBackend>> {
+        self.backend.take()
+    }
+    /// Forget about changes after a load or save that didn't involve a file. For the same reason, incremental saves to the last file are off until it's saved again.
+    fn mark_clean(&mut self) {
+        let tracking = tracking_mut(&mut self.tracking);
+        tracking.dirty.clear();
+        #[cfg(feature = "std")]
+        {
+            tracking.layout = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn backends() {
+        let mut drive = Avd::with_backend(MemoryBackend::new()).unwrap();
+        assert_eq!(drive.used_blocks(), 0);
+        drive.set_block(8, &[8; 256]).unwrap();
+        assert!(drive.is_dirty());
+        drive.persist().unwrap();
+        assert!(!drive.is_dirty());
+        let backend = drive.take_backend().unwrap();
+        assert!(drive.persist().is_err());
+        assert_eq!(Avd::with_backend(backend).unwrap(), drive);
+
+        #[cfg(feature = "std")]
+        {
+            let path = std::env::temp_dir().join(format!("avd-backend-{}.avd", std::process::id()));
+            let mut d2 = Avd::with_backend(FileBackend::new(&path)).unwrap();
+            d2.set_block(9, &[9; 256]).unwrap();
+            d2.persist().unwrap();
+            assert_eq!(Avd::from_host_drive(&path).unwrap(), d2);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,64 @@
+//! Marking blocks as bad, to test how guest software copes with a failing drive.
+
+use alloc::vec::Vec;
+use crate::{Avd, AvdError, Result};
+
+impl Avd {
+    /// Mark a block as bad. Reads and writes of it through `set_block`, [`crate::BlockDevice`] and the other device-side APIs fail with `AvdError::BadSector` from then on, and its contents are left alone. Host-side access like `get_block` still works, so the data can be inspected.
+    /// 
+    /// Bad blocks are saved with the drive in version 2 archives. Older versions have nowhere to put them, so they're lost.
+    pub fn mark_bad(&mut self, idx: u16) {
+        self.format.bad.insert(idx);
+    }
+    /// Make a bad block good again.
+    pub fn clear_bad(&mut self, idx: u16) {
+        self.format.bad.remove(&idx);
+    }
+    /// Check if a block is marked bad.
+    pub fn is_bad(&self, idx: u16) -> bool {
+        self.format.bad.contains(&idx)
+    }
+    /// The indices of every block marked bad, in order.
+    pub fn bad_blocks(&self) -> Vec<u16> {
+        self.format.bad.iter().copied().collect()
+    }
+    /// Fail if `idx` is bad.
+    pub(crate) fn check_bad(&self, idx: u16) -> Result<()> {
+        match self.is_bad(idx) {
+            true => Err(AvdError::BadSector(idx)),
+            false => Ok(())
+        }
+    }
+    /// Read a block the way the device side sees it: absent blocks are zeros and bad blocks are errors.
+    pub(crate) fn device_read(&self, idx: u16) -> Result<[u8; 256]> {
+        self.check_bad(idx)?;
+        Ok(self.get_block(idx).unwrap_or([0; 256]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ArchiveVersion, BlockDevice};
+    #[test]
+    fn bad_blocks() {
+        let mut drive = Avd::new();
+        drive.set_block(5, &[5; 256]).unwrap();
+        drive.mark_bad(5);
+        drive.mark_bad(900);
+        assert!(matches!(drive.set_block(5, &[6; 256]), Err(AvdError::BadSector(5))));
+        assert!(matches!(drive.set_blocks(899, &[[1; 256]; 2]), Err(AvdError::BadSector(900))));
+        assert!(!drive.is_block_used(899));
+        assert!(matches!(BlockDevice::read_block(&mut drive, 5), Err(AvdError::BadSector(5))));
+        assert!(matches!(drive.read_block_timed(900), Err(AvdError::BadSector(900))));
+        assert_eq!(drive.get_block(5), Some([5; 256]));
+
+        drive.set_archive_version(ArchiveVersion::V2);
+        let d2 = Avd::from_bytes(&drive.to_bytes()).unwrap();
+        assert_eq!(d2.bad_blocks(), [5, 900]);
+        drive.set_archive_version(ArchiveVersion::V0);
+        assert!(Avd::from_bytes(&drive.to_bytes()).unwrap().bad_blocks().is_empty());
+        drive.clear_bad(5);
+        drive.set_block(5, &[6; 256]).unwrap();
+    }
+}
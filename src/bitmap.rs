@@ -0,0 +1,59 @@
+//! A bitmap with one bit for each block on the drive.
+
+use core::fmt;
+use alloc::boxed::Box;
+use crate::BLOCK_COUNT;
+
+/// One bit per block, 8kb in total.
+#[derive(Clone, PartialEq)]
+pub(crate) struct Bitmap(Box<[u64; BLOCK_COUNT / 64]>);
+impl Bitmap {
+    pub fn new() -> Bitmap {
+        Bitmap(Box::new([0; BLOCK_COUNT / 64]))
+    }
+    pub fn get(&self, idx: u16) -> bool {
+        self.0[idx as usize / 64] & (1 << (idx % 64)) != 0
+    }
+    pub fn set(&mut self, idx: u16, v: bool) {
+        let word = &mut self.0[idx as usize / 64];
+        if v {
+            *word |= 1 << (idx % 64)
+        }
+        else {
+            *word &= !(1 << (idx % 64))
+        }
+    }
+    pub fn clear(&mut self) {
+        self.0.fill(0)
+    }
+    /// Iterate over the set bits, in order.
+    pub fn iter(&self) -> impl Iterator<Item = u16> + '_ {
+        self.0.iter().enumerate().filter(|(_, w)| **w != 0).flat_map(|(i, w)| {
+            (0..64).filter(move |b| w & (1 << b) != 0).map(move |b| (i * 64 + b) as u16)
+        })
+    }
+    pub fn count(&self) -> usize {
+        self.0.iter().map(|w| w.count_ones() as usize).sum()
+    }
+}
+impl fmt::Debug for Bitmap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Bitmap({} set)", self.count())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn set_get() {
+        let mut b = Bitmap::new();
+        b.set(0, true);
+        b.set(65535, true);
+        b.set(64, true);
+        b.set(64, false);
+        assert!(b.get(0) && b.get(65535) && !b.get(64) && !b.get(1));
+        assert_eq!(b.count(), 2);
+        assert_eq!(b.iter().collect::<Vec<_>>(), [0, 65535]);
+    }
+}
@@ -0,0 +1,222 @@
+//! A builder for drives with non-default settings.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::fs::File;
+#[cfg(feature = "std")]
+use std::io::BufReader;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "std")]
+use crate::{AvdError, autosave::DropSave};
+use crate::{Avd, ArchiveVersion, LatencyModel, Result, StorageBackend};
+
+/// Where a built drive's blocks come from.
+#[derive(Debug, Default)]
+enum Contents {
+    #[default]
+    Empty,
+    Blocks(Vec<(u16, [u8; 256])>),
+    #[cfg(feature = "std")]
+    Archive(PathBuf),
+    #[cfg(feature = "std")]
+    Raw(PathBuf),
+    Backend(Box<dyn StorageBackend>),
+}
+
+/// Builds an [`Avd`] with settings and initial contents. Settings that aren't given are left at their defaults, or whatever the loaded archive used.
+#[derive(Debug, Default)]
+pub struct AvdBuilder {
+    contents: Contents,
+    zero_elision: Option<bool>,
+    version: Option<ArchiveVersion>,
+    block_checksums: Option<bool>,
+    archive_checksum: Option<bool>,
+    #[cfg(feature = "compression")]
+    compression: Option<bool>,
+    write_protected: Option<bool>,
+    latency: Option<LatencyModel>,
+    label: Option<String>,
+    comment: Option<String>,
+    #[cfg(feature = "std")]
+    backups: Option<u32>,
+    #[cfg(feature = "std")]
+    drop_save: DropSave,
+}
+impl AvdBuilder {
+    /// Start building a blank drive with default settings.
+    pub fn new() -> AvdBuilder {
+        AvdBuilder::default()
+    }
+    /// Start with these blocks, set in order as with `set_block`.
+    pub fn blocks(mut self, blocks: impl IntoIterator<Item = (u16, [u8; 256])>) -> AvdBuilder {
+        self.contents = Contents::Blocks(blocks.into_iter().collect());
+        self
+    }
+    /// Start with the contents and format of an archive file.
+    #[cfg(feature = "std")]
+    pub fn archive(mut self, path: impl Into<PathBuf>) -> AvdBuilder {
+        self.contents = Contents::Archive(path.into());
+        self
+    }
+    /// Start with the contents of a flat raw image file.
+    #[cfg(feature = "std")]
+    pub fn raw_image(mut self, path: impl Into<PathBuf>) -> AvdBuilder {
+        self.contents = Contents::Raw(path.into());
+        self
+    }
+    /// Keep the drive in a storage backend, starting with whatever is stored there. See `Avd::with_backend`.
+    pub fn backend(mut self, backend: impl StorageBackend + 'static) -> AvdBuilder {
+        self.contents = Contents::Backend(Box::new(backend));
+        self
+    }
+    /// See `Avd::set_zero_elision`. This also applies to the initial blocks.
+    pub fn zero_elision(mut self, enabled: bool) -> AvdBuilder {
+        self.zero_elision = Some(enabled);
+        self
+    }
+    /// See `Avd::set_archive_version`.
+    pub fn archive_version(mut self, version: ArchiveVersion) -> AvdBuilder {
+        self.version = Some(version);
+        self
+    }
+    /// See `Avd::set_block_checksums`.
+    pub fn block_checksums(mut self, enabled: bool) -> AvdBuilder {
+        self.block_checksums = Some(enabled);
+        self
+    }
+    /// See `Avd::set_archive_checksum`.
+    pub fn archive_checksum(mut self, enabled: bool) -> AvdBuilder {
+        self.archive_checksum = Some(enabled);
+        self
+    }
+    /// See `Avd::set_compression`.
+    #[cfg(feature = "compression")]
+    pub fn compression(mut self, enabled: bool) -> AvdBuilder {
+        self.compression = Some(enabled);
+        self
+    }
+    /// See `Avd::set_write_protected`. The initial blocks are written either way.
+    pub fn write_protected(mut self, protected: bool) -> AvdBuilder {
+        self.write_protected = Some(protected);
+        self
+    }
+    /// See `Avd::set_latency`.
+    pub fn latency(mut self, model: LatencyModel) -> AvdBuilder {
+        self.latency = Some(model);
+        self
+    }
+    /// See `Avd::set_label`.
+    pub fn label(mut self, label: impl Into<String>) -> AvdBuilder {
+        self.label = Some(label.into());
+        self
+    }
+    /// See `Avd::set_comment`.
+    pub fn comment(mut self, comment: impl Into<String>) -> AvdBuilder {
+        self.comment = Some(comment.into());
+        self
+    }
+    /// See `Avd::set_backups`.
+    #[cfg(feature = "std")]
+    pub fn backups(mut self, n: u32) -> AvdBuilder {
+        self.backups = Some(n);
+        self
+    }
+    /// See `Avd::save_on_drop`. To save back to the archive the drive was loaded from, pass the same path to `archive` and here.
+    #[cfg(feature = "std")]
+    pub fn save_on_drop(mut self, path: impl Into<PathBuf>) -> AvdBuilder {
+        self.drop_save.path(path.into());
+        self
+    }
+    /// See `Avd::on_save_error`.
+    #[cfg(feature = "std")]
+    pub fn on_save_error(mut self, f: impl Fn(&Path, AvdError) + Send + Sync + 'static) -> AvdBuilder {
+        self.drop_save.on_error(Box::new(f));
+        self
+    }
+    /// Make the drive. Only fails if the initial contents can't be loaded.
+    pub fn build(self) -> Result<Avd> {
+        let mut d = Avd::new();
+        if let Some(v) = self.zero_elision {
+            d.set_zero_elision(v)
+        }
+        match self.contents {
+            Contents::Empty => {}
+            Contents::Blocks(blocks) => d.extend(blocks),
+            #[cfg(feature = "std")]
+            Contents::Archive(path) => d.load(path)?,
+            #[cfg(feature = "std")]
+            Contents::Raw(path) => d.read_raw(BufReader::new(File::open(path)?))?,
+            Contents::Backend(b) => d.set_backend(b)?,
+        }
+        if let Some(v) = self.version {
+            d.set_archive_version(v)
+        }
+        if let Some(v) = self.block_checksums {
+            d.set_block_checksums(v)
+        }
+        if let Some(v) = self.archive_checksum {
+            d.set_archive_checksum(v)
+        }
+        #[cfg(feature = "compression")]
+        if let Some(v) = self.compression {
+            d.set_compression(v)
+        }
+        if let Some(v) = self.write_protected {
+            d.set_write_protected(v)
+        }
+        if let Some(v) = self.latency {
+            d.set_latency(v)
+        }
+        if let Some(v) = self.label {
+            d.set_label(v)
+        }
+        if let Some(v) = self.comment {
+            d.set_comment(v)
+        }
+        #[cfg(feature = "std")]
+        if let Some(v) = self.backups {
+            d.set_backups(v)
+        }
+        #[cfg(feature = "std")]
+        d.set_drop_save(self.drop_save);
+        Ok(d)
+    }
+}
+
+impl Avd {
+    /// Start building a drive with non-default settings. See [`AvdBuilder`].
+    pub fn builder() -> AvdBuilder {
+        AvdBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn builder() {
+        let drive = Avd::builder().blocks([(1, [1; 256]), (2, [0; 256])]).block_checksums(true).label("built").build().unwrap();
+        assert_eq!(drive.used_blocks(), 1);
+        assert!(drive.block_checksums() && drive.zero_elision());
+        assert_eq!(drive.label(), "built");
+        #[cfg(feature = "std")]
+        {
+            let path = std::env::temp_dir().join(format!("avd-builder-{}.avd", std::process::id()));
+            drive.save(&path).unwrap();
+            // the archive's settings stick unless they're overridden
+            let loaded = Avd::builder().archive(&path).label("renamed").build().unwrap();
+            assert_eq!(loaded, drive);
+            assert!(loaded.block_checksums());
+            assert_eq!(loaded.label(), "renamed");
+            assert!(Avd::builder().archive(path.with_extension("missing")).build().is_err());
+            let mut d = Avd::builder().archive(&path).save_on_drop(&path).build().unwrap();
+            d.set_block(3, &[3; 256]).unwrap();
+            drop(d);
+            assert_eq!(Avd::from_host_drive(&path).unwrap().used_blocks(), 2);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,318 @@
+//! Byte-addressed access to the drive, treating it as one flat 16mb address space.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::vec;
+use crate::{Avd, block_range, BLOCK_SIZE, DRIVE_SIZE};
+
+impl Avd {
+    /// Read bytes from the drive, starting at the byte address `addr`. Reads can cross block boundaries, and absent blocks read as zeros.
+    /// 
+    /// Panics if the read goes past the end of the drive.
+    pub fn read_bytes(&self, addr: u32, buf: &mut [u8]) {
+        let addr = check_span(addr, buf.len());
+        for (idx, offset, range) in spans(addr, buf.len()) {
+            let dst = &mut buf[range];
+            match self.get_block_ref(idx) {
+                Some(data) => dst.copy_from_slice(&data[offset..offset + dst.len()]),
+                None => dst.fill(0)
+            }
+        }
+    }
+    /// Write bytes to the drive, starting at the byte address `addr`. Blocks are created as needed, and any that end up all zeros are removed, unless zero elision is off (see `set_zero_elision`).
+    /// 
+    /// Panics if the write goes past the end of the drive.
+    pub fn write_bytes(&mut self, addr: u32, data: &[u8]) {
+        let addr = check_span(addr, data.len());
+        for (idx, offset, range) in spans(addr, data.len()) {
+            let src = &data[range];
+            if self.elide_zeros && !self.used.get(idx) && src.iter().all(|b| *b == 0) {
+                continue // nothing to do, and no point creating the block
+            }
+            self.modify_block(idx, |b| b[offset..offset + src.len()].copy_from_slice(src))
+        }
+    }
+}
+
+impl Avd {
+    /// Fill `len` bytes starting at the byte address `addr` with `pattern`, repeated as many times as needed. Blocks that end up all zeros are removed, unless zero elision is off.
+    /// 
+    /// Panics if the pattern is empty or the fill goes past the end of the drive.
+    pub fn fill_range(&mut self, addr: u32, len: usize, pattern: &[u8]) {
+        assert!(!pattern.is_empty(), "empty fill pattern");
+        let addr = check_span(addr, len);
+        let zero = self.elide_zeros && pattern.iter().all(|b| *b == 0);
+        for (idx, offset, range) in spans(addr, len) {
+            if zero && (offset, range.len()) == (0, BLOCK_SIZE) {
+                self.delete_block(idx);
+                continue
+            }
+            self.modify_block(idx, |b| {
+                for (i, byte) in range.enumerate() {
+                    b[offset + i] = pattern[byte % pattern.len()]
+                }
+            })
+        }
+    }
+    /// Fill every block in `range` with `byte`. Filling with zero removes the blocks, unless zero elision is off.
+    pub fn fill_blocks(&mut self, range: impl core::ops::RangeBounds<u16>, byte: u8) {
+        let range = block_range(range);
+        if range.is_empty() {
+            return
+        }
+        if byte == 0 && self.elide_zeros {
+            self.delete_range(range.start as u16..=(range.end - 1) as u16)
+        }
+        else {
+            for idx in range {
+                self.store_block(idx as u16, &[byte; BLOCK_SIZE])
+            }
+        }
+    }
+    /// Find the byte address of the first occurrence of `pattern` on the drive. Matches can cross block boundaries. An empty pattern never matches.
+    pub fn find(&self, pattern: &[u8]) -> Option<u32> {
+        self.search(pattern, true).first().copied()
+    }
+    /// Find the byte addresses of every occurrence of `pattern` on the drive, in ascending order. Matches may overlap.
+    pub fn find_all(&self, pattern: &[u8]) -> Vec<u32> {
+        self.search(pattern, false)
+    }
+    fn search(&self, pattern: &[u8], first_only: bool) -> Vec<u32> {
+        let mut ret = Vec::new();
+        if pattern.is_empty() || pattern.len() > DRIVE_SIZE {
+            return ret
+        }
+        // a match containing a non-zero byte has to overlap an occupied block, so only look near those.
+        // an all-zero pattern can match anywhere, so it gets the slow path
+        let mut windows: Vec<core::ops::Range<usize>> = Vec::new();
+        if pattern.iter().all(|b| *b == 0) {
+            windows.push(0..DRIVE_SIZE)
+        }
+        else {
+            for (i, _) in self.blocks() {
+                let start = (i as usize * BLOCK_SIZE).saturating_sub(pattern.len() - 1);
+                let end = i as usize * BLOCK_SIZE + BLOCK_SIZE;
+                match windows.last_mut() {
+                    Some(w) if w.end >= start => w.end = end,
+                    _ => windows.push(start..end)
+                }
+            }
+        }
+        let mut buf = Vec::new();
+        for w in windows {
+            // w is the range of possible match starts
+            let end = (w.end + pattern.len() - 1).min(DRIVE_SIZE);
+            buf.resize(end - w.start, 0);
+            self.read_bytes(w.start as u32, &mut buf);
+            for (i, win) in buf.windows(pattern.len()).enumerate() {
+                if win == pattern {
+                    ret.push((w.start + i) as u32);
+                    if first_only {
+                        return ret
+                    }
+                }
+            }
+        }
+        ret
+    }
+}
+
+impl Avd {
+    /// Read a null-terminated string at the byte address `addr`, stopping after `max_len` bytes if no terminator is found (or at the end of the drive). Invalid UTF-8 is replaced with U+FFFD.
+    pub fn read_cstr(&self, addr: u32, max_len: usize) -> String {
+        let addr = check_span(addr, 0);
+        let mut buf = vec![0; max_len.min(DRIVE_SIZE - addr)];
+        self.read_bytes(addr as u32, &mut buf);
+        let end = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+        String::from_utf8_lossy(&buf[..end]).into_owned()
+    }
+    /// Write `s` as a null-terminated string at the byte address `addr`.
+    /// 
+    /// Panics if the string goes past the end of the drive.
+    pub fn write_cstr(&mut self, addr: u32, s: &str) {
+        self.write_bytes(addr, s.as_bytes());
+        self.write_bytes(addr + s.len() as u32, &[0])
+    }
+    /// Read a length-prefixed string (one length byte, then the data) at the byte address `addr`. Invalid UTF-8 is replaced with U+FFFD.
+    /// 
+    /// Panics if the string goes past the end of the drive.
+    pub fn read_pstr(&self, addr: u32) -> String {
+        let mut len = [0];
+        self.read_bytes(addr, &mut len);
+        let mut buf = vec![0; len[0] as usize];
+        self.read_bytes(addr + 1, &mut buf);
+        String::from_utf8_lossy(&buf).into_owned()
+    }
+    /// Write `s` as a length-prefixed string (one length byte, then the data) at the byte address `addr`.
+    /// 
+    /// Panics if the string is longer than 255 bytes or goes past the end of the drive.
+    pub fn write_pstr(&mut self, addr: u32, s: &str) {
+        let len: u8 = s.len().try_into().expect("string too long to length-prefix");
+        self.write_bytes(addr, &[len]);
+        self.write_bytes(addr + 1, s.as_bytes())
+    }
+}
+
+macro_rules! read_int {
+    ($($name:ident: $t:ty, $conv:ident, $doc:literal;)*) => {
+        impl Avd {
+            $(
+                #[doc = $doc]
+                /// 
+                /// Panics if the value goes past the end of the drive.
+                pub fn $name(&self, addr: u32) -> $t {
+                    let mut buf = [0; core::mem::size_of::<$t>()];
+                    self.read_bytes(addr, &mut buf);
+                    <$t>::$conv(buf)
+                }
+            )*
+        }
+    };
+}
+read_int! {
+    read_u16_be: u16, from_be_bytes, "Read a big-endian `u16` at the byte address `addr`.";
+    read_u16_le: u16, from_le_bytes, "Read a little-endian `u16` at the byte address `addr`.";
+    read_u32_be: u32, from_be_bytes, "Read a big-endian `u32` at the byte address `addr`.";
+    read_u32_le: u32, from_le_bytes, "Read a little-endian `u32` at the byte address `addr`.";
+    read_u64_be: u64, from_be_bytes, "Read a big-endian `u64` at the byte address `addr`.";
+    read_u64_le: u64, from_le_bytes, "Read a little-endian `u64` at the byte address `addr`.";
+}
+
+macro_rules! write_int {
+    ($($name:ident: $t:ty, $conv:ident, $doc:literal;)*) => {
+        impl Avd {
+            $(
+                #[doc = $doc]
+                /// 
+                /// Panics if the value goes past the end of the drive.
+                pub fn $name(&mut self, addr: u32, v: $t) {
+                    self.write_bytes(addr, &v.$conv())
+                }
+            )*
+        }
+    };
+}
+write_int! {
+    write_u16_be: u16, to_be_bytes, "Write a big-endian `u16` at the byte address `addr`.";
+    write_u16_le: u16, to_le_bytes, "Write a little-endian `u16` at the byte address `addr`.";
+    write_u32_be: u32, to_be_bytes, "Write a big-endian `u32` at the byte address `addr`.";
+    write_u32_le: u32, to_le_bytes, "Write a little-endian `u32` at the byte address `addr`.";
+    write_u64_be: u64, to_be_bytes, "Write a big-endian `u64` at the byte address `addr`.";
+    write_u64_le: u64, to_le_bytes, "Write a little-endian `u64` at the byte address `addr`.";
+}
+
+/// Check that `len` bytes starting at `addr` fit on the drive, panicking if they don't.
+pub(crate) fn check_span(addr: u32, len: usize) -> usize {
+    let addr = addr as usize;
+    assert!(addr + len <= DRIVE_SIZE, "byte span goes past the end of the drive");
+    addr
+}
+
+/// Split a byte span into its per-block pieces, as (block index, offset into block, range in the caller's buffer).
+pub(crate) fn spans(addr: usize, len: usize) -> impl Iterator<Item = (u16, usize, core::ops::Range<usize>)> {
+    let mut pos = 0;
+    core::iter::from_fn(move || {
+        if pos == len {
+            return None
+        }
+        let a = addr + pos;
+        let offset = a % BLOCK_SIZE;
+        let n = (BLOCK_SIZE - offset).min(len - pos);
+        let ret = ((a / BLOCK_SIZE) as u16, offset, pos..pos + n);
+        pos += n;
+        Some(ret)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn read_across_blocks() {
+        let mut drive = Avd::new();
+        drive.set_block(1, &[1; 256]).unwrap();
+        drive.set_block(3, &[3; 256]).unwrap();
+        let mut buf = [0xaa; 514];
+        drive.read_bytes(0x1fe, &mut buf);
+        assert_eq!(buf[..2], [1, 1]);
+        assert!(buf[2..258].iter().all(|b| *b == 0));
+        assert!(buf[258..].iter().all(|b| *b == 3));
+    }
+    #[test]
+    fn write_across_blocks() {
+        let mut drive = Avd::new();
+        let data: Vec<u8> = (1..=255).cycle().take(600).collect();
+        drive.write_bytes(0x2ff, &data);
+        assert_eq!(drive.used_blocks(), 4);
+        let mut buf = vec![0; 600];
+        drive.read_bytes(0x2ff, &mut buf);
+        assert_eq!(buf, data);
+        drive.write_bytes(0x2ff, &[0; 600]);
+        assert_eq!(drive.used_blocks(), 0);
+    }
+    #[test]
+    fn read_ints() {
+        let mut drive = Avd::new();
+        drive.write_bytes(0xfe, &[0x12, 0x34, 0x56, 0x78]);
+        assert_eq!(drive.read_u16_be(0xff), 0x3456);
+        assert_eq!(drive.read_u16_le(0xff), 0x5634);
+        assert_eq!(drive.read_u32_be(0xfe), 0x12345678);
+        assert_eq!(drive.read_u32_le(0xfe), 0x78563412);
+        assert_eq!(drive.read_u64_be(0xfe), 0x1234567800000000);
+    }
+    #[test]
+    fn write_ints() {
+        let mut drive = Avd::new();
+        drive.write_u32_le(0x1fe, 0xdeadbeef);
+        assert_eq!(drive.get_block(1).unwrap()[254..], [0xef, 0xbe]);
+        assert_eq!(drive.get_block(2).unwrap()[..2], [0xad, 0xde]);
+        drive.write_u64_be(0x3fc, u64::MAX);
+        assert_eq!(drive.read_u64_le(0x3fc), u64::MAX);
+        drive.write_u16_be(0x1fe, 0);
+        drive.write_u16_le(0x200, 0);
+        assert_eq!(drive.used_blocks(), 2);
+    }
+    #[test]
+    fn search() {
+        let mut drive = Avd::new();
+        drive.write_bytes(0x4fe, b"hello");
+        drive.write_bytes(0xff00, b"hellhello");
+        assert_eq!(drive.find(b"hello"), Some(0x4fe));
+        assert_eq!(drive.find_all(b"hello"), [0x4fe, 0xff04]);
+        assert_eq!(drive.find_all(b"l"), [0x500, 0x501, 0xff02, 0xff03, 0xff06, 0xff07]);
+        assert_eq!(drive.find(b"goodbye"), None);
+        assert_eq!(drive.find(&[0, 0]), Some(0));
+        assert_eq!(drive.find(b""), None);
+    }
+    #[test]
+    fn fill() {
+        let mut drive = Avd::new();
+        drive.fill_blocks(10..20, 0xe5);
+        assert_eq!(drive.used_blocks(), 10);
+        drive.fill_range(0x0a80, 0x100, &[0]);
+        assert_eq!(drive.used_blocks(), 10);
+        drive.fill_range(0x0a00, 0x200, &[0]);
+        assert_eq!(drive.used_blocks(), 8);
+        drive.fill_range(0x0bff, 4, b"ab");
+        assert_eq!(drive.read_u32_be(0x0bff), u32::from_be_bytes(*b"abab"));
+        drive.fill_blocks(0..0, 0);
+        drive.fill_blocks(.., 0);
+        assert_eq!(drive.used_blocks(), 0);
+    }
+    #[test]
+    fn strings() {
+        let mut drive = Avd::new();
+        drive.write_cstr(0xfc, "DISK LABEL");
+        assert_eq!(drive.read_cstr(0xfc, 64), "DISK LABEL");
+        assert_eq!(drive.read_cstr(0xfc, 4), "DISK");
+        assert_eq!(drive.read_cstr(DRIVE_SIZE as u32 - 1, 64), "");
+        drive.write_pstr(0x1000, "hello");
+        assert_eq!(drive.get_block(0x10).unwrap()[0], 5);
+        assert_eq!(drive.read_pstr(0x1000), "hello");
+    }
+    #[test]
+    #[should_panic]
+    fn read_past_end() {
+        Avd::new().read_bytes(DRIVE_SIZE as u32 - 1, &mut [0; 2])
+    }
+}
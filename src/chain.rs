@@ -0,0 +1,175 @@
+//! Backup chains: a full archive followed by incremental backups, each holding just the blocks that changed since the one before.
+//! 
+//! An incremental backup starts with the magic bytes `AVI` and a version byte (0), then the manifest hash of the drive it follows on from and the manifest hash of the drive once it's been applied, 32 bytes each. After that come entries as in a delta archive, and a big-endian CRC32 of everything after the version byte.
+//! 
+//! A manifest hash is the SHA-256 of each stored block's big-endian index followed by the SHA-256 of its data, in index order, so it only depends on what's on the drive. Saved manifests are the magic bytes `AVM` and a version byte (0), then each block's index and data hash, then a big-endian CRC32 of those.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use sha2::{Digest, Sha256};
+use crate::{Avd, AvdError, Result, atomic, delta};
+
+const BACKUP_HEADER: [u8; 4] = *b"AVI\x00";
+const MANIFEST_HEADER: [u8; 4] = *b"AVM\x00";
+
+/// The hash of every block on a drive at some point, so an incremental backup can be made against it without keeping the drive itself around. Made with `Avd::manifest`, or returned by `save_incremental_since` for the backup it just made.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupManifest {
+    blocks: BTreeMap<u16, [u8; 32]>,
+    hash: [u8; 32],
+}
+impl BackupManifest {
+    fn new(blocks: BTreeMap<u16, [u8; 32]>) -> BackupManifest {
+        let mut h = Sha256::new();
+        for (idx, block) in &blocks {
+            h.update(idx.to_be_bytes());
+            h.update(block);
+        }
+        BackupManifest {
+            hash: h.finalize().into(), blocks
+        }
+    }
+    /// The hash of the whole drive. Incremental backups refer to their parent by this.
+    pub fn hash(&self) -> [u8; 32] {
+        self.hash
+    }
+    /// Turn the manifest into bytes, to keep it around until the next backup.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut ret = MANIFEST_HEADER.to_vec();
+        for (idx, block) in &self.blocks {
+            ret.extend(idx.to_be_bytes());
+            ret.extend(block);
+        }
+        let crc = crc32fast::hash(&ret[4..]);
+        ret.extend(crc.to_be_bytes());
+        ret
+    }
+    /// Read a manifest made by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<BackupManifest> {
+        let header = bytes.get(..4).ok_or(AvdError::MalformedArchive)?;
+        if header != MANIFEST_HEADER {
+            return Err(AvdError::BadHeader(header[0], header[1], header[2], header[3]))
+        }
+        let body = &bytes[4..];
+        let split = body.len().checked_sub(4).ok_or(AvdError::MalformedArchive)?;
+        let (entries, crc) = body.split_at(split);
+        if crc32fast::hash(entries).to_be_bytes() != crc {
+            return Err(AvdError::BadArchiveChecksum)
+        }
+        if !entries.len().is_multiple_of(34) {
+            return Err(AvdError::MalformedArchive)
+        }
+        let blocks = entries.chunks_exact(34).map(|e| {
+            (u16::from_be_bytes([e[0], e[1]]), e[2..].try_into().unwrap()) // SHOULD NEVER PANIC
+        }).collect();
+        Ok(BackupManifest::new(blocks))
+    }
+}
+
+/// Split an incremental backup into its parent's hash, its own hash, and its changes.
+fn parse_backup(backup: &[u8]) -> Result<([u8; 32], [u8; 32], Vec<delta::Change>)> {
+    let header = backup.get(..4).ok_or(AvdError::MalformedArchive)?;
+    if header != BACKUP_HEADER {
+        return Err(AvdError::BadHeader(header[0], header[1], header[2], header[3]))
+    }
+    let body = backup.get(4..).filter(|b| b.len() >= 68).ok_or(AvdError::MalformedArchive)?;
+    let (body, crc) = body.split_at(body.len() - 4);
+    if crc32fast::hash(body).to_be_bytes() != crc {
+        return Err(AvdError::BadArchiveChecksum)
+    }
+    let parent = body[..32].try_into().unwrap(); // SHOULD NEVER PANIC
+    let hash = body[32..64].try_into().unwrap(); // SHOULD NEVER PANIC
+    Ok((parent, hash, delta::parse_entries(&body[64..])?))
+}
+
+impl Avd {
+    /// Make a manifest of the drive as it is now. To start a backup chain, `save` the drive and keep its manifest, then make each backup after that with `save_incremental_since`.
+    pub fn manifest(&self) -> BackupManifest {
+        BackupManifest::new(self.blocks.iter().map(|(idx, data)| (*idx, Sha256::digest(data).into())).collect())
+    }
+    /// Save an incremental backup to `path`, holding just the blocks that have changed since `base` was made. Returns the manifest of the drive as it is now, to make the next backup against.
+    /// 
+    /// The backup refers to `base` by its hash, so `from_chain` can tell if it's been put after the wrong file. Like `save`, the file is written atomically.
+    pub fn save_incremental_since(&self, path: impl AsRef<Path>, base: &BackupManifest) -> Result<BackupManifest> {
+        let manifest = self.manifest();
+        let mut changes: BTreeMap<u16, Option<&[u8; 256]>> = base.blocks.keys()
+            .filter(|idx| !manifest.blocks.contains_key(idx))
+            .map(|idx| (*idx, None))
+            .collect();
+        changes.extend(manifest.blocks.iter()
+            .filter(|(idx, block)| base.blocks.get(idx) != Some(block))
+            .map(|(idx, _)| (*idx, self.blocks.get(idx))));
+        let mut backup = BACKUP_HEADER.to_vec();
+        backup.extend(base.hash);
+        backup.extend(manifest.hash);
+        delta::write_entries(&mut backup, changes.into_iter());
+        let crc = crc32fast::hash(&backup[4..]);
+        backup.extend(crc.to_be_bytes());
+        let len = atomic::write_atomic(path.as_ref(), 0, |w| Ok(w.write_all(&backup)?))?;
+        self.metrics.flushed(len);
+        Ok(manifest)
+    }
+    /// Put a drive back together from a backup chain: a full archive, then the incremental backups made after it, oldest first. An empty chain gives an empty drive.
+    /// 
+    /// Each backup is checked against the drive it's applied to, so one that's missing, out of order, or from a different chain fails with `AvdError::BrokenChain`, giving its position in `paths`.
+    pub fn from_chain(paths: &[impl AsRef<Path>]) -> Result<Avd> {
+        let Some((base, rest)) = paths.split_first() else {
+            return Ok(Avd::new())
+        };
+        let mut drive = Avd::from_host_drive(base)?;
+        let mut hash = drive.manifest().hash;
+        for (i, path) in rest.iter().enumerate() {
+            let (parent, child, changes) = parse_backup(&fs::read(path)?)?;
+            if parent != hash {
+                return Err(AvdError::BrokenChain(i + 1))
+            }
+            for (idx, data) in changes {
+                match data {
+                    Some(data) => drive.store_block(idx, &data),
+                    None => {
+                        drive.delete_block(idx);
+                    }
+                }
+            }
+            hash = drive.manifest().hash;
+            if hash != child {
+                return Err(AvdError::BrokenChain(i + 1))
+            }
+        }
+        Ok(drive)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn chain() {
+        let path = |n: &str| std::env::temp_dir().join(format!("avd-chain-{}-{}.avd", std::process::id(), n));
+        let (full, inc1, inc2) = (path("full"), path("inc1"), path("inc2"));
+        let mut drive = Avd::new();
+        for i in 0..50 {
+            drive.set_block(i, &[i as u8 + 1; 256]).unwrap();
+        }
+        drive.save(&full).unwrap();
+        let base = drive.manifest();
+        drive.set_block(3, &[0xff; 256]).unwrap();
+        drive.delete_block(4);
+        let m1 = drive.save_incremental_since(&inc1, &base).unwrap();
+        assert_eq!(fs::metadata(&inc1).unwrap().len(), 4 + 64 + 259 + 3 + 4);
+        let snap1 = Avd::from_chain(&[&full, &inc1]).unwrap();
+        drive.set_block(1000, &[7; 256]).unwrap();
+        let m1 = BackupManifest::from_bytes(&m1.to_bytes()).unwrap();
+        drive.save_incremental_since(&inc2, &m1).unwrap();
+        assert_eq!(Avd::from_chain(&[&full, &inc1, &inc2]).unwrap(), drive);
+        assert_eq!(snap1.used_blocks(), 49);
+        assert!(matches!(Avd::from_chain(&[&full, &inc2]), Err(AvdError::BrokenChain(1))));
+        assert!(matches!(Avd::from_chain(&[&full, &inc2, &inc1]), Err(AvdError::BrokenChain(1))));
+        for p in [full, inc1, inc2] {
+            fs::remove_file(p).unwrap();
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! A controller for machines with more than one drive.
+
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::path::Path;
+use crate::{Avd, AvdDrive, AvdError, BlockDevice, Result};
+
+/// What a drive on a controller is up to. See `AvdController::status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DriveStatus {
+    /// Whether there's a disk in the drive. The rest is all false or 0 if there isn't.
+    pub media: bool,
+    /// Whether the disk has been changed since the media changed flag was last cleared.
+    pub media_changed: bool,
+    pub write_protected: bool,
+    /// Whether the disk has changed since it was last saved or loaded.
+    pub dirty: bool,
+    pub used_blocks: usize,
+}
+
+/// A disk controller with a fixed number of [`AvdDrive`] units, addressed by drive number from 0.
+/// 
+/// Reads and writes through the controller, including through [`BlockDevice`], go to the selected drive, which starts out as drive 0. Individual drives can also be reached directly with `drive` and `drive_mut`.
+#[derive(Debug)]
+pub struct AvdController {
+    drives: Vec<AvdDrive>,
+    selected: usize,
+}
+impl AvdController {
+    /// Make a controller with `units` empty drives.
+    /// 
+    /// Panics if `units` is 0.
+    pub fn new(units: usize) -> AvdController {
+        assert!(units != 0, "a controller needs at least one drive");
+        AvdController {
+            drives: (0..units).map(|_| AvdDrive::new()).collect(),
+            selected: 0
+        }
+    }
+    /// The number of drives on the controller.
+    pub fn units(&self) -> usize {
+        self.drives.len()
+    }
+    /// Get a drive by number.
+    pub fn drive(&self, n: usize) -> Option<&AvdDrive> {
+        self.drives.get(n)
+    }
+    /// Get a drive by number, to insert or eject disks.
+    pub fn drive_mut(&mut self, n: usize) -> Option<&mut AvdDrive> {
+        self.drives.get_mut(n)
+    }
+    /// Choose which drive reads and writes go to. Fails with `AvdError::NoSuchDrive` if there isn't one with that number.
+    pub fn select(&mut self, n: usize) -> Result<()> {
+        if n >= self.drives.len() {
+            return Err(AvdError::NoSuchDrive(n))
+        }
+        self.selected = n;
+        Ok(())
+    }
+    /// The number of the selected drive.
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+    /// The selected drive.
+    pub fn selected_drive(&mut self) -> &mut AvdDrive {
+        &mut self.drives[self.selected]
+    }
+    /// Put a disk in drive `n`, handing back any disk that was already there.
+    pub fn insert(&mut self, n: usize, disk: Avd) -> Result<Option<Avd>> {
+        Ok(self.drives.get_mut(n).ok_or(AvdError::NoSuchDrive(n))?.insert(disk))
+    }
+    /// Take the disk out of drive `n`.
+    pub fn eject(&mut self, n: usize) -> Result<Avd> {
+        self.drives.get_mut(n).ok_or(AvdError::NoSuchDrive(n))?.eject()
+    }
+    /// The status of drive `n`, or `None` if there isn't one.
+    pub fn status(&self, n: usize) -> Option<DriveStatus> {
+        let drive = self.drives.get(n)?;
+        Some(match drive.media() {
+            Some(disk) => DriveStatus {
+                media: true,
+                media_changed: drive.media_changed(),
+                write_protected: disk.is_write_protected(),
+                dirty: disk.is_dirty(),
+                used_blocks: disk.used_blocks()
+            },
+            None => DriveStatus {
+                media: false, media_changed: drive.media_changed(), write_protected: false, dirty: false, used_blocks: 0
+            }
+        })
+    }
+    /// Read a block from the selected drive.
+    pub fn read_block(&self, idx: u16) -> Result<[u8; 256]> {
+        self.drives[self.selected].read_block(idx)
+    }
+    /// Write a block to the selected drive.
+    pub fn write_block(&mut self, idx: u16, data: &[u8; 256]) -> Result<()> {
+        self.drives[self.selected].write_block(idx, data)
+    }
+    /// Save the disk in every drive that has one, to the path `path_of` gives for its drive number. Returns how many were saved. Stops at the first error.
+    #[cfg(feature = "std")]
+    pub fn save_all<P: AsRef<Path>>(&self, mut path_of: impl FnMut(usize) -> P) -> Result<usize> {
+        let mut saved = 0;
+        for (n, drive) in self.drives.iter().enumerate() {
+            if let Some(disk) = drive.media() {
+                disk.save(path_of(n))?;
+                saved += 1
+            }
+        }
+        Ok(saved)
+    }
+    /// Load a disk into every drive from the path `path_of` gives for its drive number, replacing whatever was inserted. Drives whose file doesn't exist are left alone. Returns how many were loaded. Stops at the first error.
+    #[cfg(feature = "std")]
+    pub fn load_all<P: AsRef<Path>>(&mut self, mut path_of: impl FnMut(usize) -> P) -> Result<usize> {
+        let mut loaded = 0;
+        for (n, drive) in self.drives.iter_mut().enumerate() {
+            let path = path_of(n);
+            if path.as_ref().exists() {
+                drive.insert(Avd::from_host_drive(path)?);
+                loaded += 1
+            }
+        }
+        Ok(loaded)
+    }
+}
+/// Everything goes to the selected drive.
+impl BlockDevice for AvdController {
+    fn read_block(&mut self, idx: u16) -> Result<[u8; 256]> {
+        AvdController::read_block(self, idx)
+    }
+    fn write_block(&mut self, idx: u16, data: &[u8; 256]) -> Result<()> {
+        AvdController::write_block(self, idx, data)
+    }
+    fn flush(&mut self) -> Result<()> {
+        self.selected_drive().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn controller() {
+        let mut c = AvdController::new(2);
+        assert!(matches!(c.select(2), Err(AvdError::NoSuchDrive(2))));
+        c.insert(1, Avd::new()).unwrap();
+        c.select(1).unwrap();
+        c.write_block(3, &[3; 256]).unwrap();
+        c.select(0).unwrap();
+        assert!(matches!(c.read_block(3), Err(AvdError::NoMedia)));
+        let s = c.status(1).unwrap();
+        assert!(s.media && s.media_changed && s.dirty);
+        assert_eq!(s.used_blocks, 1);
+        assert!(!c.status(0).unwrap().media);
+        assert!(c.status(2).is_none());
+
+        #[cfg(feature = "std")]
+        {
+            let dir = std::env::temp_dir();
+            let path_of = |n| dir.join(format!("avd-controller-{}-{}.avd", std::process::id(), n));
+            assert_eq!(c.save_all(path_of).unwrap(), 1);
+            let mut c2 = AvdController::new(2);
+            assert_eq!(c2.load_all(path_of).unwrap(), 1);
+            assert_eq!(c2.drive(1).unwrap().read_block(3).unwrap(), [3; 256]);
+            assert!(!c2.status(1).unwrap().dirty);
+            std::fs::remove_file(path_of(1)).unwrap();
+        }
+    }
+}
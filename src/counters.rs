@@ -0,0 +1,89 @@
+//! Running totals of what a drive has done, for dashboards.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::Avd;
+
+/// A snapshot of a drive's operation counters, from `Avd::metrics`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// Block reads, counted like observers see them.
+    pub reads: u64,
+    /// Block writes, counted like observers see them.
+    pub writes: u64,
+    /// Blocks that went from absent to present.
+    pub blocks_created: u64,
+    /// Blocks that went from present to absent, including through `clear` and `delete_range`.
+    pub blocks_deleted: u64,
+    /// Bytes written to storage by `save`, `save_incremental`, `persist`, and the other saving methods. Streams written with `save_to` aren't counted.
+    pub bytes_flushed: u64,
+}
+
+/// The counters inside a drive. Reads happen through `&self`, so they're atomic.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    reads: AtomicU64,
+    writes: AtomicU64,
+    created: AtomicU64,
+    deleted: AtomicU64,
+    flushed: AtomicU64,
+}
+/// Bump a counter, and the matching `metrics` counter if that's turned on.
+fn add(counter: &AtomicU64, _name: &'static str, n: u64) {
+    counter.fetch_add(n, Ordering::Relaxed);
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(_name).increment(n);
+}
+impl Counters {
+    pub fn read(&self) {
+        add(&self.reads, "avd_block_reads_total", 1)
+    }
+    pub fn write(&self) {
+        add(&self.writes, "avd_block_writes_total", 1)
+    }
+    pub fn created(&self) {
+        add(&self.created, "avd_blocks_created_total", 1)
+    }
+    pub fn deleted(&self, n: u64) {
+        add(&self.deleted, "avd_blocks_deleted_total", n)
+    }
+    pub fn flushed(&self, n: u64) {
+        add(&self.flushed, "avd_bytes_flushed_total", n)
+    }
+}
+
+impl Avd {
+    /// Get the drive's operation counters. They count up from when the drive was created.
+    /// 
+    /// With the `metrics` feature, every count is also sent to the `metrics` crate's global recorder, as `avd_block_reads_total`, `avd_block_writes_total`, `avd_blocks_created_total`, `avd_blocks_deleted_total` and `avd_bytes_flushed_total`.
+    pub fn metrics(&self) -> Metrics {
+        let c = &self.metrics;
+        Metrics {
+            reads: c.reads.load(Ordering::Relaxed),
+            writes: c.writes.load(Ordering::Relaxed),
+            blocks_created: c.created.load(Ordering::Relaxed),
+            blocks_deleted: c.deleted.load(Ordering::Relaxed),
+            bytes_flushed: c.flushed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn metrics() {
+        let path = std::env::temp_dir().join(format!("avd-metrics-{}.avd", std::process::id()));
+        let mut drive = Avd::new();
+        drive.set_block(1, &[1; 256]).unwrap();
+        drive.set_block(1, &[2; 256]).unwrap();
+        drive.set_block(2, &[2; 256]).unwrap();
+        drive.get_block(1);
+        drive.set_block(1, &[0; 256]).unwrap();
+        drive.save(&path).unwrap();
+        drive.clear();
+        assert_eq!(drive.metrics(), Metrics {
+            reads: 1, writes: 4, blocks_created: 2, blocks_deleted: 2, bytes_flushed: std::fs::metadata(&path).unwrap().len()
+        });
+        std::fs::remove_file(&path).unwrap();
+    }
+}
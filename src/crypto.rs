@@ -0,0 +1,69 @@
+//! Encrypted archives, for keeping drives private at rest.
+//! 
+//! An encrypted archive starts with the magic bytes `AVE` and a version byte (0), then a 24-byte random nonce, then a normal archive sealed with XChaCha20-Poly1305. The header and nonce are authenticated along with the ciphertext, so any tampering is caught.
+
+use std::fs::read;
+use std::io::Write;
+use std::path::Path;
+use chacha20poly1305::{XChaCha20Poly1305, XNonce, KeyInit, AeadCore, aead::{Aead, OsRng, Payload}};
+use crate::{Avd, AvdError, Result, atomic::write_atomic};
+
+const ENCRYPTED_HEADER: [u8; 4] = *b"AVE\x00";
+const NONCE_LEN: usize = 24;
+
+impl Avd {
+    /// Save the AVD to an encrypted archive, using a 256-bit key. The archive inside is saved in the drive's usual format.
+    pub fn save_encrypted(&self, path: impl AsRef<Path>, key: &[u8; 32]) -> Result<()> {
+        let mut plain = Vec::new();
+        self.save_to(&mut plain)?;
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let mut aad = ENCRYPTED_HEADER.to_vec();
+        aad.extend(nonce);
+        let sealed = XChaCha20Poly1305::new(key.into()).encrypt(&nonce, Payload {
+            msg: &plain, aad: &aad
+        }).map_err(|_| AvdError::Unsupported("archive too big to encrypt"))?;
+        aad.extend(sealed);
+        let len = write_atomic(path.as_ref(), self.backups, |w| Ok(w.write_all(&aad)?))?;
+        self.metrics.flushed(len);
+        Ok(())
+    }
+    /// Load an encrypted archive into the AVD. Like `load`, this overwrites the entire drive, but only once the archive has been decrypted and checked.
+    pub fn load_encrypted(&mut self, path: impl AsRef<Path>, key: &[u8; 32]) -> Result<()> {
+        let archive = read(path)?;
+        let header = archive.get(..4).ok_or(AvdError::MalformedArchive)?;
+        if header != ENCRYPTED_HEADER {
+            return Err(AvdError::BadHeader(header[0], header[1], header[2], header[3]))
+        }
+        let (aad, sealed) = archive.split_at_checked(4 + NONCE_LEN).ok_or(AvdError::MalformedArchive)?;
+        let nonce = XNonce::from_slice(&aad[4..]);
+        let plain = XChaCha20Poly1305::new(key.into()).decrypt(nonce, Payload {
+            msg: sealed, aad
+        }).map_err(|_| AvdError::DecryptionFailed)?;
+        self.load_from(&plain[..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn encrypted() {
+        let path = std::env::temp_dir().join(format!("avd-crypto-{}.avd", std::process::id()));
+        let key = [0x42; 32];
+        let mut drive = Avd::new();
+        drive.set_block(9, &[b'x'; 256]).unwrap();
+        drive.save_encrypted(&path, &key).unwrap();
+        let file = read(&path).unwrap();
+        assert_eq!(file[..4], *b"AVE\x00");
+        assert!(!file.windows(16).any(|w| w == [b'x'; 16]));
+
+        let mut loaded = Avd::new();
+        loaded.set_block(1, &[1; 256]).unwrap();
+        assert!(matches!(loaded.load_encrypted(&path, &[0; 32]), Err(AvdError::DecryptionFailed)));
+        assert_eq!(loaded.get_block(1), Some([1; 256])); // untouched after a failure
+        loaded.load_encrypted(&path, &key).unwrap();
+        assert_eq!(loaded, drive);
+        assert!(matches!(Avd::from_host_drive(&path), Err(AvdError::BadHeader(..))));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
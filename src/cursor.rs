@@ -0,0 +1,130 @@
+//! `std::io` adapters over the drive's flat byte address space.
+
+use std::borrow::{Borrow, BorrowMut};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use crate::{Avd, DRIVE_SIZE};
+
+/// A cursor over the drive's flat 16mb address space, implementing [`Read`], [`Write`] and [`Seek`]. Absent blocks read as zeros, and reads and writes stop at the end of the drive.
+/// 
+/// Works like [`std::io::Cursor`]: it can wrap anything that borrows as an [`Avd`], so it can own the drive or just borrow it.
+#[derive(Debug)]
+pub struct Cursor<A> {
+    inner: A,
+    pos: u64,
+}
+impl<A> Cursor<A> {
+    /// Create a new cursor at the start of the drive.
+    pub fn new(inner: A) -> Cursor<A> {
+        Cursor {
+            inner, pos: 0
+        }
+    }
+    /// The current byte address of the cursor.
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+    /// Move the cursor to a byte address. It's fine for this to be past the end of the drive.
+    pub fn set_position(&mut self, pos: u64) {
+        self.pos = pos
+    }
+    /// Get back the wrapped drive.
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+    /// How many bytes can be transferred at the current position, up to `len`.
+    fn available(&self, len: usize) -> usize {
+        (DRIVE_SIZE as u64).saturating_sub(self.pos).min(len as u64) as usize
+    }
+}
+impl<A: Borrow<Avd>> Read for Cursor<A> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.available(buf.len());
+        if n > 0 {
+            self.inner.borrow().read_bytes(self.pos as u32, &mut buf[..n]);
+            self.pos += n as u64;
+        }
+        Ok(n)
+    }
+}
+impl<A: BorrowMut<Avd>> Write for Cursor<A> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.available(buf.len());
+        if n > 0 {
+            self.inner.borrow_mut().write_bytes(self.pos as u32, &buf[..n]);
+            self.pos += n as u64;
+        }
+        Ok(n)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+impl<A> Seek for Cursor<A> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(v) => {
+                self.pos = v;
+                return Ok(v)
+            }
+            SeekFrom::End(v) => (DRIVE_SIZE as u64, v),
+            SeekFrom::Current(v) => (self.pos, v)
+        };
+        match base.checked_add_signed(offset) {
+            Some(v) => {
+                self.pos = v;
+                Ok(v)
+            }
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position"))
+        }
+    }
+}
+
+impl Avd {
+    /// Get a [`Read`] + [`Seek`] cursor over the drive, starting at byte address 0.
+    pub fn cursor(&self) -> Cursor<&Avd> {
+        Cursor::new(self)
+    }
+    /// Get a [`Read`] + [`Write`] + [`Seek`] cursor over the drive, starting at byte address 0.
+    pub fn cursor_mut(&mut self) -> Cursor<&mut Avd> {
+        Cursor::new(self)
+    }
+    /// Get a [`Read`] + [`Write`] + [`Seek`] cursor over the drive, starting at byte address `pos`.
+    pub fn cursor_at(&mut self, pos: u64) -> Cursor<&mut Avd> {
+        let mut c = Cursor::new(self);
+        c.set_position(pos);
+        c
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn read_seek() {
+        let mut drive = Avd::new();
+        drive.write_bytes(0x1234, b"kernel");
+        let mut c = drive.cursor();
+        c.seek(SeekFrom::Start(0x1234)).unwrap();
+        let mut buf = [0; 6];
+        c.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"kernel");
+        assert_eq!(c.seek(SeekFrom::End(-2)).unwrap(), DRIVE_SIZE as u64 - 2);
+        let mut rest = Vec::new();
+        c.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, [0, 0]);
+        assert!(c.seek(SeekFrom::Current(-(DRIVE_SIZE as i64) - 1)).is_err());
+    }
+    #[test]
+    fn write() {
+        let mut drive = Avd::new();
+        let image = vec![0x55; 1000];
+        io::copy(&mut &image[..], &mut drive.cursor_at(0x1000)).unwrap();
+        assert_eq!(drive.used_blocks(), 4);
+        assert_eq!(drive.read_u16_be(0x1000 + 998), 0x5555);
+        assert_eq!(drive.read_u16_be(0x1000 + 1000), 0);
+        let mut c = drive.cursor_mut();
+        c.seek(SeekFrom::End(-1)).unwrap();
+        assert_eq!(c.write(&[1, 2]).unwrap(), 1);
+        assert!(c.write_all(&[1]).is_err());
+    }
+}
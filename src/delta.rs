@@ -0,0 +1,234 @@
+//! Delta archives, holding only the blocks that differ between two drives.
+//! 
+//! A delta starts with the magic bytes `AVP` and a version byte (0). Then comes any number of entries, each a tag byte, a big-endian `u16` block index, and for tag 1 the 256 bytes of new data:
+//! 
+//! - tag 0: the block was removed.
+//! - tag 1: the block was added or changed.
+//! 
+//! The delta ends with a big-endian CRC32 of the entries.
+
+use core::cmp::Ordering;
+use core::iter::Peekable;
+use alloc::vec::Vec;
+use crate::{Avd, AvdError, Result, blocks_eq};
+
+const DELTA_HEADER: [u8; 4] = *b"AVP\x00";
+
+/// A change to one block: its new contents, or `None` if it was removed.
+pub(crate) type Change = (u16, Option<[u8; 256]>);
+
+/// One difference between two drives, from `Avd::diff`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(clippy::large_enum_variant)] // they're all block-sized, boxing would just get in the way
+pub enum BlockChange {
+    /// The block is only in the other drive.
+    Added { idx: u16, data: [u8; 256] },
+    /// The block is only in this drive.
+    Removed { idx: u16, old: [u8; 256] },
+    /// The block is in both, with different contents.
+    Modified { idx: u16, old: [u8; 256], new: [u8; 256] },
+}
+impl BlockChange {
+    /// The index of the block that changed.
+    pub fn idx(&self) -> u16 {
+        match self {
+            BlockChange::Added { idx, .. } | BlockChange::Removed { idx, .. } | BlockChange::Modified { idx, .. } => *idx
+        }
+    }
+}
+
+impl Avd {
+    /// Make a delta archive holding only the blocks that have to change to turn `base` into this drive. Apply it to a copy of `base` with `apply_delta`.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn diff_archive(&self, base: &Avd) -> Vec<u8> {
+        let mut ret = DELTA_HEADER.to_vec();
+        write_entries(&mut ret, self.changes_from(base));
+        let crc = crc32fast::hash(&ret[4..]);
+        ret.extend(crc.to_be_bytes());
+        ret
+    }
+    /// Apply a delta archive made by `diff_archive`. The whole delta is checked before anything is changed, so a bad delta leaves the drive alone.
+    /// 
+    /// Like `set_blocks`, fails with `AvdError::WriteProtected` if the drive is write protected, or `AvdError::BadSector` if the delta writes to a bad block.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(len = delta.len())))]
+    pub fn apply_delta(&mut self, delta: &[u8]) -> Result<()> {
+        let changes = parse_delta(delta)?;
+        if self.write_protected {
+            return Err(AvdError::WriteProtected)
+        }
+        for (idx, data) in &changes {
+            if data.is_some() {
+                self.check_bad(*idx)?;
+            }
+        }
+        for (idx, data) in changes {
+            match data {
+                Some(data) => self.store_block(idx, &data),
+                None => {
+                    self.delete_block(idx);
+                }
+            }
+        }
+        Ok(())
+    }
+    /// List what would have to change to turn this drive into `other`, in index order. This is what `diff_report` describes; the same changes can be turned into a delta archive with `other.diff_archive(self)`.
+    pub fn diff(&self, other: &Avd) -> Vec<BlockChange> {
+        other.changes_from(self).map(|(idx, new)| match (self.blocks.get(&idx), new) {
+            (None, Some(data)) => BlockChange::Added { idx, data: *data },
+            (Some(old), None) => BlockChange::Removed { idx, old: *old },
+            (Some(old), Some(new)) => BlockChange::Modified { idx, old: *old, new: *new },
+            (None, None) => unreachable!("blocks absent from both drives aren't changes")
+        }).collect()
+    }
+    /// Every block that differs between `base` and this drive, in index order, with this drive's data (or `None` if the block isn't here).
+    pub(crate) fn changes_from<'a>(&'a self, base: &'a Avd) -> impl Iterator<Item = (u16, Option<&'a [u8; 256]>)> + 'a {
+        Changes {
+            ours: self.blocks.iter().peekable(), base: base.blocks.iter().peekable()
+        }
+    }
+}
+
+/// Walks two drives' block maps side by side.
+struct Changes<I: Iterator> {
+    ours: Peekable<I>,
+    base: Peekable<I>,
+}
+impl<'a, I: Iterator<Item = (&'a u16, &'a [u8; 256])>> Iterator for Changes<I> {
+    type Item = (u16, Option<&'a [u8; 256]>);
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let ord = match (self.ours.peek(), self.base.peek()) {
+                (None, None) => return None,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((a, _)), Some((b, _))) => a.cmp(b)
+            };
+            match ord {
+                Ordering::Less => {
+                    let (idx, data) = self.ours.next()?;
+                    return Some((*idx, Some(data)))
+                }
+                Ordering::Greater => {
+                    let (idx, _) = self.base.next()?;
+                    return Some((*idx, None))
+                }
+                Ordering::Equal => {
+                    let (idx, a) = self.ours.next()?;
+                    let (_, b) = self.base.next()?;
+                    if !blocks_eq(a, b) {
+                        return Some((*idx, Some(a)))
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Write out delta entries for a list of changes.
+pub(crate) fn write_entries<'a>(ret: &mut Vec<u8>, changes: impl Iterator<Item = (u16, Option<&'a [u8; 256]>)>) {
+    for (idx, data) in changes {
+        match data {
+            Some(data) => {
+                ret.push(1);
+                ret.extend(idx.to_be_bytes());
+                ret.extend(data);
+            }
+            None => {
+                ret.push(0);
+                ret.extend(idx.to_be_bytes());
+            }
+        }
+    }
+}
+
+fn parse_delta(delta: &[u8]) -> Result<Vec<Change>> {
+    let header = delta.get(..4).ok_or(AvdError::MalformedArchive)?;
+    if header != DELTA_HEADER {
+        return Err(AvdError::BadHeader(header[0], header[1], header[2], header[3]))
+    }
+    let body = &delta[4..];
+    let split = body.len().checked_sub(4).ok_or(AvdError::MalformedArchive)?;
+    let (entries, crc) = body.split_at(split);
+    if crc32fast::hash(entries).to_be_bytes() != crc {
+        return Err(AvdError::BadArchiveChecksum)
+    }
+    parse_entries(entries)
+}
+
+/// Parse the entries written by `write_entries`.
+pub(crate) fn parse_entries(mut entries: &[u8]) -> Result<Vec<Change>> {
+    let mut ret = Vec::new();
+    while !entries.is_empty() {
+        let (tag, idx) = match entries {
+            [tag, a, b, ..] => (*tag, u16::from_be_bytes([*a, *b])),
+            _ => return Err(AvdError::MalformedArchive)
+        };
+        entries = &entries[3..];
+        match tag {
+            0 => ret.push((idx, None)),
+            1 => {
+                let data = entries.get(..256).ok_or(AvdError::MalformedArchive)?;
+                ret.push((idx, Some(data.try_into().unwrap()))); // SHOULD NEVER PANIC
+                entries = &entries[256..];
+            }
+            _ => return Err(AvdError::MalformedArchive)
+        }
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn delta() {
+        let make = || {
+            let mut avd = Avd::new();
+            for i in 0..100 {
+                avd.set_block(i, &[i as u8 + 1; 256]).unwrap();
+            }
+            avd
+        };
+        let base = make();
+        let mut new = make();
+        new.set_block(5, &[0xff; 256]).unwrap();
+        new.delete_block(6);
+        new.set_block(1000, &[1; 256]).unwrap();
+        let delta = new.diff_archive(&base);
+        assert_eq!(delta.len(), 4 + 259 + 3 + 259 + 4);
+        let mut patched = make();
+        patched.apply_delta(&delta).unwrap();
+        assert_eq!(patched, new);
+
+        let mut bad = delta.clone();
+        bad[10] ^= 1;
+        assert!(matches!(patched.apply_delta(&bad), Err(AvdError::BadArchiveChecksum)));
+        let mut protected = make();
+        protected.set_write_protected(true);
+        assert!(matches!(protected.apply_delta(&delta), Err(AvdError::WriteProtected)));
+        protected.set_write_protected(false);
+        protected.mark_bad(1000);
+        assert!(matches!(protected.apply_delta(&delta), Err(AvdError::BadSector(1000))));
+        assert_eq!(protected.get_block(5), Some([6; 256]));
+        assert_eq!(Avd::new().diff_archive(&Avd::new()), [b'A', b'V', b'P', 0, 0, 0, 0, 0]);
+    }
+    #[test]
+    fn diff() {
+        let mut a = Avd::new();
+        a.set_block(1, &[1; 256]).unwrap();
+        a.set_block(2, &[2; 256]).unwrap();
+        a.set_block(3, &[3; 256]).unwrap();
+        let mut b = Avd::new();
+        b.set_block(0, &[9; 256]).unwrap();
+        b.set_block(2, &[2; 256]).unwrap();
+        b.set_block(3, &[4; 256]).unwrap();
+        let changes = a.diff(&b);
+        assert_eq!(changes, [
+            BlockChange::Added { idx: 0, data: [9; 256] },
+            BlockChange::Removed { idx: 1, old: [1; 256] },
+            BlockChange::Modified { idx: 3, old: [3; 256], new: [4; 256] },
+        ]);
+        assert_eq!(changes.iter().map(BlockChange::idx).collect::<Vec<_>>(), [0, 1, 3]);
+        assert!(b.diff(&b).is_empty());
+    }
+}
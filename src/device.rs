@@ -0,0 +1,239 @@
+//! Traits for code that wants a block device, and doesn't care whether it's an [`Avd`] or something else.
+
+use alloc::boxed::Box;
+use core::future::{Future, ready};
+use crate::{Avd, OverlayAvd, Result};
+#[cfg(feature = "std")]
+use crate::{JournalAvd, LazyAvd, SharedAvd, ShardedAvd, WalAvd};
+#[cfg(feature = "mmap")]
+use crate::MmapAvd;
+
+/// A block device, as seen by an emulated disk controller. Code written against this works the same with an [`Avd`], any of the other drive types in this crate, or a mock.
+/// 
+/// Blocks that aren't present read as all zeros, as on a real drive.
+pub trait BlockDevice {
+    /// Read a block.
+    fn read_block(&mut self, idx: u16) -> Result<[u8; 256]>;
+    /// Write a block.
+    fn write_block(&mut self, idx: u16, data: &[u8; 256]) -> Result<()>;
+    /// Make sure everything written so far has been stored wherever the device keeps it.
+    fn flush(&mut self) -> Result<()>;
+}
+
+impl<D: BlockDevice + ?Sized> BlockDevice for &mut D {
+    fn read_block(&mut self, idx: u16) -> Result<[u8; 256]> {
+        (**self).read_block(idx)
+    }
+    fn write_block(&mut self, idx: u16, data: &[u8; 256]) -> Result<()> {
+        (**self).write_block(idx, data)
+    }
+    fn flush(&mut self) -> Result<()> {
+        (**self).flush()
+    }
+}
+impl<D: BlockDevice + ?Sized> BlockDevice for Box<D> {
+    fn read_block(&mut self, idx: u16) -> Result<[u8; 256]> {
+        (**self).read_block(idx)
+    }
+    fn write_block(&mut self, idx: u16, data: &[u8; 256]) -> Result<()> {
+        (**self).write_block(idx, data)
+    }
+    fn flush(&mut self) -> Result<()> {
+        (**self).flush()
+    }
+}
+
+/// The drive only lives in memory, so flushing does nothing. Save it to persist it.
+impl BlockDevice for Avd {
+    fn read_block(&mut self, idx: u16) -> Result<[u8; 256]> {
+        self.device_read(idx)
+    }
+    fn write_block(&mut self, idx: u16, data: &[u8; 256]) -> Result<()> {
+        self.set_block(idx, data)
+    }
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+#[cfg(feature = "std")]
+impl BlockDevice for SharedAvd {
+    fn read_block(&mut self, idx: u16) -> Result<[u8; 256]> {
+        self.read().device_read(idx)
+    }
+    fn write_block(&mut self, idx: u16, data: &[u8; 256]) -> Result<()> {
+        self.set_block(idx, data)
+    }
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+impl BlockDevice for OverlayAvd {
+    fn read_block(&mut self, idx: u16) -> Result<[u8; 256]> {
+        self.base().check_bad(idx)?;
+        Ok(self.get_block(idx).unwrap_or([0; 256]))
+    }
+    fn write_block(&mut self, idx: u16, data: &[u8; 256]) -> Result<()> {
+        self.set_block(idx, data)
+    }
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+#[cfg(feature = "std")]
+impl BlockDevice for ShardedAvd {
+    fn read_block(&mut self, idx: u16) -> Result<[u8; 256]> {
+        self.check_bad(idx)?;
+        Ok(self.get_block(idx).unwrap_or([0; 256]))
+    }
+    fn write_block(&mut self, idx: u16, data: &[u8; 256]) -> Result<()> {
+        self.set_block(idx, data)
+    }
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+/// Every write already goes to the file, so flushing syncs it to the disk.
+#[cfg(feature = "std")]
+impl BlockDevice for JournalAvd {
+    fn read_block(&mut self, idx: u16) -> Result<[u8; 256]> {
+        Ok(self.get_block(idx).unwrap_or([0; 256]))
+    }
+    fn write_block(&mut self, idx: u16, data: &[u8; 256]) -> Result<()> {
+        self.set_block(idx, data)
+    }
+    fn flush(&mut self) -> Result<()> {
+        self.sync()
+    }
+}
+/// Every write already goes to the log, so flushing syncs it to the disk. The archive is only written by `checkpoint`.
+#[cfg(feature = "std")]
+impl BlockDevice for WalAvd {
+    fn read_block(&mut self, idx: u16) -> Result<[u8; 256]> {
+        self.drive().check_bad(idx)?;
+        Ok(self.get_block(idx).unwrap_or([0; 256]))
+    }
+    fn write_block(&mut self, idx: u16, data: &[u8; 256]) -> Result<()> {
+        self.set_block(idx, data)
+    }
+    fn flush(&mut self) -> Result<()> {
+        self.sync()
+    }
+}
+/// The file is never written, so flushing does nothing.
+#[cfg(feature = "std")]
+impl BlockDevice for LazyAvd {
+    fn read_block(&mut self, idx: u16) -> Result<[u8; 256]> {
+        Ok(self.get_block(idx)?.unwrap_or([0; 256]))
+    }
+    fn write_block(&mut self, idx: u16, data: &[u8; 256]) -> Result<()> {
+        self.set_block(idx, data);
+        Ok(())
+    }
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+#[cfg(feature = "mmap")]
+impl BlockDevice for MmapAvd {
+    fn read_block(&mut self, idx: u16) -> Result<[u8; 256]> {
+        Ok(self.get_block(idx).unwrap_or([0; 256]))
+    }
+    fn write_block(&mut self, idx: u16, data: &[u8; 256]) -> Result<()> {
+        self.set_block(idx, data)
+    }
+    fn flush(&mut self) -> Result<()> {
+        MmapAvd::flush(self)
+    }
+}
+
+/// A block device that's accessed asynchronously, like a drive on a server. Lets async emulators treat local and remote drives the same way.
+/// 
+/// Blocks that aren't present read as all zeros, as on a real drive.
+pub trait AsyncBlockDevice {
+    /// Read a block.
+    fn read_block(&mut self, idx: u16) -> impl Future<Output = Result<[u8; 256]>> + Send;
+    /// Write a block.
+    fn write_block(&mut self, idx: u16, data: &[u8; 256]) -> impl Future<Output = Result<()>> + Send;
+    /// Make sure everything written so far has been stored wherever the device keeps it.
+    fn flush(&mut self) -> impl Future<Output = Result<()>> + Send;
+}
+
+impl AsyncBlockDevice for Avd {
+    fn read_block(&mut self, idx: u16) -> impl Future<Output = Result<[u8; 256]>> + Send {
+        ready(self.device_read(idx))
+    }
+    fn write_block(&mut self, idx: u16, data: &[u8; 256]) -> impl Future<Output = Result<()>> + Send {
+        ready(self.set_block(idx, data))
+    }
+    /// The drive only lives in memory, so there's nothing to flush. Save it to persist it.
+    fn flush(&mut self) -> impl Future<Output = Result<()>> + Send {
+        ready(Ok(()))
+    }
+}
+
+/// Each call takes the drive's lock just long enough to do its job, so other handles aren't held up while the future waits to be polled.
+#[cfg(feature = "std")]
+impl AsyncBlockDevice for SharedAvd {
+    fn read_block(&mut self, idx: u16) -> impl Future<Output = Result<[u8; 256]>> + Send {
+        ready(self.read().device_read(idx))
+    }
+    fn write_block(&mut self, idx: u16, data: &[u8; 256]) -> impl Future<Output = Result<()>> + Send {
+        ready(self.set_block(idx, data))
+    }
+    fn flush(&mut self) -> impl Future<Output = Result<()>> + Send {
+        ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    async fn copy(dev: &mut impl AsyncBlockDevice, src: u16, dst: u16) -> Result<()> {
+        let data = dev.read_block(src).await?;
+        dev.write_block(dst, &data).await?;
+        dev.flush().await
+    }
+    /// A controller that only knows about the trait.
+    fn boot(dev: &mut dyn BlockDevice) -> Result<u8> {
+        let boot = dev.read_block(0)?;
+        dev.write_block(1, &[boot[0] + 1; 256])?;
+        dev.flush()?;
+        Ok(boot[0])
+    }
+    #[test]
+    fn device() {
+        let mut drive = Avd::new();
+        drive.set_block(0, &[5; 256]).unwrap();
+        assert_eq!(boot(&mut drive).unwrap(), 5);
+        assert_eq!(drive.get_block(1), Some([6; 256]));
+        let mut boxed: Box<dyn BlockDevice> = Box::new(Avd::new());
+        assert_eq!(boot(&mut boxed).unwrap(), 0);
+        assert_eq!(BlockDevice::read_block(&mut boxed, 1).unwrap(), [1; 256]);
+
+        #[cfg(feature = "std")]
+        {
+            let path = std::env::temp_dir().join(format!("avd-device-{}.avd", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            let mut journal = Avd::open_journal(&path).unwrap();
+            BlockDevice::write_block(&mut journal, 0, &[9; 256]).unwrap();
+            assert_eq!(boot(&mut journal).unwrap(), 9);
+            assert_eq!(Avd::from_host_drive(&path).unwrap().get_block(1), Some([10; 256]));
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+    #[test]
+    fn async_device() {
+        let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let mut drive = Avd::new();
+        drive.set_block(1, &[1; 256]).unwrap();
+        rt.block_on(copy(&mut drive, 1, 2)).unwrap();
+        assert_eq!(drive.get_block(2), Some([1; 256]));
+        assert_eq!(rt.block_on(AsyncBlockDevice::read_block(&mut drive, 3)).unwrap(), [0; 256]);
+        #[cfg(feature = "std")]
+        {
+            let mut shared = drive.into_shared();
+            rt.block_on(copy(&mut shared, 2, 3)).unwrap();
+            assert_eq!(shared.get_block(3), Some([1; 256]));
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! Dirty-block tracking and incremental saving.
+
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::collections::{BTreeSet, HashMap};
+#[cfg(feature = "std")]
+use std::fs::OpenOptions;
+#[cfg(feature = "std")]
+use std::io::{Seek, SeekFrom, Write};
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "std")]
+use crate::{Result, archive::Format};
+use crate::{Avd, bitmap::Bitmap};
+
+/// What's changed since the drive was last saved or loaded.
+#[derive(Debug)]
+pub(crate) struct Tracking {
+    /// Blocks that have been written or removed.
+    pub dirty: Bitmap,
+    /// Where each block lives in the last file we saved or loaded, if we know.
+    #[cfg(feature = "std")]
+    pub layout: Option<Layout>,
+}
+
+/// Holds the tracking info inside a drive. `save` updates it through `&self`, so it needs a lock. Without std there's no `save`, so it can be stored as-is.
+#[cfg(feature = "std")]
+pub(crate) type TrackingCell = std::sync::Mutex<Tracking>;
+#[cfg(not(feature = "std"))]
+pub(crate) type TrackingCell = Tracking;
+
+/// Get at the tracking info while other fields of the drive are borrowed.
+pub(crate) fn tracking_mut(cell: &mut TrackingCell) -> &mut Tracking {
+    #[cfg(feature = "std")]
+    return cell.get_mut().unwrap_or_else(|e| e.into_inner());
+    #[cfg(not(feature = "std"))]
+    cell
+}
+
+impl Tracking {
+    pub fn new() -> TrackingCell {
+        let t = Tracking {
+            dirty: Bitmap::new(),
+            #[cfg(feature = "std")]
+            layout: None
+        };
+        #[cfg(feature = "std")]
+        return std::sync::Mutex::new(t);
+        #[cfg(not(feature = "std"))]
+        t
+    }
+    /// Forget about any changes, and remember that `path` holds `slots` in that order, saved in `format`.
+    #[cfg(feature = "std")]
+    pub fn reset(&mut self, path: &Path, format: &Format, slots: Vec<u16>) {
+        self.dirty.clear();
+        self.layout = Some(Layout {
+            path: path.to_owned(), format: format.clone(), slots
+        })
+    }
+}
+
+/// The order that blocks appear in an archive file.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub(crate) struct Layout {
+    path: PathBuf,
+    format: Format,
+    slots: Vec<u16>,
+}
+
+impl Avd {
+    /// Check if anything has changed since the drive was last saved or loaded.
+    pub fn is_dirty(&self) -> bool {
+        self.tracking_ref().dirty.count() != 0
+    }
+    /// The indices of blocks that have been written or removed since the drive was last saved or loaded, in order.
+    pub fn dirty_blocks(&self) -> Vec<u16> {
+        self.tracking_ref().dirty.iter().collect()
+    }
+    /// Save the AVD to a file, only rewriting the blocks that have changed.
+    /// 
+    /// This only works if the drive was last saved to or loaded from the same path, in the same format, and the archive isn't compressed and doesn't have a checksum trailer. Otherwise (or if the file looks like it's been changed by someone else) it falls back to a normal `save`. Blocks in a file saved this way may not be in index order. Unlike `save`, the file is patched in place, so a crash partway through can leave it broken.
+    #[cfg(feature = "std")]
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(path = %path.as_ref().display())))]
+    pub fn save_incremental(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let blocks = &self.blocks;
+        let format = &self.format;
+        let tracking = tracking_mut(&mut self.tracking);
+        let layout = match &mut tracking.layout {
+            Some(l) if l.path == path && l.format == *format && format.is_plain() && !format.has_checksum() => l,
+            _ => return self.save(path)
+        };
+        let header_len = format.header_len() as u64;
+        let record_len = format.record_len() as u64;
+        let mut f = match OpenOptions::new().write(true).open(path) {
+            Ok(f) => f,
+            Err(_) => return self.save(path)
+        };
+        if f.metadata()?.len() != header_len + record_len * layout.slots.len() as u64 {
+            drop(f);
+            return self.save(path)
+        }
+
+        let slot_of: HashMap<u16, usize> = layout.slots.iter().enumerate().map(|(s, i)| (*i, s)).collect();
+        let mut holes = BTreeSet::new();
+        let mut rewrite = BTreeSet::new();
+        let mut new = Vec::new();
+        for idx in tracking.dirty.iter() {
+            match (blocks.contains_key(&idx), slot_of.get(&idx)) {
+                (true, Some(_)) => {
+                    rewrite.insert(idx);
+                }
+                (true, None) => new.push(idx),
+                (false, Some(s)) => {
+                    holes.insert(*s);
+                }
+                (false, None) => {}
+            }
+        }
+        let slots = &mut layout.slots;
+        // new blocks go in the holes left by removed ones first
+        for idx in new {
+            match holes.pop_first() {
+                Some(s) => slots[s] = idx,
+                None => slots.push(idx)
+            }
+            rewrite.insert(idx);
+        }
+        // then fill any leftover holes with records from the end of the file, so it can be truncated
+        while let Some(&h) = holes.first() {
+            let last = slots.len() - 1;
+            if holes.remove(&last) {
+                slots.pop();
+                continue
+            }
+            holes.remove(&h);
+            slots[h] = slots.pop().unwrap(); // SHOULD NEVER PANIC
+            rewrite.insert(slots[h]);
+        }
+
+        f.set_len(header_len + record_len * slots.len() as u64)?;
+        let mut rec = vec![0; record_len as usize];
+        for (s, idx) in slots.iter().enumerate() {
+            if rewrite.contains(idx) {
+                format.write_record(&mut rec, *idx, &blocks[idx]);
+                f.seek(SeekFrom::Start(header_len + record_len * s as u64))?;
+                f.write_all(&rec)?;
+                self.metrics.flushed(record_len);
+            }
+        }
+        f.flush()?;
+        tracking.dirty.clear();
+        Ok(())
+    }
+    #[cfg(feature = "std")]
+    pub(crate) fn tracking_ref(&self) -> std::sync::MutexGuard<'_, Tracking> {
+        self.tracking.lock().unwrap_or_else(|e| e.into_inner())
+    }
+    #[cfg(not(feature = "std"))]
+    pub(crate) fn tracking_ref(&self) -> &Tracking {
+        &self.tracking
+    }
+    /// Mark a block as changed since the last save.
+    pub(crate) fn touch(&mut self, idx: u16) {
+        tracking_mut(&mut self.tracking).dirty.set(idx, true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn dirty_blocks() {
+        let mut drive = Avd::new();
+        drive.set_block(5, &[5; 256]).unwrap();
+        drive.set_block(2, &[2; 256]).unwrap();
+        drive.delete_block(2);
+        assert!(drive.is_dirty());
+        assert_eq!(drive.dirty_blocks(), [2, 5]);
+    }
+    #[cfg(feature = "std")]
+    #[test]
+    fn incremental() {
+        let path = std::env::temp_dir().join(format!("avd-incremental-{}.avd", std::process::id()));
+        let mut drive = Avd::new();
+        for i in 0..10 {
+            drive.set_block(i, &[i as u8 + 1; 256]).unwrap();
+        }
+        assert!(drive.is_dirty());
+        drive.save_incremental(&path).unwrap();
+        assert!(!drive.is_dirty());
+
+        drive.set_block(3, &[0xff; 256]).unwrap();
+        drive.delete_block(1);
+        drive.delete_block(9);
+        drive.delete_block(8);
+        drive.set_block(100, &[100; 256]).unwrap();
+        drive.delete_block(4);
+        assert_eq!(drive.dirty_blocks(), [1, 3, 4, 8, 9, 100]);
+        drive.save_incremental(&path).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 4 + 258 * 7);
+        assert_eq!(Avd::from_host_drive(&path).unwrap(), drive);
+
+        drive.clear();
+        drive.save_incremental(&path).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 4);
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,85 @@
+//! Bulk transfers for emulated DMA controllers.
+
+use crate::{Access, Avd, AvdError, BLOCK_COUNT, BLOCK_SIZE, Result};
+
+impl Avd {
+    /// Read `count` blocks starting at `start_block` straight into `buf`, returning how many blocks were read. Absent blocks read as zeros.
+    /// 
+    /// Like a real controller, the transfer stops at the end of the drive, so fewer than `count` blocks can come back; the rest of `buf` is left alone. If any block in the transfer is bad, it fails with `AvdError::BadSector` and nothing is read.
+    /// 
+    /// Panics if `buf` is shorter than `count` blocks.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(start = start_block, count = count)))]
+    pub fn dma_read(&self, start_block: u16, count: usize, buf: &mut [u8]) -> Result<usize> {
+        assert!(buf.len() >= count * BLOCK_SIZE, "DMA buffer too small for {count} blocks");
+        let count = count.min(BLOCK_COUNT - start_block as usize);
+        if count == 0 {
+            return Ok(0)
+        }
+        let last = (start_block as usize + count - 1) as u16;
+        if let Some(idx) = self.format.bad.range(start_block..=last).next() {
+            return Err(AvdError::BadSector(*idx))
+        }
+        let buf = &mut buf[..count * BLOCK_SIZE];
+        buf.fill(0);
+        for idx in start_block..=last {
+            self.notify(idx, Access::Read)
+        }
+        for (idx, data) in self.blocks.range(start_block..=last) {
+            let start = (idx - start_block) as usize * BLOCK_SIZE;
+            buf[start..start + BLOCK_SIZE].copy_from_slice(data)
+        }
+        Ok(count)
+    }
+    /// Write whole blocks from `data` starting at `start_block`, returning how many blocks were written. All-zero blocks are removed, as with `set_block`.
+    /// 
+    /// The transfer stops at the end of the drive. If the drive is write protected or any block in the transfer is bad, it fails and nothing is written.
+    /// 
+    /// Panics if `data` isn't a whole number of blocks.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(start = start_block, len = data.len())))]
+    pub fn dma_write(&mut self, start_block: u16, data: &[u8]) -> Result<usize> {
+        assert!(data.len().is_multiple_of(BLOCK_SIZE), "DMA transfers have to be whole blocks");
+        let count = (data.len() / BLOCK_SIZE).min(BLOCK_COUNT - start_block as usize);
+        if self.write_protected {
+            return Err(AvdError::WriteProtected)
+        }
+        if count == 0 {
+            return Ok(0)
+        }
+        let last = (start_block as usize + count - 1) as u16;
+        if let Some(idx) = self.format.bad.range(start_block..=last).next() {
+            return Err(AvdError::BadSector(*idx))
+        }
+        for (i, block) in data.chunks_exact(BLOCK_SIZE).take(count).enumerate() {
+            self.store_block(start_block + i as u16, block.try_into().unwrap()) // SHOULD NEVER PANIC
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn dma() {
+        let mut drive = Avd::new();
+        let mut data = vec![0; 3 * BLOCK_SIZE];
+        data[..BLOCK_SIZE].fill(1);
+        data[2 * BLOCK_SIZE..].fill(3);
+        assert_eq!(drive.dma_write(10, &data).unwrap(), 3);
+        assert_eq!(drive.used_blocks(), 2);
+        let mut buf = vec![0xff; 4 * BLOCK_SIZE];
+        assert_eq!(drive.dma_read(9, 4, &mut buf).unwrap(), 4);
+        assert_eq!(&buf[BLOCK_SIZE..], &data[..]);
+        assert!(buf[..BLOCK_SIZE].iter().all(|b| *b == 0));
+
+        // off the end of the drive
+        assert_eq!(drive.dma_write(65535, &data).unwrap(), 1);
+        assert_eq!(drive.dma_read(65534, 4, &mut buf).unwrap(), 2);
+        assert_eq!(buf[BLOCK_SIZE], 1);
+
+        drive.mark_bad(11);
+        assert!(matches!(drive.dma_read(8, 4, &mut buf), Err(AvdError::BadSector(11))));
+        assert!(matches!(drive.dma_write(11, &data), Err(AvdError::BadSector(11))));
+        assert_eq!(drive.get_block(12), Some([3; 256]));
+    }
+}
@@ -0,0 +1,112 @@
+//! A drive with removable media.
+
+use crate::{Avd, AvdError, BlockDevice, Result};
+
+/// A drive that disks can be put into and taken out of at runtime, as the AVC2 allows. Each disk is an [`Avd`].
+/// 
+/// Reads and writes go to whatever disk is inserted, and fail with `AvdError::NoMedia` if there isn't one. Swapping disks sets a "media changed" flag, like the disk change line on a real controller, so guest drivers can notice.
+#[derive(Debug, Default)]
+pub struct AvdDrive {
+    media: Option<Avd>,
+    changed: bool,
+}
+impl AvdDrive {
+    /// Make an empty drive.
+    pub fn new() -> AvdDrive {
+        AvdDrive::default()
+    }
+    /// Make a drive with a disk already in it. The media changed flag starts off clear.
+    pub fn with_media(disk: Avd) -> AvdDrive {
+        AvdDrive {
+            media: Some(disk), changed: false
+        }
+    }
+    /// Put a disk in the drive. If there was one in already, it's ejected and handed back.
+    pub fn insert(&mut self, disk: Avd) -> Option<Avd> {
+        self.changed = true;
+        self.media.replace(disk)
+    }
+    /// Take the disk out of the drive. Fails with `AvdError::NoMedia` if it's empty.
+    pub fn eject(&mut self) -> Result<Avd> {
+        let disk = self.media.take().ok_or(AvdError::NoMedia)?;
+        self.changed = true;
+        Ok(disk)
+    }
+    /// Whether there's a disk in the drive.
+    pub fn has_media(&self) -> bool {
+        self.media.is_some()
+    }
+    /// The disk in the drive, if there is one.
+    pub fn media(&self) -> Option<&Avd> {
+        self.media.as_ref()
+    }
+    /// The disk in the drive, if there is one, for changing settings or host-side edits.
+    pub fn media_mut(&mut self) -> Option<&mut Avd> {
+        self.media.as_mut()
+    }
+    /// Whether a disk has been inserted or ejected since the flag was last cleared with `take_media_changed`.
+    pub fn media_changed(&self) -> bool {
+        self.changed
+    }
+    /// Whether a disk has been inserted or ejected since this was last called. Calling it clears the flag.
+    pub fn take_media_changed(&mut self) -> bool {
+        core::mem::take(&mut self.changed)
+    }
+    /// Read a block from the disk, as the device sees it: absent blocks are zeros, and bad blocks are errors.
+    pub fn read_block(&self, idx: u16) -> Result<[u8; 256]> {
+        self.media.as_ref().ok_or(AvdError::NoMedia)?.device_read(idx)
+    }
+    /// Write a block to the disk. See `Avd::set_block`.
+    pub fn write_block(&mut self, idx: u16, data: &[u8; 256]) -> Result<()> {
+        self.media.as_mut().ok_or(AvdError::NoMedia)?.set_block(idx, data)
+    }
+    /// Bulk read from the disk. See `Avd::dma_read`.
+    pub fn dma_read(&self, start_block: u16, count: usize, buf: &mut [u8]) -> Result<usize> {
+        self.media.as_ref().ok_or(AvdError::NoMedia)?.dma_read(start_block, count, buf)
+    }
+    /// Bulk write to the disk. See `Avd::dma_write`.
+    pub fn dma_write(&mut self, start_block: u16, data: &[u8]) -> Result<usize> {
+        self.media.as_mut().ok_or(AvdError::NoMedia)?.dma_write(start_block, data)
+    }
+}
+/// Flushing an empty drive does nothing.
+impl BlockDevice for AvdDrive {
+    fn read_block(&mut self, idx: u16) -> Result<[u8; 256]> {
+        AvdDrive::read_block(self, idx)
+    }
+    fn write_block(&mut self, idx: u16, data: &[u8; 256]) -> Result<()> {
+        AvdDrive::write_block(self, idx, data)
+    }
+    fn flush(&mut self) -> Result<()> {
+        match &mut self.media {
+            Some(disk) => disk.flush(),
+            None => Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn removable() {
+        let mut drive = AvdDrive::new();
+        assert!(matches!(drive.read_block(0), Err(AvdError::NoMedia)));
+        assert!(matches!(drive.write_block(0, &[1; 256]), Err(AvdError::NoMedia)));
+        assert!(matches!(drive.eject(), Err(AvdError::NoMedia)));
+        assert!(!drive.take_media_changed());
+
+        let mut disk = Avd::new();
+        disk.set_block(0, &[1; 256]).unwrap();
+        assert!(drive.insert(disk).is_none());
+        assert!(drive.take_media_changed());
+        assert!(!drive.take_media_changed());
+        assert_eq!(drive.read_block(0).unwrap(), [1; 256]);
+        drive.write_block(1, &[2; 256]).unwrap();
+        let old = drive.insert(Avd::new()).unwrap();
+        assert_eq!(old.get_block(1), Some([2; 256]));
+        assert_eq!(drive.read_block(0).unwrap(), [0; 256]);
+        assert_eq!(drive.eject().unwrap().used_blocks(), 0);
+        assert!(!drive.has_media());
+    }
+}
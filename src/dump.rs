@@ -0,0 +1,144 @@
+//! Human-readable views of the drive, for debugging.
+
+use core::fmt::Write;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::{Avd, BlockChange, BLOCK_COUNT, BLOCK_SIZE};
+
+const LINE_LEN: usize = 16;
+
+impl Avd {
+    /// Format a block as a classic hex dump: byte address, 16 bytes in hex, and the same bytes as ASCII, with anything unprintable shown as `.`. Absent blocks dump as zeros.
+    pub fn hexdump_block(&self, idx: u16) -> String {
+        self.hexdump_range(idx as u32 * BLOCK_SIZE as u32, BLOCK_SIZE)
+    }
+    /// Format `len` bytes starting at the byte address `addr` as a hex dump, like `hexdump_block`. Lines are numbered by their real address, and the last one may be short.
+    /// 
+    /// Panics if the range goes past the end of the drive.
+    pub fn hexdump_range(&self, addr: u32, len: usize) -> String {
+        let mut buf = vec![0; len];
+        self.read_bytes(addr, &mut buf);
+        let mut out = String::new();
+        for (i, line) in buf.chunks(LINE_LEN).enumerate() {
+            write!(out, "{:06x} ", addr as usize + i * LINE_LEN).unwrap(); // SHOULD NEVER PANIC
+            for j in 0..LINE_LEN {
+                if j == LINE_LEN / 2 {
+                    out.push(' ')
+                }
+                match line.get(j) {
+                    Some(b) => write!(out, " {:02x}", b).unwrap(), // SHOULD NEVER PANIC
+                    None => out.push_str("   ")
+                }
+            }
+            out.push_str("  |");
+            out.extend(line.iter().map(|b| if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' }));
+            out.push_str("|\n");
+        }
+        out
+    }
+    /// Count the blocks in use in each group of `group` consecutive blocks, in index order. With `group` = 1 this is a 0-or-1 bitmap of the whole drive, which lays out nicely as 256 rows of 256.
+    /// 
+    /// Panics if `group` is 0.
+    pub fn usage_map(&self, group: usize) -> Vec<usize> {
+        assert!(group != 0, "usage map groups can't be empty");
+        let mut counts = vec![0; BLOCK_COUNT.div_ceil(group)];
+        for idx in self.blocks.keys() {
+            counts[*idx as usize / group] += 1
+        }
+        counts
+    }
+    /// Draw `usage_map` as text, like a defragmenter's screen: one character per group of `group` blocks, `.` for empty, `o` for partly used and `#` for full, with a line break every `width` characters.
+    /// 
+    /// Panics if `group` or `width` is 0.
+    pub fn usage_string(&self, group: usize, width: usize) -> String {
+        assert!(width != 0, "usage map lines can't be empty");
+        let mut out = String::new();
+        for (i, n) in self.usage_map(group).into_iter().enumerate() {
+            // the last group can be short
+            let size = group.min(BLOCK_COUNT - i * group);
+            out.push(match n {
+                0 => '.',
+                n if n == size => '#',
+                _ => 'o'
+            });
+            if (i + 1) % width == 0 {
+                out.push('\n')
+            }
+        }
+        if !out.ends_with('\n') {
+            out.push('\n')
+        }
+        out
+    }
+    /// Describe what changed between this drive and `other`, one block per line, in index order, followed by a summary line. Blocks are `added` if they're only in `other`, `removed` if they're only here, and `changed` if their contents differ.
+    /// 
+    /// If `bytes` is set, each changed block is followed by the bytes that differ, as `offset: old -> new`.
+    pub fn diff_report(&self, other: &Avd, bytes: bool) -> String {
+        let mut out = String::new();
+        let (mut added, mut removed, mut changed) = (0, 0, 0);
+        for change in self.diff(other) {
+            match change {
+                BlockChange::Added { idx, .. } => {
+                    added += 1;
+                    writeln!(out, "added   {:04x}", idx).unwrap() // SHOULD NEVER PANIC
+                }
+                BlockChange::Removed { idx, .. } => {
+                    removed += 1;
+                    writeln!(out, "removed {:04x}", idx).unwrap() // SHOULD NEVER PANIC
+                }
+                BlockChange::Modified { idx, old, new } => {
+                    changed += 1;
+                    let diffs = old.iter().zip(&new).filter(|(a, b)| a != b).count();
+                    writeln!(out, "changed {:04x} ({} bytes)", idx, diffs).unwrap(); // SHOULD NEVER PANIC
+                    if bytes {
+                        for (i, (a, b)) in old.iter().zip(&new).enumerate().filter(|(_, (a, b))| a != b) {
+                            writeln!(out, "    {:02x}: {:02x} -> {:02x}", i, a, b).unwrap() // SHOULD NEVER PANIC
+                        }
+                    }
+                }
+            }
+        }
+        writeln!(out, "{} added, {} removed, {} changed", added, removed, changed).unwrap(); // SHOULD NEVER PANIC
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn hexdump() {
+        let mut drive = Avd::new();
+        drive.write_bytes(0x110, b"Hello, world!\n\0\xff");
+        let dump = drive.hexdump_block(1);
+        assert_eq!(dump.lines().count(), 16);
+        assert_eq!(dump.lines().nth(1).unwrap(), "000110  48 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 0a 00 ff  |Hello, world!...|");
+        assert_eq!(drive.hexdump_range(0x112, 3), "000112  6c 6c 6f                                          |llo|\n");
+    }
+    #[test]
+    fn usage() {
+        let mut drive = Avd::new();
+        drive.fill_blocks(0..4, 1);
+        drive.set_block(5, &[1; 256]).unwrap();
+        drive.set_block(65535, &[1; 256]).unwrap();
+        let map = drive.usage_map(4);
+        assert_eq!((map.len(), map[0], map[1], map[16383]), (16384, 4, 1, 1));
+        assert_eq!(drive.usage_map(1).iter().sum::<usize>(), 6);
+        let s = drive.usage_string(4096, 8);
+        assert_eq!(s, "o.......\n.......o\n");
+        assert_eq!(drive.usage_string(65535, 8), "o#\n");
+    }
+    #[test]
+    fn diff_report() {
+        let mut a = Avd::new();
+        a.set_block(1, &[1; 256]).unwrap();
+        a.set_block(2, &[2; 256]).unwrap();
+        let mut b = Avd::new();
+        b.set_block(2, &[2; 256]).unwrap();
+        b.write_bytes(0x200, &[9, 9]);
+        b.set_block(0x300, &[3; 256]).unwrap();
+        assert_eq!(a.diff_report(&b, true), "removed 0001\nchanged 0002 (2 bytes)\n    00: 02 -> 09\n    01: 02 -> 09\nadded   0300\n1 added, 1 removed, 1 changed\n");
+        assert_eq!(a.diff_report(&a, false), "0 added, 0 removed, 0 changed\n");
+    }
+}
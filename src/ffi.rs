@@ -0,0 +1,223 @@
+//! A C interface, for emulators that aren't written in Rust. The matching header is `include/avd.h`.
+//! 
+//! Build a shared library with `cargo rustc --release --features ffi --crate-type cdylib`.
+//! 
+//! Functions that can fail return one of the `AVD_*` codes, which are 0 for success and negative for errors.
+
+use std::ffi::{c_char, c_int, CStr};
+use std::ptr;
+use crate::{Avd, AvdError};
+
+pub const AVD_OK: c_int = 0;
+/// A pointer was null, or a path wasn't valid UTF-8.
+pub const AVD_ERR_ARG: c_int = -1;
+pub const AVD_ERR_IO: c_int = -2;
+/// The file isn't an archive, or is damaged.
+pub const AVD_ERR_FORMAT: c_int = -3;
+pub const AVD_ERR_CHECKSUM: c_int = -4;
+pub const AVD_ERR_UNSUPPORTED: c_int = -5;
+pub const AVD_ERR_OTHER: c_int = -6;
+pub const AVD_ERR_WRITE_PROTECTED: c_int = -7;
+pub const AVD_ERR_BAD_SECTOR: c_int = -8;
+
+fn error_code(e: &AvdError) -> c_int {
+    match e {
+        AvdError::FsError(_) => AVD_ERR_IO,
+        AvdError::BadHeader(..) | AvdError::MalformedArchive | AvdError::UnsupportedVersion(_)
+            | AvdError::UnknownFlags(_) | AvdError::UnknownChunk(_) | AvdError::BadText(..) => AVD_ERR_FORMAT,
+        AvdError::BadBlockChecksum(_) | AvdError::BadArchiveChecksum => AVD_ERR_CHECKSUM,
+        AvdError::Unsupported(_) => AVD_ERR_UNSUPPORTED,
+        AvdError::WriteProtected => AVD_ERR_WRITE_PROTECTED,
+        AvdError::BadSector(_) => AVD_ERR_BAD_SECTOR,
+        _ => AVD_ERR_OTHER
+    }
+}
+
+unsafe fn path<'a>(p: *const c_char) -> Option<&'a str> {
+    if p.is_null() {
+        return None
+    }
+    CStr::from_ptr(p).to_str().ok()
+}
+
+/// Create a new, blank drive. Free it with `avd_free`.
+#[no_mangle]
+pub extern "C" fn avd_new() -> *mut Avd {
+    Box::into_raw(Box::new(Avd::new()))
+}
+/// Free a drive made by `avd_new` or `avd_load`.
+/// 
+/// # Safety
+/// `avd` must be null or a drive from this library that hasn't been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn avd_free(avd: *mut Avd) {
+    if !avd.is_null() {
+        drop(Box::from_raw(avd))
+    }
+}
+/// Load an archive file into a new drive, storing it in `*out`. `*out` is set to null on failure.
+/// 
+/// # Safety
+/// `path` must be null or a nul-terminated string, and `out` must be null or valid to write a pointer to.
+#[no_mangle]
+pub unsafe extern "C" fn avd_load(path_ptr: *const c_char, out: *mut *mut Avd) -> c_int {
+    if out.is_null() {
+        return AVD_ERR_ARG
+    }
+    *out = ptr::null_mut();
+    let Some(p) = path(path_ptr) else {
+        return AVD_ERR_ARG
+    };
+    match Avd::from_host_drive(p) {
+        Ok(d) => {
+            *out = Box::into_raw(Box::new(d));
+            AVD_OK
+        }
+        Err(e) => error_code(&e)
+    }
+}
+/// Save a drive to an archive file.
+/// 
+/// # Safety
+/// `avd` must be null or a live drive, and `path` must be null or a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn avd_save(avd: *const Avd, path_ptr: *const c_char) -> c_int {
+    let (Some(avd), Some(p)) = (avd.as_ref(), path(path_ptr)) else {
+        return AVD_ERR_ARG
+    };
+    match avd.save(p) {
+        Ok(()) => AVD_OK,
+        Err(e) => error_code(&e)
+    }
+}
+/// Copy block `idx` into the 256 bytes at `out`. Returns 1 if the block is present, or 0 if it's absent (and `out` is filled with zeros).
+/// 
+/// # Safety
+/// `avd` must be null or a live drive, and `out` must be null or valid to write 256 bytes to.
+#[no_mangle]
+pub unsafe extern "C" fn avd_get_block(avd: *const Avd, idx: u16, out: *mut u8) -> c_int {
+    let Some(avd) = avd.as_ref() else {
+        return AVD_ERR_ARG
+    };
+    if out.is_null() {
+        return AVD_ERR_ARG
+    }
+    let out = &mut *(out as *mut [u8; 256]);
+    match avd.get_block_ref(idx) {
+        Some(data) => {
+            *out = *data;
+            1
+        }
+        None => {
+            *out = [0; 256];
+            0
+        }
+    }
+}
+/// Set block `idx` from the 256 bytes at `data`.
+/// 
+/// # Safety
+/// `avd` must be null or a live drive, and `data` must be null or valid to read 256 bytes from.
+#[no_mangle]
+pub unsafe extern "C" fn avd_set_block(avd: *mut Avd, idx: u16, data: *const u8) -> c_int {
+    let Some(avd) = avd.as_mut() else {
+        return AVD_ERR_ARG
+    };
+    if data.is_null() {
+        return AVD_ERR_ARG
+    }
+    match avd.set_block(idx, &*(data as *const [u8; 256])) {
+        Ok(()) => AVD_OK,
+        Err(e) => error_code(&e)
+    }
+}
+/// Remove block `idx` from the drive.
+/// 
+/// # Safety
+/// `avd` must be null or a live drive.
+#[no_mangle]
+pub unsafe extern "C" fn avd_delete_block(avd: *mut Avd, idx: u16) -> c_int {
+    let Some(avd) = avd.as_mut() else {
+        return AVD_ERR_ARG
+    };
+    avd.delete_block(idx);
+    AVD_OK
+}
+/// The number of blocks stored on the drive, or 0 if `avd` is null.
+/// 
+/// # Safety
+/// `avd` must be null or a live drive.
+#[no_mangle]
+pub unsafe extern "C" fn avd_used_blocks(avd: *const Avd) -> usize {
+    avd.as_ref().map_or(0, Avd::used_blocks)
+}
+/// A short description of an error code. The string is static and mustn't be freed.
+#[no_mangle]
+pub extern "C" fn avd_error_string(code: c_int) -> *const c_char {
+    let s: &'static CStr = match code {
+        AVD_OK => c"no error",
+        AVD_ERR_ARG => c"invalid argument",
+        AVD_ERR_IO => c"host fs error",
+        AVD_ERR_FORMAT => c"not a valid archive",
+        AVD_ERR_CHECKSUM => c"checksum mismatch",
+        AVD_ERR_UNSUPPORTED => c"not supported for this archive",
+        AVD_ERR_OTHER => c"other error",
+        AVD_ERR_WRITE_PROTECTED => c"drive is write protected",
+        AVD_ERR_BAD_SECTOR => c"block is marked bad",
+        _ => c"unknown error"
+    };
+    s.as_ptr()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+    #[test]
+    fn ffi() {
+        let path = std::env::temp_dir().join(format!("avd-ffi-{}.avd", std::process::id()));
+        let cpath = CString::new(path.to_str().unwrap()).unwrap();
+        unsafe {
+            let d = avd_new();
+            assert_eq!(avd_set_block(d, 7, [7u8; 256].as_ptr()), AVD_OK);
+            assert_eq!(avd_save(d, cpath.as_ptr()), AVD_OK);
+            avd_free(d);
+
+            let mut loaded = ptr::null_mut();
+            assert_eq!(avd_load(cpath.as_ptr(), &mut loaded), AVD_OK);
+            let mut buf = [1u8; 256];
+            assert_eq!(avd_get_block(loaded, 7, buf.as_mut_ptr()), 1);
+            assert_eq!(buf, [7; 256]);
+            assert_eq!(avd_get_block(loaded, 8, buf.as_mut_ptr()), 0);
+            assert_eq!(buf, [0; 256]);
+            assert_eq!(avd_used_blocks(loaded), 1);
+            avd_free(loaded);
+
+            let missing = CString::new("/nonexistent/avd.avd").unwrap();
+            assert_eq!(avd_load(missing.as_ptr(), &mut loaded), AVD_ERR_IO);
+            assert!(loaded.is_null());
+            assert_eq!(avd_set_block(ptr::null_mut(), 0, buf.as_ptr()), AVD_ERR_ARG);
+            assert_eq!(CStr::from_ptr(avd_error_string(AVD_ERR_CHECKSUM)).to_str().unwrap(), "checksum mismatch");
+            assert_eq!(CStr::from_ptr(avd_error_string(AVD_ERR_WRITE_PROTECTED)).to_str().unwrap(), "drive is write protected");
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+    #[test]
+    fn header_in_sync() {
+        let header = include_str!("../include/avd.h");
+        let src = include_str!("ffi.rs");
+        for line in src.lines() {
+            if let Some(rest) = line.split("extern \"C\" fn ").nth(1) {
+                let name = rest.split('(').next().unwrap();
+                assert!(header.contains(&format!("{}(", name)), "{} missing from avd.h", name);
+            }
+            if let Some(rest) = line.strip_prefix("pub const ") {
+                let name = rest.split(':').next().unwrap();
+                assert!(header.contains(name), "{} missing from avd.h", name);
+                let code: c_int = rest.split("= ").nth(1).unwrap().trim_end_matches(';').parse().unwrap();
+                let s = unsafe { CStr::from_ptr(avd_error_string(code)) };
+                assert_ne!(s.to_str().unwrap(), "unknown error", "{} has no string", name);
+            }
+        }
+    }
+}
@@ -0,0 +1,86 @@
+//! Read-only drives that can be shared freely between threads.
+
+use core::ops::Index;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use crate::Avd;
+
+/// An immutable copy of a drive, made with `Avd::freeze`.
+/// 
+/// Blocks are kept in a sorted slice, so lookups are a binary search with no pointer chasing. There's no way to change a frozen drive, so it's `Send + Sync` and can be shared between threads (in an `Arc`, say) without any locking.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrozenAvd {
+    indices: Box<[u16]>,
+    data: Box<[[u8; 256]]>,
+}
+impl FrozenAvd {
+    /// Get a block from the drive.
+    pub fn get_block(&self, idx: u16) -> Option<[u8; 256]> {
+        self.get_block_ref(idx).copied()
+    }
+    /// Get a reference to a block on the drive, without copying it.
+    pub fn get_block_ref(&self, idx: u16) -> Option<&[u8; 256]> {
+        self.indices.binary_search(&idx).ok().map(|i| &self.data[i])
+    }
+    /// Check if a block is present.
+    pub fn is_block_used(&self, idx: u16) -> bool {
+        self.indices.binary_search(&idx).is_ok()
+    }
+    /// The number of blocks stored on the drive.
+    pub fn used_blocks(&self) -> usize {
+        self.indices.len()
+    }
+    /// Iterate over all the blocks stored on the drive, in index order.
+    pub fn blocks(&self) -> impl Iterator<Item = (u16, &[u8; 256])> {
+        self.indices.iter().copied().zip(self.data.iter())
+    }
+    /// Make a normal, mutable AVD with the same blocks.
+    pub fn thaw(&self) -> Avd {
+        let mut d = Avd::new();
+        for (idx, data) in self.blocks() {
+            d.insert(idx, *data)
+        }
+        d
+    }
+    pub(crate) fn from_map(blocks: &BTreeMap<u16, [u8; 256]>) -> FrozenAvd {
+        FrozenAvd {
+            indices: blocks.keys().copied().collect(), data: blocks.values().copied().collect()
+        }
+    }
+}
+/// Index the drive by block. Absent blocks read as all zeros.
+impl Index<u16> for FrozenAvd {
+    type Output = [u8; 256];
+    fn index(&self, idx: u16) -> &[u8; 256] {
+        static ZERO: [u8; 256] = [0; 256];
+        self.get_block_ref(idx).unwrap_or(&ZERO)
+    }
+}
+
+impl Avd {
+    /// Make an immutable copy of the drive that can be shared between threads without locking. See [`FrozenAvd`].
+    pub fn freeze(&self) -> FrozenAvd {
+        FrozenAvd::from_map(&self.blocks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn frozen() {
+        fn shareable<T: Send + Sync>(_: &T) {}
+        let mut drive = Avd::new();
+        for i in 0..100 {
+            drive.set_block(i * 11, &[i as u8 + 1; 256]).unwrap();
+        }
+        let frozen = drive.freeze();
+        shareable(&frozen);
+        assert_eq!(frozen.used_blocks(), 100);
+        assert_eq!(frozen.get_block(22), Some([3; 256]));
+        assert_eq!(frozen.get_block(23), None);
+        assert_eq!(frozen[23], [0; 256]);
+        assert!(frozen.blocks().map(|(i, _)| i).eq(drive.blocks().map(|(i, _)| i)));
+        assert_eq!(frozen.thaw(), drive);
+    }
+}
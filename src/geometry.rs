@@ -0,0 +1,119 @@
+//! Cylinder/head/sector addressing, for firmware that doesn't use flat block numbers.
+
+use crate::{AvdError, BLOCK_COUNT, Result};
+
+/// A cylinder/head/sector address. See [`Geometry`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Chs {
+    pub cylinder: u16,
+    pub head: u16,
+    pub sector: u16,
+}
+
+/// A disk geometry, for translating between CHS addresses and block indices.
+/// 
+/// Blocks are numbered sector by sector, then head by head, then cylinder by cylinder, the usual way. Cylinders and heads count from 0. Sectors count from 1 by convention, but that can be changed with `with_first_sector`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Geometry {
+    cylinders: u16,
+    heads: u16,
+    sectors: u16,
+    first_sector: u16,
+}
+impl Geometry {
+    /// Make a geometry. Fails with `AvdError::InvalidGeometry` if any of the counts is 0, or the disk would have more blocks than the drive. It can have fewer; the blocks past the end are just unreachable by CHS.
+    pub fn new(cylinders: u16, heads: u16, sectors: u16) -> Result<Geometry> {
+        let g = Geometry {
+            cylinders, heads, sectors, first_sector: 1
+        };
+        if cylinders == 0 || heads == 0 || sectors == 0 || g.blocks() > BLOCK_COUNT {
+            return Err(AvdError::InvalidGeometry)
+        }
+        Ok(g)
+    }
+    /// Number sectors from `first` instead of 1. Fails with `AvdError::InvalidGeometry` if the last sector's number wouldn't fit in a `u16`.
+    pub fn with_first_sector(self, first: u16) -> Result<Geometry> {
+        if first as usize + self.sectors as usize - 1 > u16::MAX as usize {
+            return Err(AvdError::InvalidGeometry)
+        }
+        Ok(Geometry {
+            first_sector: first, ..self
+        })
+    }
+    /// The number of blocks the geometry covers.
+    pub fn blocks(&self) -> usize {
+        self.cylinders as usize * self.heads as usize * self.sectors as usize
+    }
+    pub fn cylinders(&self) -> u16 {
+        self.cylinders
+    }
+    pub fn heads(&self) -> u16 {
+        self.heads
+    }
+    pub fn sectors(&self) -> u16 {
+        self.sectors
+    }
+    /// Turn a CHS address into a block index. Fails with `AvdError::ChsOutOfRange` if any part of it is out of range.
+    pub fn to_block(&self, chs: Chs) -> Result<u16> {
+        let out_of_range = |component, value, max| AvdError::ChsOutOfRange {
+            component, value, max
+        };
+        if chs.cylinder >= self.cylinders {
+            return Err(out_of_range("cylinder", chs.cylinder, self.cylinders - 1))
+        }
+        if chs.head >= self.heads {
+            return Err(out_of_range("head", chs.head, self.heads - 1))
+        }
+        let sector = chs.sector.wrapping_sub(self.first_sector);
+        if chs.sector < self.first_sector || sector >= self.sectors {
+            return Err(out_of_range("sector", chs.sector, self.first_sector + (self.sectors - 1)))
+        }
+        let idx = (chs.cylinder as usize * self.heads as usize + chs.head as usize) * self.sectors as usize + sector as usize;
+        Ok(idx as u16)
+    }
+    /// Turn a block index into a CHS address, or `None` if the block is past the end of the geometry.
+    pub fn to_chs(&self, idx: u16) -> Option<Chs> {
+        if idx as usize >= self.blocks() {
+            return None
+        }
+        let idx = idx as usize;
+        let track = idx / self.sectors as usize;
+        Some(Chs {
+            cylinder: (track / self.heads as usize) as u16,
+            head: (track % self.heads as usize) as u16,
+            sector: (idx % self.sectors as usize) as u16 + self.first_sector
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn geometry() {
+        assert!(matches!(Geometry::new(0, 4, 16), Err(AvdError::InvalidGeometry)));
+        assert!(matches!(Geometry::new(1025, 4, 16), Err(AvdError::InvalidGeometry)));
+        let g = Geometry::new(1024, 4, 16).unwrap();
+        assert_eq!(g.blocks(), 65536);
+        let chs = |cylinder, head, sector| Chs {
+            cylinder, head, sector
+        };
+        assert_eq!(g.to_block(chs(0, 0, 1)).unwrap(), 0);
+        assert_eq!(g.to_block(chs(0, 1, 1)).unwrap(), 16);
+        assert_eq!(g.to_block(chs(1023, 3, 16)).unwrap(), 65535);
+        assert_eq!(g.to_chs(65535), Some(chs(1023, 3, 16)));
+        assert_eq!(g.to_chs(17), Some(chs(0, 1, 2)));
+        assert!(matches!(g.to_block(chs(0, 0, 0)), Err(AvdError::ChsOutOfRange { component: "sector", value: 0, max: 16 })));
+        assert!(matches!(g.to_block(chs(0, 4, 1)), Err(AvdError::ChsOutOfRange { component: "head", .. })));
+        let z = Geometry::new(10, 2, 8).unwrap().with_first_sector(0).unwrap();
+        assert_eq!(z.to_block(chs(1, 0, 0)).unwrap(), 16);
+        assert!(z.to_block(chs(0, 0, 8)).is_err());
+        assert_eq!(z.to_chs(160), None);
+        let high = Geometry::new(2, 1, 16).unwrap();
+        assert!(matches!(high.with_first_sector(65521), Err(AvdError::InvalidGeometry)));
+        let high = high.with_first_sector(65520).unwrap();
+        assert_eq!(high.to_chs(31), Some(chs(1, 0, 65535)));
+        assert_eq!(high.to_block(chs(1, 0, 65535)).unwrap(), 31);
+        assert!(matches!(high.to_block(chs(0, 0, 1)), Err(AvdError::ChsOutOfRange { max: 65535, .. })));
+    }
+}
@@ -0,0 +1,89 @@
+//! Intel HEX and Motorola S-record exports, for EPROM programmers and the like.
+//! 
+//! Both only cover blocks that are present, addressed by their flat byte address, 16 bytes to a line.
+
+use core::fmt::Write;
+use alloc::string::String;
+use alloc::vec;
+use crate::{Avd, BLOCK_SIZE};
+
+const LINE_LEN: usize = 16;
+
+/// Append one record to `out` as hex digits, followed by the checksum `checksum` works out from the bytes.
+fn push_record(out: &mut String, prefix: &str, bytes: &[u8], checksum: impl Fn(u8) -> u8) {
+    let sum = bytes.iter().fold(0u8, |a, b| a.wrapping_add(*b));
+    out.push_str(prefix);
+    for b in bytes {
+        write!(out, "{:02X}", b).unwrap(); // SHOULD NEVER PANIC
+    }
+    writeln!(out, "{:02X}", checksum(sum)).unwrap(); // SHOULD NEVER PANIC
+}
+
+impl Avd {
+    /// Export the drive's blocks as Intel HEX. Addresses above 64k use extended linear address records.
+    pub fn to_intel_hex(&self) -> String {
+        let mut out = String::new();
+        let mut upper = 0;
+        for (idx, data) in self.blocks() {
+            let hi = idx >> 8; // upper 16 bits of the byte address
+            if hi != upper {
+                let [a, b] = hi.to_be_bytes();
+                push_record(&mut out, ":", &[2, 0, 0, 4, a, b], u8::wrapping_neg);
+                upper = hi
+            }
+            for (i, line) in data.chunks(LINE_LEN).enumerate() {
+                let addr = ((idx & 0xff) as usize * BLOCK_SIZE + i * LINE_LEN) as u16;
+                let [a, b] = addr.to_be_bytes();
+                let mut rec = vec![line.len() as u8, a, b, 0];
+                rec.extend(line);
+                push_record(&mut out, ":", &rec, u8::wrapping_neg)
+            }
+        }
+        out.push_str(":00000001FF\n");
+        out
+    }
+    /// Export the drive's blocks as Motorola S-records, using 24-bit addresses (S2 records).
+    pub fn to_srec(&self) -> String {
+        let mut out = String::new();
+        push_record(&mut out, "S0", &[3, 0, 0], |s| !s);
+        for (idx, data) in self.blocks() {
+            for (i, line) in data.chunks(LINE_LEN).enumerate() {
+                let addr = (idx as u32 * BLOCK_SIZE as u32 + (i * LINE_LEN) as u32).to_be_bytes();
+                let mut rec = vec![line.len() as u8 + 4, addr[1], addr[2], addr[3]];
+                rec.extend(line);
+                push_record(&mut out, "S2", &rec, |s| !s)
+            }
+        }
+        push_record(&mut out, "S8", &[4, 0, 0, 0], |s| !s);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn intel_hex() {
+        let mut drive = Avd::new();
+        drive.set_block(0x101, &[0x11; 256]).unwrap();
+        let hex = drive.to_intel_hex();
+        let lines: Vec<_> = hex.lines().collect();
+        assert_eq!(lines.len(), 1 + 16 + 1);
+        assert_eq!(lines[0], ":020000040001F9");
+        assert_eq!(lines[1], ":1001000011111111111111111111111111111111DF");
+        assert_eq!(lines[16], ":1001F00011111111111111111111111111111111EF");
+        assert_eq!(lines[17], ":00000001FF");
+        assert_eq!(Avd::new().to_intel_hex(), ":00000001FF\n");
+    }
+    #[test]
+    fn srec() {
+        let mut drive = Avd::new();
+        drive.set_block(0x101, &[0x11; 256]).unwrap();
+        let srec = drive.to_srec();
+        let lines: Vec<_> = srec.lines().collect();
+        assert_eq!(lines.len(), 1 + 16 + 1);
+        assert_eq!(lines[0], "S0030000FC");
+        assert_eq!(lines[1], "S21401010011111111111111111111111111111111D9");
+        assert_eq!(lines[17], "S804000000FB");
+    }
+}
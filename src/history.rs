@@ -0,0 +1,129 @@
+//! Undo and redo for block writes, for disk editors.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use crate::Avd;
+
+/// A change to one block, and what it held before (`None` if it was absent).
+type Entry = (u16, Option<[u8; 256]>);
+
+/// The undo and redo stacks for a drive.
+#[derive(Debug)]
+pub(crate) struct History {
+    undo: VecDeque<Entry>,
+    redo: Vec<Entry>,
+    depth: usize,
+}
+impl History {
+    pub fn push(&mut self, entry: Entry) {
+        self.redo.clear();
+        if self.depth == 0 {
+            return
+        }
+        if self.undo.len() == self.depth {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(entry)
+    }
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear()
+    }
+}
+
+impl Avd {
+    /// Start keeping the old contents of each block that gets written or removed, so the changes can be stepped back through with `undo`. Up to `depth` changes are kept, and the oldest are forgotten first.
+    /// 
+    /// If history is already on, this just changes the depth, trimming off the oldest changes if there are too many. Every single-block change is recorded, as well as `delete_range`, `clear` and `blocks_mut`, which record a change for each block they touch. Loading a file or archive replaces the whole drive and forgets the history.
+    pub fn enable_history(&mut self, depth: usize) {
+        let h = self.history.get_or_insert_with(|| History {
+            undo: VecDeque::new(), redo: Vec::new(), depth
+        });
+        h.depth = depth;
+        while h.undo.len() > depth {
+            h.undo.pop_front();
+        }
+    }
+    /// Stop keeping history, and forget what's been kept so far.
+    pub fn disable_history(&mut self) {
+        self.history = None
+    }
+    /// How many changes can be undone.
+    pub fn undo_len(&self) -> usize {
+        self.history.as_ref().map_or(0, |h| h.undo.len())
+    }
+    /// How many undone changes can be redone.
+    pub fn redo_len(&self) -> usize {
+        self.history.as_ref().map_or(0, |h| h.redo.len())
+    }
+    /// Undo the last `n` block changes, returning how many there were to undo. Like the other host-side operations, this ignores write protection and isn't reported to observers.
+    pub fn undo(&mut self, n: usize) -> usize {
+        self.step(n, true)
+    }
+    /// Redo the last `n` changes that were undone, returning how many there were to redo. Any new change clears the redo list.
+    pub fn redo(&mut self, n: usize) -> usize {
+        self.step(n, false)
+    }
+    fn step(&mut self, n: usize, undo: bool) -> usize {
+        // take the history out so the changes below don't get recorded
+        let Some(mut h) = self.history.take() else {
+            return 0
+        };
+        let mut done = 0;
+        while done < n {
+            let entry = if undo { h.undo.pop_back() } else { h.redo.pop() };
+            let Some((idx, data)) = entry else {
+                break
+            };
+            let current = self.blocks.get(&idx).copied();
+            match data {
+                Some(data) => self.insert(idx, data),
+                None => {
+                    self.remove(idx);
+                }
+            }
+            if undo {
+                h.redo.push((idx, current))
+            }
+            else {
+                h.undo.push_back((idx, current))
+            }
+            done += 1
+        }
+        self.history = Some(h);
+        done
+    }
+    /// Remember what block `idx` holds before it's changed, if history is on.
+    pub(crate) fn record(&mut self, idx: u16) {
+        if let Some(h) = &mut self.history {
+            h.push((idx, self.blocks.get(&idx).copied()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn undo_redo() {
+        let mut drive = Avd::new();
+        drive.set_block(0, &[1; 256]).unwrap();
+        drive.enable_history(3);
+        drive.set_block(0, &[2; 256]).unwrap();
+        drive.delete_block(0);
+        drive.modify_block(1, |b| b[0] = 1);
+        drive.modify_block(1, |b| b[0] = 0);
+        assert_eq!(drive.undo_len(), 3);
+        assert_eq!(drive.undo(2), 2);
+        assert_eq!(drive.get_block(1), None);
+        assert!(!drive.is_block_used(0));
+        assert_eq!(drive.undo(5), 1);
+        assert_eq!(drive.get_block(0), Some([2; 256]));
+        assert_eq!(drive.redo(2), 2);
+        assert_eq!(drive.get_block(1).map(|b| b[0]), Some(1));
+        drive.clear();
+        assert_eq!(drive.redo_len(), 0);
+        drive.undo(1);
+        assert_eq!(drive.get_block(1).map(|b| b[0]), Some(1));
+    }
+}
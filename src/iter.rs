@@ -0,0 +1,123 @@
+//! Iterators over a drive's blocks.
+
+use alloc::collections::btree_map;
+use core::iter::FusedIterator;
+use crate::Avd;
+
+/// An iterator over the blocks stored on a drive, in index order. Made by `Avd::blocks`, or by iterating over `&Avd`.
+#[derive(Debug, Clone)]
+pub struct Blocks<'a>(pub(crate) btree_map::Iter<'a, u16, [u8; 256]>);
+impl<'a> Iterator for Blocks<'a> {
+    type Item = (u16, &'a [u8; 256]);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(idx, data)| (*idx, data))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+impl DoubleEndedIterator for Blocks<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|(idx, data)| (*idx, data))
+    }
+}
+impl ExactSizeIterator for Blocks<'_> {}
+impl FusedIterator for Blocks<'_> {}
+
+/// An iterator that takes the blocks out of a drive, in index order. Made by iterating over an `Avd`.
+#[derive(Debug)]
+pub struct IntoBlocks(btree_map::IntoIter<u16, [u8; 256]>);
+impl Iterator for IntoBlocks {
+    type Item = (u16, [u8; 256]);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+impl DoubleEndedIterator for IntoBlocks {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back()
+    }
+}
+impl ExactSizeIterator for IntoBlocks {}
+impl FusedIterator for IntoBlocks {}
+
+impl IntoIterator for Avd {
+    type Item = (u16, [u8; 256]);
+    type IntoIter = IntoBlocks;
+    fn into_iter(self) -> IntoBlocks {
+        let mut drive = self;
+        #[cfg(feature = "std")]
+        drive.cancel_save_on_drop(); // the blocks are about to go, don't save an empty drive over the file
+        IntoBlocks(core::mem::take(&mut drive.blocks).into_iter())
+    }
+}
+impl<'a> IntoIterator for &'a Avd {
+    type Item = (u16, &'a [u8; 256]);
+    type IntoIter = Blocks<'a>;
+    fn into_iter(self) -> Blocks<'a> {
+        self.blocks()
+    }
+}
+
+/// Set each block in turn. Later blocks with the same index win.
+/// 
+/// Unlike `set_block`, this skips the write-protect and bad block checks, since it's for building drives on the host side.
+impl Extend<(u16, [u8; 256])> for Avd {
+    fn extend<I: IntoIterator<Item = (u16, [u8; 256])>>(&mut self, iter: I) {
+        for (idx, data) in iter {
+            self.store_block(idx, &data)
+        }
+    }
+}
+/// Set each block in turn, skipping the write-protect and bad block checks like the owned version. Handy for copying blocks from another drive.
+impl<'a> Extend<(u16, &'a [u8; 256])> for Avd {
+    fn extend<I: IntoIterator<Item = (u16, &'a [u8; 256])>>(&mut self, iter: I) {
+        for (idx, data) in iter {
+            self.store_block(idx, data)
+        }
+    }
+}
+/// Build a drive from blocks, as if by `extend` on a new drive.
+impl FromIterator<(u16, [u8; 256])> for Avd {
+    fn from_iter<I: IntoIterator<Item = (u16, [u8; 256])>>(iter: I) -> Avd {
+        let mut d = Avd::new();
+        d.extend(iter);
+        d
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn into_iter() {
+        let mut drive = Avd::new();
+        for i in 1..=10 {
+            drive.set_block(i, &[i as u8; 256]).unwrap();
+        }
+        let mut n = 0;
+        for (idx, data) in &drive {
+            assert_eq!(data[0], idx as u8);
+            n += 1
+        }
+        assert_eq!(n, 10);
+        assert_eq!(drive.blocks().len(), 10);
+        assert_eq!(drive.blocks().next_back().map(|(i, _)| i), Some(10));
+        let evens: Vec<_> = drive.into_iter().filter(|(idx, _)| idx % 2 == 0).collect();
+        assert_eq!(evens.len(), 5);
+        assert_eq!(evens[0], (2, [2; 256]));
+    }
+    #[test]
+    fn collect() {
+        let drive: Avd = (0..20u16).map(|i| (i, [i as u8; 256])).collect();
+        assert_eq!(drive.used_blocks(), 19); // block 0 is all zeros
+        let mut copy = Avd::new();
+        copy.extend(drive.blocks().filter(|(idx, _)| *idx < 10));
+        copy.extend([(100, [1; 256])]);
+        assert_eq!(copy.used_blocks(), 10);
+        assert_eq!(copy.get_block(9), Some([9; 256]));
+    }
+}
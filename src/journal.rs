@@ -0,0 +1,154 @@
+//! Drives that persist every change by appending it to the archive file.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use crate::{Avd, AvdError, Result, archive::{self, Format}, atomic::write_atomic};
+
+/// An AVD that appends a block record to its archive file every time a block is written, instead of saving the whole drive.
+/// 
+/// This works because later records for a block override earlier ones, so the file is always a valid archive describing the current state of the drive. Removed blocks are recorded as all-zero blocks. Call `compact` now and then to squash the history back down to one record per block.
+/// 
+/// Compressed archives and archives with a whole-archive checksum can't be appended to, so they can't be opened. New files are created as version 0. If the program dies halfway through writing a record, the partial record is thrown away the next time the file is opened.
+#[derive(Debug)]
+pub struct JournalAvd {
+    path: PathBuf,
+    file: File,
+    format: Format,
+    drive: Avd,
+    /// The number of records in the file, including ones that have been overridden since.
+    records: usize,
+}
+impl JournalAvd {
+    /// Open an archive file as a journal, replaying its records. Creates an empty archive if the file doesn't exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<JournalAvd> {
+        let path = path.as_ref().to_owned();
+        let file = OpenOptions::new().read(true).append(true).create(true).open(&path)?;
+        let mut archive = fs::read(&path)?;
+        if archive.is_empty() {
+            archive = Format::new().header();
+            (&file).write_all(&archive)?;
+        }
+        let format = Format::parse_header(&archive)?;
+        if format.has_checksum() {
+            return Err(AvdError::Unsupported("journaling onto an archive with a checksum trailer"))
+        }
+        if !format.is_plain() {
+            return Err(AvdError::Unsupported("journaling onto a compressed or chunked archive"))
+        }
+        let (header_len, record_len) = (format.header_len(), format.record_len());
+        let data_seg = archive.get(header_len..).ok_or(AvdError::MalformedArchive)?;
+        let records = data_seg.len() / record_len;
+        if data_seg.len() % record_len != 0 {
+            file.set_len((header_len + records * record_len) as u64)?; // torn write at the end
+        }
+        let mut drive = Avd::new();
+        for rec in data_seg.chunks_exact(record_len) {
+            let (idx, data) = format.read_record(rec)?;
+            drive.store_block(idx, &data)
+        }
+        drive.format = format.clone();
+        Ok(JournalAvd {
+            path, file, format, drive, records
+        })
+    }
+    /// Get a block from the drive.
+    pub fn get_block(&self, idx: u16) -> Option<[u8; 256]> {
+        self.drive.get_block(idx)
+    }
+    /// Set a block on the drive, appending it to the file. Fails without writing anything if the drive is write protected or the block is bad.
+    pub fn set_block(&mut self, idx: u16, data: &[u8; 256]) -> Result<()> {
+        if self.drive.is_write_protected() {
+            return Err(AvdError::WriteProtected)
+        }
+        self.drive.check_bad(idx)?;
+        let mut rec = vec![0; self.format.record_len()];
+        self.format.write_record(&mut rec, idx, data);
+        if let Err(e) = self.file.write_all(&rec) {
+            // cut off whatever part of the record made it, or every record after it would be misaligned
+            let _ = self.file.set_len((self.format.header_len() + self.records * self.format.record_len()) as u64);
+            return Err(e.into())
+        }
+        self.records += 1;
+        self.drive.store_block(idx, data);
+        Ok(())
+    }
+    /// Remove a block from the drive, appending an all-zero record for it to the file.
+    pub fn delete_block(&mut self, idx: u16) -> Result<()> {
+        if self.drive.is_block_used(idx) {
+            self.set_block(idx, &[0; 256])?
+        }
+        Ok(())
+    }
+    /// The contents of the drive.
+    pub fn drive(&self) -> &Avd {
+        &self.drive
+    }
+    /// Set or clear the drive's write-protect tab, like `Avd::set_write_protected`.
+    pub fn set_write_protected(&mut self, protected: bool) {
+        self.drive.set_write_protected(protected)
+    }
+    /// Whether the drive is write protected.
+    pub fn is_write_protected(&self) -> bool {
+        self.drive.is_write_protected()
+    }
+    /// The number of records in the file. This goes up with every write, until the next `compact`.
+    pub fn journal_len(&self) -> usize {
+        self.records
+    }
+    /// Make sure everything written so far has hit the disk.
+    pub fn sync(&self) -> Result<()> {
+        self.file.sync_data()?;
+        Ok(())
+    }
+    /// Rewrite the file with just one record per block, in index order. The new archive is written next to the old one and moved over it, so a crash partway through doesn't lose anything.
+    pub fn compact(&mut self) -> Result<()> {
+        write_atomic(&self.path, 0, |w| Ok(archive::write_archive(&self.format, &self.drive.blocks, w)?))?;
+        self.file = OpenOptions::new().read(true).append(true).open(&self.path)?;
+        self.records = self.drive.used_blocks();
+        Ok(())
+    }
+    /// Turn this into a normal AVD. The file is left as it is.
+    pub fn into_avd(self) -> Avd {
+        self.drive
+    }
+}
+
+impl Avd {
+    /// Open an archive file as a journal, so every write is appended to it straight away. See [`JournalAvd`].
+    pub fn open_journal(path: impl AsRef<Path>) -> Result<JournalAvd> {
+        JournalAvd::open(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn journal() {
+        let path = std::env::temp_dir().join(format!("avd-journal-{}.avd", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut j = Avd::open_journal(&path).unwrap();
+        for i in 0..10 {
+            j.set_block(1, &[i; 256]).unwrap();
+        }
+        j.set_block(2, &[2; 256]).unwrap();
+        j.delete_block(2).unwrap();
+        assert_eq!(j.journal_len(), 12);
+        drop(j);
+        // a torn record at the end gets dropped
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(&[0, 5, 5]).unwrap();
+        let mut j = Avd::open_journal(&path).unwrap();
+        assert_eq!((j.get_block(1), j.get_block(2)), (Some([9; 256]), None));
+        assert_eq!(fs::metadata(&path).unwrap().len(), 4 + 12 * 258);
+        j.compact().unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 4 + 258);
+        j.set_block(3, &[3; 256]).unwrap();
+        assert_eq!(Avd::from_host_drive(&path).unwrap(), *j.drive());
+        // refused writes don't make it into the file
+        j.set_write_protected(true);
+        assert!(matches!(j.set_block(4, &[4; 256]), Err(AvdError::WriteProtected)));
+        assert_eq!((j.journal_len(), fs::metadata(&path).unwrap().len()), (2, 4 + 2 * 258));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,58 @@
+//! A JSON dump of a drive, for reading and diffing by eye.
+
+use serde_json::{json, Value};
+use crate::{Avd, AvdError, Result};
+
+fn bad(msg: impl Into<String>) -> AvdError {
+    AvdError::BadText("debug json", msg.into())
+}
+
+impl Avd {
+    /// Dump the drive as pretty-printed JSON: an array of objects, each with the block's `index` and its `data` as a hex string, in index order.
+    pub fn to_debug_json(&self) -> String {
+        let blocks: Vec<_> = self.blocks().map(|(idx, data)| {
+            let hex: String = data.iter().map(|b| format!("{:02x}", b)).collect();
+            json!({ "index": idx, "data": hex })
+        }).collect();
+        serde_json::to_string_pretty(&blocks).unwrap() // SHOULD NEVER PANIC
+    }
+    /// Load a new AVD from JSON in the format `to_debug_json` writes. To make writing it by hand easier, `data` can be shorter than a block, and the rest is filled with zeros.
+    pub fn from_debug_json(text: &str) -> Result<Avd> {
+        let value: Value = serde_json::from_str(text).map_err(|e| bad(e.to_string()))?;
+        let mut d = Avd::new();
+        for block in value.as_array().ok_or_else(|| bad("expected an array of blocks"))? {
+            let idx = block["index"].as_u64().and_then(|i| u16::try_from(i).ok()).ok_or_else(|| bad("missing or invalid index"))?;
+            let hex = block["data"].as_str().ok_or_else(|| bad(format!("block {}: missing data", idx)))?;
+            if hex.len() % 2 != 0 || hex.len() > 512 {
+                return Err(bad(format!("block {}: data has to be an even number of hex digits, up to 512", idx)))
+            }
+            let mut data = [0; 256];
+            for (b, pair) in data.iter_mut().zip(hex.as_bytes().chunks(2)) {
+                let pair = std::str::from_utf8(pair).ok().and_then(|p| u8::from_str_radix(p, 16).ok());
+                *b = pair.ok_or_else(|| bad(format!("block {}: data isn't hex", idx)))?
+            }
+            d.store_block(idx, &data)
+        }
+        Ok(d)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn debug_json() {
+        let mut drive = Avd::new();
+        drive.set_block(2, &[0xab; 256]).unwrap();
+        drive.set_block(300, &[1; 256]).unwrap();
+        let text = drive.to_debug_json();
+        assert!(text.contains("\"index\": 300"));
+        assert!(text.contains(&"ab".repeat(256)));
+        assert_eq!(Avd::from_debug_json(&text).unwrap(), drive);
+
+        let d = Avd::from_debug_json(r#"[{"index": 7, "data": "0102"}]"#).unwrap();
+        assert_eq!(d.get_block(7).unwrap()[..3], [1, 2, 0]);
+        assert!(matches!(Avd::from_debug_json(r#"[{"index": 7, "data": "xy"}]"#), Err(AvdError::BadText(..))));
+        assert!(Avd::from_debug_json(r#"[{"index": 70000, "data": ""}]"#).is_err());
+    }
+}
@@ -0,0 +1,84 @@
+//! A simple timing model, for emulators that need to know how long disk accesses take.
+
+use crate::{Avd, Result};
+
+/// How many cycles an access to the drive takes. Give one to a drive with `set_latency`, then use `read_block_timed` and `write_block_timed` to find out what each access costs.
+/// 
+/// The drive has a head that sits just after the last block accessed. An access to the block under the head only costs `per_block_cycles` (plus `write_cycles` for writes), so sequential transfers are cheap. Anything else has to seek first, which costs `seek_cost` plus `seek_cycles_per_block` for each block the head moves. The default model costs nothing at all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LatencyModel {
+    /// Cycles to transfer one block.
+    pub per_block_cycles: u64,
+    /// Cycles to start a seek, however far it goes.
+    pub seek_cost: u64,
+    /// Extra cycles for each block the head moves during a seek.
+    pub seek_cycles_per_block: u64,
+    /// Extra cycles for writing a block, on top of `per_block_cycles`.
+    pub write_cycles: u64,
+}
+impl LatencyModel {
+    /// The cost of accessing block `idx` with the head at `head`.
+    pub fn cost(&self, head: u16, idx: u16, write: bool) -> u64 {
+        let mut cycles = self.per_block_cycles;
+        if idx != head {
+            cycles += self.seek_cost + self.seek_cycles_per_block * idx.abs_diff(head) as u64
+        }
+        if write {
+            cycles += self.write_cycles
+        }
+        cycles
+    }
+}
+
+impl Avd {
+    /// Set the timing model used by `read_block_timed` and `write_block_timed`. See [`LatencyModel`].
+    pub fn set_latency(&mut self, model: LatencyModel) {
+        self.latency = model
+    }
+    /// The timing model in use.
+    pub fn latency(&self) -> LatencyModel {
+        self.latency
+    }
+    /// The block the head is over, which can be accessed without seeking.
+    pub fn head_position(&self) -> u16 {
+        self.head
+    }
+    /// Read a block, also returning how many cycles it took. Absent blocks read as all zeros, and bad blocks fail without moving the head. Otherwise the head ends up after the block.
+    pub fn read_block_timed(&mut self, idx: u16) -> Result<([u8; 256], u64)> {
+        let data = self.device_read(idx)?;
+        Ok((data, self.charge(idx, false)))
+    }
+    /// Write a block like `set_block`, returning how many cycles it took. The head ends up after the block. If the write fails, the head doesn't move.
+    pub fn write_block_timed(&mut self, idx: u16, data: &[u8; 256]) -> Result<u64> {
+        self.set_block(idx, data)?;
+        Ok(self.charge(idx, true))
+    }
+    /// Work out the cost of accessing `idx`, and move the head past it.
+    pub(crate) fn charge(&mut self, idx: u16, write: bool) -> u64 {
+        let cycles = self.latency.cost(self.head, idx, write);
+        self.head = idx.wrapping_add(1);
+        cycles
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn latency() {
+        let mut drive = Avd::new();
+        drive.set_latency(LatencyModel {
+            per_block_cycles: 10, seek_cost: 100, seek_cycles_per_block: 2, write_cycles: 5
+        });
+        assert_eq!(drive.read_block_timed(0).unwrap(), ([0; 256], 10));
+        assert_eq!(drive.read_block_timed(1).unwrap().1, 10);
+        assert_eq!(drive.write_block_timed(2, &[2; 256]).unwrap(), 15);
+        assert_eq!(drive.read_block_timed(50).unwrap().1, 10 + 100 + 2 * 47);
+        assert_eq!(drive.head_position(), 51);
+        assert_eq!(drive.read_block_timed(2).unwrap(), ([2; 256], 10 + 100 + 2 * 49));
+        drive.set_write_protected(true);
+        assert!(drive.write_block_timed(3, &[3; 256]).is_err());
+        assert_eq!(drive.head_position(), 3);
+        assert_eq!(Avd::new().read_block_timed(1000).unwrap().1, 0);
+    }
+}
@@ -0,0 +1,132 @@
+//! Opening archives without reading all the block data up front.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use crate::{Avd, AvdError, Result, archive::{self, Format}};
+
+/// An AVD backed by an archive file, where blocks are only read from the file the first time they're accessed.
+/// 
+/// Opening only reads the index of each block record, so it's cheap even for very full drives. That also means the archive checksum, if there is one, isn't checked. Blocks that have been read or written live in memory from then on. The file itself is never modified; use `save` to write the drive back out.
+#[derive(Debug)]
+pub struct LazyAvd {
+    file: File,
+    format: Format,
+    /// Blocks still sitting in the file, and the offset of their records.
+    pending: BTreeMap<u16, u64>,
+    loaded: Avd,
+}
+impl LazyAvd {
+    /// Open an archive file lazily.
+    pub fn open(path: impl AsRef<Path>) -> Result<LazyAvd> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut r = BufReader::new(&file);
+        let format = archive::read_header(&mut r)?;
+        if !format.is_plain() {
+            return Err(AvdError::Unsupported("lazily loading a compressed or chunked archive"))
+        }
+        let header_len = format.header_len() as u64;
+        let record_len = format.record_len() as u64;
+        r.seek(SeekFrom::Start(header_len))?;
+        let data_len = len.checked_sub(header_len + format.trailer_len() as u64).ok_or(AvdError::MalformedArchive)?;
+        if !data_len.is_multiple_of(record_len) {
+            return Err(AvdError::MalformedArchive)
+        }
+        let mut pending = BTreeMap::new();
+        for i in 0..data_len / record_len {
+            let mut idx = [0; 2];
+            r.read_exact(&mut idx)?;
+            r.seek_relative(record_len as i64 - 2)?;
+            pending.insert(u16::from_be_bytes(idx), header_len + i * record_len);
+        }
+        drop(r);
+        let mut loaded = Avd::new();
+        loaded.format = format.clone();
+        Ok(LazyAvd {
+            file, format, pending, loaded
+        })
+    }
+    /// Get a block from the drive, reading it from the file if this is the first access. Block checksums, if the archive has them, are checked as blocks are read.
+    pub fn get_block(&mut self, idx: u16) -> Result<Option<[u8; 256]>> {
+        self.fetch(idx)?;
+        Ok(self.loaded.get_block(idx))
+    }
+    /// Set a block inside the drive.
+    pub fn set_block(&mut self, idx: u16, data: &[u8; 256]) {
+        self.pending.remove(&idx);
+        self.loaded.store_block(idx, data)
+    }
+    /// Remove a block from the drive.
+    pub fn delete_block(&mut self, idx: u16) {
+        self.pending.remove(&idx);
+        self.loaded.delete_block(idx);
+    }
+    /// Check if a block is present, without reading it.
+    pub fn is_block_used(&self, idx: u16) -> bool {
+        self.pending.contains_key(&idx) || self.loaded.is_block_used(idx)
+    }
+    /// The number of blocks that haven't been read from the file yet.
+    pub fn pending_blocks(&self) -> usize {
+        self.pending.len()
+    }
+    /// Read every remaining block from the file and turn this into a normal AVD.
+    pub fn into_avd(mut self) -> Result<Avd> {
+        self.fetch_all()?;
+        Ok(self.loaded)
+    }
+    /// Save the drive to a file, in the format version it was opened from. Any blocks that haven't been read yet are read first.
+    pub fn save(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        self.fetch_all()?;
+        self.loaded.save(path)
+    }
+    fn fetch(&mut self, idx: u16) -> Result<()> {
+        if let Some(offset) = self.pending.get(&idx) {
+            let mut rec = vec![0; self.format.record_len()];
+            self.file.seek(SeekFrom::Start(*offset))?;
+            self.file.read_exact(&mut rec)?;
+            let (_, data) = self.format.read_record(&rec)?;
+            self.pending.remove(&idx);
+            self.loaded.store_block(idx, &data);
+        }
+        Ok(())
+    }
+    fn fetch_all(&mut self) -> Result<()> {
+        while let Some((&idx, _)) = self.pending.first_key_value() {
+            self.fetch(idx)?
+        }
+        Ok(())
+    }
+}
+
+impl Avd {
+    /// Open an archive file lazily, so block data is only read when it's needed. See [`LazyAvd`].
+    pub fn open_lazy(path: impl AsRef<Path>) -> Result<LazyAvd> {
+        LazyAvd::open(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn lazy() {
+        let path = std::env::temp_dir().join(format!("avd-lazy-{}.avd", std::process::id()));
+        let mut drive = Avd::new();
+        for i in 0..20 {
+            drive.set_block(i * 3, &[i as u8 + 1; 256]).unwrap();
+        }
+        drive.save(&path).unwrap();
+        let mut lazy = Avd::open_lazy(&path).unwrap();
+        assert_eq!(lazy.pending_blocks(), 20);
+        assert_eq!(lazy.get_block(6).unwrap(), Some([3; 256]));
+        assert_eq!(lazy.get_block(7).unwrap(), None);
+        lazy.set_block(9, &[0xff; 256]);
+        assert!(lazy.is_block_used(12));
+        assert_eq!(lazy.pending_blocks(), 18);
+        drive.set_block(9, &[0xff; 256]).unwrap();
+        assert_eq!(lazy.into_avd().unwrap(), drive);
+        let _ = std::fs::remove_file(path);
+    }
+}
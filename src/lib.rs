@@ -1,73 +1,424 @@
+//! An emulated 16mb block storage device for the AVC2, and the archive format it's saved in. See [`Avd`].
+//! 
+//! Features:
+//! 
+//! - `std` (default): file and stream APIs. Without it, the crate is `no_std` and only needs `alloc`, which also makes it easy to use from wasm. Drives can still be moved in and out as bytes with `to_bytes`, `from_bytes` and friends.
+//! - `compression`: zstd-compressed archives.
+//! - `crypto`: encrypted archives.
+//! - `mmap`: drives backed by a memory-mapped file.
+//! - `rayon`: encode and decode archives on several threads.
+//! - `json`: a JSON dump for debugging.
+//! - `serde`: `Serialize` and `Deserialize` for `Avd`.
+//! - `ffi`: a C interface, see [`ffi`].
+//! - `python`: Python bindings with pyo3.
+//! - `async`: `save_async` and `load_async`, using tokio's file APIs.
+//! - `watch`: noticing when a drive's file is changed by another program, with notify.
+//! - `stats`: per-block read and write counters, see `access_stats`. Off by default, since it costs a lock on every access.
+//! - `log`: debug logs for loading and saving, and warnings for odd-looking archives, through the log crate.
+//! - `tracing`: tracing spans around loading, saving, and bulk operations like DMA and raw image imports.
+//! - `metrics`: send the counters from `Avd::metrics` to the metrics crate too.
+//! - `chain`: incremental backup chains checked with SHA-256, see `Avd::save_incremental_since`.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
 use std::path::{Path};
-use std::fs::{write, read};
+use core::ops::{Bound, Index, IndexMut, Range, RangeBounds};
+#[cfg(feature = "std")]
+use std::fs::read;
+#[cfg(feature = "std")]
+use std::io::{Read, Write};
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::vec;
 use thiserror::Error;
-use std::cmp::{PartialOrd, Ordering};
+use archive::Format;
+use bitmap::Bitmap;
+use dirty::{Tracking, TrackingCell, tracking_mut};
+use observe::Observers;
+
+#[cfg(feature = "std")]
+mod analyze;
+mod archive;
+mod armor;
+#[cfg(feature = "std")]
+mod atomic;
+#[cfg(feature = "async")]
+mod async_io;
+#[cfg(feature = "std")]
+mod autosave;
+mod backend;
+mod badblock;
+mod bitmap;
+mod builder;
+#[cfg(feature = "chain")]
+mod chain;
+mod controller;
+#[cfg(feature = "std")]
+mod counters;
+mod bytes;
+#[cfg(feature = "std")]
+mod cursor;
+#[cfg(feature = "crypto")]
+mod crypto;
+mod delta;
+mod device;
+mod dma;
+mod drive;
+mod dump;
+mod dirty;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod frozen;
+mod geometry;
+mod hex;
+mod history;
+mod iter;
+#[cfg(feature = "std")]
+mod journal;
+#[cfg(feature = "json")]
+mod json;
+#[cfg(feature = "std")]
+mod lazy;
+mod latency;
+#[cfg(feature = "std")]
+mod lock;
+mod merge;
+mod meta;
+mod observe;
+mod overlay;
+#[cfg(feature = "python")]
+mod python;
+mod raw;
+#[cfg(feature = "serde")]
+mod serde_impl;
+#[cfg(feature = "std")]
+mod shared;
+#[cfg(feature = "std")]
+mod sharded;
+mod snapshot;
+#[cfg(feature = "std")]
+mod split;
+#[cfg(feature = "stats")]
+mod stats;
+#[cfg(feature = "std")]
+mod trace;
+mod transaction;
+#[cfg(feature = "std")]
+mod wal;
+#[cfg(feature = "watch")]
+mod watch;
+mod watchpoint;
+#[cfg(feature = "mmap")]
+mod mmap;
+
+#[cfg(feature = "std")]
+pub use analyze::{BlockKind, ContentReport};
+pub use archive::ArchiveVersion;
+#[cfg(feature = "std")]
+pub use autosave::Autosave;
+#[cfg(feature = "std")]
+pub use backend::FileBackend;
+pub use backend::{MemoryBackend, StorageBackend};
+pub use builder::AvdBuilder;
+#[cfg(feature = "chain")]
+pub use chain::BackupManifest;
+pub use controller::{AvdController, DriveStatus};
+#[cfg(feature = "std")]
+pub use counters::Metrics;
+pub use delta::BlockChange;
+#[cfg(feature = "std")]
+pub use cursor::Cursor;
+pub use device::{AsyncBlockDevice, BlockDevice};
+pub use drive::AvdDrive;
+pub use frozen::FrozenAvd;
+pub use geometry::{Chs, Geometry};
+pub use iter::{Blocks, IntoBlocks};
+#[cfg(feature = "std")]
+pub use journal::JournalAvd;
+#[cfg(feature = "std")]
+pub use lazy::LazyAvd;
+pub use latency::LatencyModel;
+#[cfg(feature = "std")]
+pub use lock::DriveLock;
+pub use merge::MergePolicy;
+#[cfg(feature = "mmap")]
+pub use mmap::MmapAvd;
+pub use observe::{Access, Observer, ObserverId};
+pub use overlay::OverlayAvd;
+#[cfg(feature = "std")]
+pub use shared::SharedAvd;
+#[cfg(feature = "std")]
+pub use sharded::ShardedAvd;
+pub use snapshot::AvdSnapshot;
+#[cfg(feature = "stats")]
+pub use stats::{AccessStats, BlockStats};
+#[cfg(feature = "std")]
+pub use trace::{TraceEntry, TraceLog};
+pub use transaction::Transaction;
+#[cfg(feature = "std")]
+pub use wal::WalAvd;
+#[cfg(feature = "watch")]
+pub use watch::FileWatcher;
+pub use watchpoint::{WatchKind, WatchpointId};
 
-#[derive(Debug, PartialEq)]
+/// The size of a single block, in bytes.
+pub const BLOCK_SIZE: usize = 256;
+/// The number of blocks on a drive.
+pub const BLOCK_COUNT: usize = 65536;
+/// The size of the whole drive, in bytes.
+pub const DRIVE_SIZE: usize = BLOCK_SIZE * BLOCK_COUNT;
+
+#[derive(Debug)]
 /// The AVC2 Virtual Drive. An emulated 16mb block-based storage device. Blocks are 256 bytes long.
 /// 
 /// Only non-zero blocks are actually stored in memory and in the archive representation. This reduces memory and disk usage by a large margin, particularly when there's not much data on the drive.
+/// 
+/// Blocks are kept in a map ordered by index, so lookups are O(log n) and iteration always goes from the lowest index to the highest.
 pub struct Avd {
-    blocks: Vec<Block>,
+    blocks: BTreeMap<u16, [u8; 256]>,
+    /// Which blocks are present in `blocks`. Everything that adds or removes blocks has to keep this in sync.
+    used: Bitmap,
+    /// Dirty blocks and file layout for incremental saves. Behind a lock so `save` can update it through `&self`.
+    tracking: TrackingCell,
+    /// Whether `set_block` removes blocks instead of storing all zeros.
+    elide_zeros: bool,
+    /// Whether `set_block` refuses to write, like a drive with its write-protect tab set.
+    write_protected: bool,
+    /// How long accesses take, for the timed APIs.
+    latency: LatencyModel,
+    /// Where the head is, for the timed APIs.
+    head: u16,
+    /// How the drive gets saved. Loading a file sets this to match the file.
+    format: Format,
+    /// How many old copies of the file `save` keeps around.
+    #[cfg(feature = "std")]
+    backups: u32,
+    /// Where `persist` saves to, if anywhere.
+    backend: Option<Box<dyn StorageBackend>>,
+    /// Who gets told about accesses.
+    observers: Observers,
+    /// Read and write counts for `access_stats`.
+    #[cfg(feature = "stats")]
+    counters: stats::Counters,
+    /// Running totals for `metrics`.
+    #[cfg(feature = "std")]
+    metrics: counters::Counters,
+    /// Old block contents for `undo`, if history is on.
+    history: Option<history::History>,
+    /// Where to save to when the drive is dropped, if anywhere.
+    #[cfg(feature = "std")]
+    drop_save: autosave::DropSave,
 }
 impl Avd {
     /// Create a new, blank AVD.
     pub fn new() -> Avd {
         Avd {
-            blocks: Vec::new()
+            blocks: BTreeMap::new(),
+            used: Bitmap::new(),
+            tracking: Tracking::new(),
+            elide_zeros: true,
+            write_protected: false,
+            latency: LatencyModel::default(),
+            head: 0,
+            format: Format::new(),
+            #[cfg(feature = "std")]
+            backups: 0,
+            backend: None,
+            observers: Observers::default(),
+            #[cfg(feature = "stats")]
+            counters: Default::default(),
+            #[cfg(feature = "std")]
+            metrics: Default::default(),
+            history: None,
+            #[cfg(feature = "std")]
+            drop_save: Default::default(),
         }
     }
+    /// Create a new, blank AVD, expecting about `n_blocks` blocks to be stored.
+    /// 
+    /// Blocks are kept in a map that allocates as it goes rather than one big buffer, so there's nothing to set aside up front and this is the same as `new`. It's here so code that sizes its storage doesn't need to care.
+    pub fn with_capacity(n_blocks: usize) -> Avd {
+        let mut d = Avd::new();
+        d.reserve(n_blocks);
+        d
+    }
+    /// Expect about `n` more blocks to be stored. Like `with_capacity`, this is only a hint and currently does nothing.
+    pub fn reserve(&mut self, n: usize) {
+        let _ = n;
+    }
     /// Save the AVD to a file.
+    /// 
+    /// Blocks are always written in index order, no matter what order they were set in, so drives with the same contents and settings save to byte-for-byte identical archives.
+    /// 
+    /// The archive is written to a temporary file next to `path`, synced, and then renamed over it, so a crash partway through leaves the old file as it was.
+    #[cfg(feature = "std")]
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(path = %path.as_ref().display(), blocks = self.blocks.len())))]
     pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
-        let a = self.save_archive();
-        write(&path, a)?;
+        #[cfg(feature = "log")]
+        let start = std::time::Instant::now();
+        let len = atomic::write_atomic(path.as_ref(), self.backups, |w| self.save_to(w))?;
+        self.metrics.flushed(len);
+        self.tracking_ref().reset(path.as_ref(), &self.format, self.blocks.keys().copied().collect());
+        #[cfg(feature = "log")]
+        log::debug!(
+            "saved {} blocks to {} ({} bytes) in {:?}",
+            self.blocks.len(), path.as_ref().display(), len, start.elapsed()
+        );
 
         Ok(())
     }
-    fn save_archive(&self) -> Vec<u8> {
-        let mut ret = vec![0x41, 0x56, 0x44, 0x00];
-        for b in &self.blocks {
-            ret.extend(b.idx.to_be_bytes());
-            ret.extend(b.data)
-        }
-        ret
+    /// Write the AVD's archive to anything that implements [`Write`], without building the whole thing in memory first.
+    #[cfg(feature = "std")]
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn save_to(&self, w: impl Write) -> Result<()> {
+        archive::write_archive(&self.format, &self.blocks, w)?;
+        Ok(())
     }
     /// Load a file into the AVD. Be warned! This will overwrite the entire drive!
+    #[cfg(feature = "std")]
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(path = %path.as_ref().display())))]
     pub fn load(&mut self, path: impl AsRef<Path>) -> Result<()> {
-        let archive = read(path)?;
-        self.blocks = self.load_archive(&archive)?;
+        let archive = read(&path)?;
+        self.load_archive(path.as_ref(), &archive)
+    }
+    /// Replace the contents with a decoded archive that was read from `path`, and remember its layout.
+    #[cfg(feature = "std")]
+    fn load_archive(&mut self, path: &Path, archive: &[u8]) -> Result<()> {
+        #[cfg(feature = "log")]
+        let start = std::time::Instant::now();
+        let (format, records) = archive::decode(archive)?;
+        self.replace_contents(format, &records);
+        let tracking = tracking_mut(&mut self.tracking);
+        tracking.reset(path, &self.format, records.iter().map(|(idx, _)| *idx).collect());
+        if self.blocks.len() != records.len() {
+            tracking.layout = None // duplicate indices, the file can't be patched in place
+        }
+        #[cfg(feature = "log")]
+        log::debug!("loaded {} blocks from {} ({} bytes) in {:?}", self.blocks.len(), path.display(), archive.len(), start.elapsed());
         Ok(())
     }
-    fn load_archive(&self, archive: &[u8]) -> Result<Vec<Block>> {
-        let header = &archive[..4];
-        if header != [0x41, 0x56, 0x44, 0x00] {
-            return Err(AvdError::BadHeader(header[0], header[1], header[2], header[3])) // SHOULD NEVER PANIC
+    /// Load an archive from anything that implements [`Read`], parsing blocks as they arrive. Like `load`, this overwrites the entire drive.
+    #[cfg(feature = "std")]
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn load_from(&mut self, r: impl Read) -> Result<()> {
+        #[cfg(feature = "log")]
+        let start = std::time::Instant::now();
+        let (format, records) = archive::read_archive(r)?;
+        self.replace_contents(format, &records);
+        #[cfg(feature = "log")]
+        log::debug!("loaded {} blocks from a stream in {:?}", self.blocks.len(), start.elapsed());
+        let tracking = tracking_mut(&mut self.tracking);
+        tracking.dirty.clear();
+        tracking.layout = None;
+        Ok(())
+    }
+    /// Load a new AVD from anything that implements [`Read`].
+    #[cfg(feature = "std")]
+    pub fn read_from(r: impl Read) -> Result<Avd> {
+        let mut d = Avd::new();
+        d.load_from(r)?;
+        Ok(d)
+    }
+    /// Get the AVD's archive as bytes, exactly as `save` would write it. Like `save`, the output only depends on the drive's contents and settings.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(blocks = self.blocks.len())))]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        archive::encode(&self.format, &self.blocks)
+    }
+    /// Load a new AVD from an archive in memory.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(len = archive.len())))]
+    pub fn from_bytes(archive: &[u8]) -> Result<Avd> {
+        let (format, records) = archive::decode(archive)?;
+        let mut d = Avd::new();
+        d.replace_contents(format, &records);
+        #[cfg(feature = "log")]
+        log::debug!("decoded {} blocks from {} bytes", d.blocks.len(), archive.len());
+        Ok(d)
+    }
+    fn replace_contents(&mut self, format: Format, records: &[archive::Record]) {
+        if let Some(h) = &mut self.history {
+            h.clear()
         }
-        let mut ret = Vec::new();
-        let mut data_seg = &archive[4..];
-        if data_seg.len() % 258 != 0 {
-            return Err(AvdError::MalformedArchive)
+        self.blocks.clear();
+        self.used.clear();
+        #[cfg(feature = "log")]
+        let (mut dupes, mut zeros) = (0, 0);
+        for (idx, data) in records {
+            self.used.set(*idx, true);
+            let _old = self.blocks.insert(*idx, *data);
+            #[cfg(feature = "log")]
+            {
+                dupes += _old.is_some() as usize;
+                zeros += is_zero(data) as usize;
+            }
         }
-        loop {
-            if data_seg.len() < 258 {
-                break
+        #[cfg(feature = "log")]
+        {
+            if dupes != 0 {
+                log::warn!("archive has {} duplicate block indices, the last copy of each was kept", dupes)
+            }
+            if zeros != 0 {
+                log::warn!("archive stores {} all-zero blocks", zeros)
             }
-            let block = &data_seg[..258];
-            data_seg = &data_seg[258..];
-            let idx = u16::from_be_bytes(block[..2].try_into().unwrap()); // SHOULD NEVER PANIC
-            let b = Block {
-                idx, data: block[2..].try_into().unwrap()
-            };
-            ret.push(b)
         }
-
-        Ok(ret)
+        self.format = format;
+    }
+    /// The archive format version the drive will be saved in. This is whatever version the drive was loaded from, or version 0 for new drives, unless a feature that needs a newer version has been turned on.
+    pub fn archive_version(&self) -> ArchiveVersion {
+        self.format.version()
+    }
+    /// Choose which archive format version the drive will be saved in. If a feature that needs a newer version is turned on, that version gets used instead.
+    pub fn set_archive_version(&mut self, version: ArchiveVersion) {
+        self.format.version = version
+    }
+    /// Choose whether to store a CRC32 with each block in the archive, so corrupted blocks are caught when loading. Needs archive version 1 or later.
+    pub fn set_block_checksums(&mut self, enabled: bool) {
+        self.format.block_crc = enabled
     }
-    /// "Defrag" the in-memory representation of the drive's blocks. Not sure why you'd need this. Theoretically makes access to lower blocks faster but not by much.
-    pub fn sort(&mut self) {
-        self.blocks.sort_by(|a, b| a.partial_cmp(&b).unwrap())
+    /// Whether a CRC32 is stored with each block in the archive.
+    pub fn block_checksums(&self) -> bool {
+        self.format.block_crc
     }
+    /// Choose whether to end the archive with a CRC32 of the whole thing, which catches truncation and corruption anywhere in the file.
+    /// 
+    /// This is on by default, but only applies to archive version 1. Version 0 archives never have one, and version 2 archives always checksum each chunk instead.
+    pub fn set_archive_checksum(&mut self, enabled: bool) {
+        self.format.checksum = enabled
+    }
+    /// Whether the archive will be checksummed as a whole. Always true for version 2, where every chunk has its own checksum.
+    pub fn archive_checksum(&self) -> bool {
+        self.format.has_checksum() || self.format.version() >= ArchiveVersion::V2
+    }
+    /// Choose whether to compress the archive with zstd when saving. Needs archive version 1 or later.
+    /// 
+    /// Compressed archives are loaded transparently by `load`, as long as the `compression` feature is enabled.
+    #[cfg(feature = "compression")]
+    pub fn set_compression(&mut self, enabled: bool) {
+        self.format.compressed = enabled
+    }
+    /// Whether the archive will be compressed when saving.
+    pub fn compression(&self) -> bool {
+        self.format.compressed
+    }
+    /// Save the AVD to a compressed archive, whether or not compression is turned on for normal saves.
+    #[cfg(feature = "compression")]
+    pub fn save_compressed(&self, path: impl AsRef<Path>) -> Result<()> {
+        let format = Format {
+            compressed: true, ..self.format.clone()
+        };
+        let len = atomic::write_atomic(path.as_ref(), self.backups, |w| Ok(archive::write_archive(&format, &self.blocks, w)?))?;
+        self.metrics.flushed(len);
+        Ok(())
+    }
+    /// "Defrag" the in-memory representation of the drive's blocks. Blocks are always kept in order now, so this does nothing.
+    #[deprecated(note = "blocks are always kept sorted by index, so this is a no-op")]
+    pub fn sort(&mut self) {}
     /// Load a new AVD from a file.
+    #[cfg(feature = "std")]
     pub fn from_host_drive(path: impl AsRef<Path>) -> Result<Avd> {
         let mut d = Avd::new();
         d.load(path)?;
@@ -76,42 +427,376 @@ impl Avd {
 
     /// Get a block from the drive.
     pub fn get_block(&self, idx: u16) -> Option<[u8; 256]> {
-        self.blocks.iter().find(|b| b.idx == idx).map(|b| b.data)
+        self.get_block_ref(idx).copied()
+    }
+    /// Get a reference to a block on the drive, without copying it.
+    pub fn get_block_ref(&self, idx: u16) -> Option<&[u8; 256]> {
+        self.notify(idx, Access::Read);
+        self.blocks.get(&idx)
+    }
+    /// Get a mutable reference to a block on the drive, if it's present.
+    pub fn get_block_mut(&mut self, idx: u16) -> Option<&mut [u8; 256]> {
+        if self.used.get(idx) {
+            self.notify(idx, Access::Write);
+            self.record(idx);
+            self.touch(idx)
+        }
+        self.blocks.get_mut(&idx)
     }
-    /// Set a block inside the drive.
-    pub fn set_block(&mut self, idx: u16, data: &[u8; 256]) {
-        let b = self.blocks.iter().position(|b| b.idx == idx);
-        match b {
-            Some(v) => self.blocks[v].data = *data,
+    /// Get a mutable reference to a block on the drive, creating it (all zeros) if it isn't present.
+    pub fn get_or_insert_block_mut(&mut self, idx: u16) -> &mut [u8; 256] {
+        self.notify(idx, Access::Write);
+        #[cfg(feature = "std")]
+        if !self.used.get(idx) {
+            self.metrics.created()
+        }
+        self.record(idx);
+        self.used.set(idx, true);
+        self.touch(idx);
+        self.blocks.entry(idx).or_insert([0; 256])
+    }
+    /// Set a block inside the drive. Setting a block to all zeros removes it, unless zero elision has been turned off.
+    /// 
+    /// Fails with `AvdError::WriteProtected` if the drive is write protected, or `AvdError::BadSector` if the block has been marked bad.
+    pub fn set_block(&mut self, idx: u16, data: &[u8; 256]) -> Result<()> {
+        if self.write_protected {
+            return Err(AvdError::WriteProtected)
+        }
+        self.check_bad(idx)?;
+        self.store_block(idx, data);
+        Ok(())
+    }
+    /// `set_block`, ignoring write protection.
+    fn store_block(&mut self, idx: u16, data: &[u8; 256]) {
+        self.notify(idx, Access::Write);
+        self.put_block(idx, data)
+    }
+    /// `store_block`, without telling observers.
+    fn put_block(&mut self, idx: u16, data: &[u8; 256]) {
+        if self.elide_zeros && is_zero(data) {
+            self.remove(idx);
+        }
+        else {
+            self.insert(idx, *data);
+        }
+    }
+    /// Set or clear the drive's write-protect tab. While it's set, `set_block`, `set_blocks`, and writes through [`BlockDevice`] and [`AsyncBlockDevice`] fail with `AvdError::WriteProtected` and leave the drive alone.
+    /// 
+    /// Like the tab on a real disk, this only stops the drive itself writing. In-place access like `get_block_mut`, the byte-level helpers and `fill_blocks`, `copy_block` and `move_block`, `extend`, deleting blocks, and replacing the whole drive by loading or importing still work, since those are for tools on the host side.
+    pub fn set_write_protected(&mut self, protected: bool) {
+        self.write_protected = protected
+    }
+    /// Whether the drive is write protected.
+    pub fn is_write_protected(&self) -> bool {
+        self.write_protected
+    }
+    /// Choose whether setting a block to all zeros removes it (the default) or stores the zeros like any other data.
+    pub fn set_zero_elision(&mut self, elide: bool) {
+        self.elide_zeros = elide
+    }
+    /// Whether setting a block to all zeros removes it.
+    pub fn zero_elision(&self) -> bool {
+        self.elide_zeros
+    }
+    /// Keep the last `n` versions of the file when saving, as `disk.avd.1` (the newest) up to `disk.avd.n`. The oldest one is deleted when there are too many. Defaults to 0, which keeps none.
+    /// 
+    /// This applies to every way of saving a whole archive, but not to `save_incremental` when it patches the file in place.
+    #[cfg(feature = "std")]
+    pub fn set_backups(&mut self, n: u32) {
+        self.backups = n
+    }
+    /// How many old versions of the file are kept when saving.
+    #[cfg(feature = "std")]
+    pub fn backups(&self) -> u32 {
+        self.backups
+    }
+    /// Get a run of consecutive blocks. Absent blocks come back as all zeros.
+    pub fn get_blocks(&self, range: impl RangeBounds<u16>) -> Vec<[u8; 256]> {
+        let range = block_range(range);
+        let mut ret = vec![[0; 256]; range.len()];
+        if range.is_empty() {
+            return ret
+        }
+        for (idx, data) in self.blocks.range(range.start as u16..=(range.end - 1) as u16) {
+            ret[*idx as usize - range.start] = *data
+        }
+        ret
+    }
+    /// Set a run of consecutive blocks, starting at `start_idx`.
+    /// 
+    /// Panics if the run goes past the end of the drive. If the drive is write protected or any block in the run is bad, nothing is written.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(start = start_idx, count = data.len())))]
+    pub fn set_blocks(&mut self, start_idx: u16, data: &[[u8; 256]]) -> Result<()> {
+        assert!(start_idx as usize + data.len() <= BLOCK_COUNT, "block run goes past the end of the drive");
+        if self.write_protected {
+            return Err(AvdError::WriteProtected)
+        }
+        if let Some(idx) = self.format.bad.range(start_idx..).next().filter(|i| (**i as usize) < start_idx as usize + data.len()) {
+            return Err(AvdError::BadSector(*idx))
+        }
+        for (i, d) in data.iter().enumerate() {
+            self.store_block(start_idx + i as u16, d);
+        }
+        Ok(())
+    }
+    /// Read-modify-write a block. The block is created if it isn't present, and removed again afterwards if it ends up all zeros (unless zero elision is off), so the drive stays sparse.
+    pub fn modify_block<R>(&mut self, idx: u16, f: impl FnOnce(&mut [u8; 256]) -> R) -> R {
+        let elide = self.elide_zeros;
+        let data = self.get_or_insert_block_mut(idx);
+        let ret = f(data);
+        if elide && is_zero(data) {
+            // the change was already recorded when the block was handed out
+            self.unlink(idx);
+        }
+        ret
+    }
+    /// Copy the contents of block `src` into block `dst`. If `src` is absent, `dst` is removed.
+    pub fn copy_block(&mut self, src: u16, dst: u16) {
+        match self.get_block(src) {
+            Some(data) => self.store_block(dst, &data),
             None => {
-                self.blocks.push(Block {
-                    idx, data: *data
-                })
+                self.delete_block(dst);
             }
         }
     }
+    /// Move the contents of block `src` into block `dst`, leaving `src` empty.
+    pub fn move_block(&mut self, src: u16, dst: u16) {
+        if src != dst {
+            self.copy_block(src, dst);
+            self.delete_block(src);
+        }
+    }
+    /// Iterate over all the blocks stored on the drive, in index order. Absent blocks are skipped.
+    pub fn blocks(&self) -> Blocks<'_> {
+        Blocks(self.blocks.iter())
+    }
+    /// Iterate mutably over all the blocks stored on the drive, in index order.
+    pub fn blocks_mut(&mut self) -> impl Iterator<Item = (u16, &mut [u8; 256])> {
+        if let Some(h) = &mut self.history {
+            for (idx, data) in &self.blocks {
+                h.push((*idx, Some(*data)))
+            }
+        }
+        let dirty = &mut tracking_mut(&mut self.tracking).dirty;
+        for idx in self.blocks.keys() {
+            dirty.set(*idx, true)
+        }
+        self.blocks.iter_mut().map(|(idx, data)| (*idx, data))
+    }
+    /// Remove a block from the drive, returning its old contents if it was present.
+    /// 
+    /// The block will read back as absent (all zeros) and won't take up any space in memory or in the archive.
+    pub fn delete_block(&mut self, idx: u16) -> Option<[u8; 256]> {
+        self.notify(idx, Access::Delete);
+        self.remove(idx)
+    }
+    /// `delete_block`, without telling observers.
+    fn remove(&mut self, idx: u16) -> Option<[u8; 256]> {
+        if self.used.get(idx) {
+            self.record(idx)
+        }
+        self.unlink(idx)
+    }
+    /// `remove`, without recording history.
+    fn unlink(&mut self, idx: u16) -> Option<[u8; 256]> {
+        let ret = self.blocks.remove(&idx);
+        if ret.is_some() {
+            #[cfg(feature = "std")]
+            self.metrics.deleted(1);
+            self.used.set(idx, false);
+            self.touch(idx)
+        }
+        ret
+    }
+    /// Remove every block with an index inside `range`.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn delete_range(&mut self, range: impl RangeBounds<u16>) {
+        if let Some(h) = &mut self.history {
+            for (idx, data) in self.blocks.range((range.start_bound().cloned(), range.end_bound().cloned())) {
+                h.push((*idx, Some(*data)))
+            }
+        }
+        let used = &mut self.used;
+        let dirty = &mut tracking_mut(&mut self.tracking).dirty;
+        #[cfg(feature = "std")]
+        let before = self.blocks.len();
+        self.blocks.retain(|idx, _| {
+            let keep = !range.contains(idx);
+            if !keep {
+                used.set(*idx, false);
+                dirty.set(*idx, true)
+            }
+            keep
+        });
+        #[cfg(feature = "std")]
+        self.metrics.deleted((before - self.blocks.len()) as u64);
+    }
+    /// Wipe the entire drive, returning it to the blank state.
+    pub fn clear(&mut self) {
+        self.delete_range(..)
+    }
+    /// Check if a block is stored on the drive. This is a constant-time bitmap lookup.
+    pub fn is_block_used(&self, idx: u16) -> bool {
+        self.used.get(idx)
+    }
+    fn insert(&mut self, idx: u16, data: [u8; 256]) {
+        self.record(idx);
+        #[cfg(feature = "std")]
+        if !self.used.get(idx) {
+            self.metrics.created()
+        }
+        self.used.set(idx, true);
+        self.touch(idx);
+        self.blocks.insert(idx, data);
+    }
+
+    /// The number of blocks actually stored on the drive.
+    pub fn used_blocks(&self) -> usize {
+        self.blocks.len()
+    }
+    /// The number of blocks not stored on the drive (ie. reading as all zeros).
+    pub fn free_blocks(&self) -> usize {
+        BLOCK_COUNT - self.used_blocks()
+    }
+    /// The number of bytes of block data stored on the drive. Doesn't include the index or any other overhead.
+    pub fn bytes_stored(&self) -> usize {
+        self.used_blocks() * BLOCK_SIZE
+    }
+    /// Roughly how many bytes of RAM the drive is using, including the block map's overhead and the fixed-size bitmaps every drive carries (16kb).
+    /// 
+    /// This is an estimate, not a measurement. The block map is a B-tree with room for 11 blocks per node, and nodes are assumed to be about half full, which is what filling a drive in order gives you. That works out to a bit under 480 bytes per block, rather than 258; drives filled in random order come in about 20% lower. Small extras like the bad-sector list and registered observers aren't counted.
+    pub fn memory_usage(&self) -> usize {
+        // 11 keys and values plus a parent pointer, parent index, and length, rounded up
+        const NODE: usize = (11 * (2 + BLOCK_SIZE) + 12).next_multiple_of(8);
+        const PER_NODE: usize = 6;
+        let nodes = self.blocks.len().div_ceil(PER_NODE);
+        // about one node in 6 is an internal node, which also carries 12 child pointers
+        let edges = nodes / PER_NODE * 12 * core::mem::size_of::<usize>();
+        core::mem::size_of::<Avd>() + 2 * BLOCK_COUNT / 8 + nodes * NODE + edges
+    }
+    /// How full the drive is, as a percentage from 0 to 100.
+    pub fn percent_full(&self) -> f64 {
+        self.used_blocks() as f64 / BLOCK_COUNT as f64 * 100.0
+    }
+}
+/// Index the drive by block. Absent blocks read as all zeros.
+impl Index<u16> for Avd {
+    type Output = [u8; 256];
+    fn index(&self, idx: u16) -> &[u8; 256] {
+        static ZERO: [u8; 256] = [0; 256];
+        self.get_block_ref(idx).unwrap_or(&ZERO)
+    }
+}
+/// Mutably index the drive by block. Absent blocks are created, and aren't removed again if they're left all zeros (use `modify_block` for that).
+impl IndexMut<u16> for Avd {
+    fn index_mut(&mut self, idx: u16) -> &mut [u8; 256] {
+        self.get_or_insert_block_mut(idx)
+    }
+}
+/// Parse an archive, like `Avd::from_bytes`.
+impl TryFrom<&[u8]> for Avd {
+    type Error = AvdError;
+    fn try_from(archive: &[u8]) -> Result<Avd> {
+        Avd::from_bytes(archive)
+    }
+}
+/// Turn the drive into its archive, like `Avd::to_bytes`.
+impl From<Avd> for Vec<u8> {
+    fn from(d: Avd) -> Vec<u8> {
+        d.to_bytes()
+    }
 }
-#[derive(Debug, PartialEq)]
-struct Block {
-    idx: u16,
-    data: [u8; 256]
+/// Drives are equal if they hold the same data.
+impl PartialEq for Avd {
+    fn eq(&self, other: &Avd) -> bool {
+        self.blocks.len() == other.blocks.len() && self.blocks.iter().zip(&other.blocks).all(|((ia, a), (ib, b))| {
+            ia == ib && blocks_eq(a, b)
+        })
+    }
 }
-impl PartialOrd for Block {
-    fn partial_cmp(&self, other: &Block) -> Option<Ordering> {
-        Some(self.idx.cmp(&other.idx))
+impl Default for Avd {
+    fn default() -> Avd {
+        Avd::new()
     }
 }
+/// Turn a range of block indices into a plain `start..end` range, so the full drive can be expressed.
+fn block_range(range: impl RangeBounds<u16>) -> Range<usize> {
+    let start = match range.start_bound() {
+        Bound::Included(v) => *v as usize,
+        Bound::Excluded(v) => *v as usize + 1,
+        Bound::Unbounded => 0
+    };
+    let end = match range.end_bound() {
+        Bound::Included(v) => *v as usize + 1,
+        Bound::Excluded(v) => *v as usize,
+        Bound::Unbounded => BLOCK_COUNT
+    };
+    start..end.max(start)
+}
 
-type Result<T> = std::result::Result<T, AvdError>;
+// these get hit for every block during bulk imports and drive comparisons.
+// folding over u64 words without an early exit lets the compiler vectorise them
+fn words(data: &[u8; 256]) -> impl Iterator<Item = u64> + '_ {
+    data.chunks_exact(8).map(|c| u64::from_ne_bytes(c.try_into().unwrap())) // SHOULD NEVER PANIC
+}
+fn is_zero(data: &[u8; 256]) -> bool {
+    words(data).fold(0, |acc, w| acc | w) == 0
+}
+fn blocks_eq(a: &[u8; 256], b: &[u8; 256]) -> bool {
+    words(a).zip(words(b)).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+type Result<T> = core::result::Result<T, AvdError>;
 
 #[derive(Error, Debug)]
 pub enum AvdError {
+    #[cfg(feature = "std")]
     #[error("host fs error")]
     FsError(#[from] std::io::Error),
     #[error("bad file header: {0:02x} {1:02x} {2:02x} {3:02x}")]
     BadHeader(u8, u8, u8, u8),
-    #[error("malformed archive file")] // appears when the data segment is not of length 0 (mod 258)
-    MalformedArchive
+    #[error("malformed archive file")] // appears when the data segment is not of length 0 (mod 258), or the file is cut off
+    MalformedArchive,
+    #[error("unsupported archive version {0}")]
+    UnsupportedVersion(u8),
+    #[error("unknown archive flags: {0:08b}")]
+    UnknownFlags(u8),
+    #[error("checksum mismatch in block {0:04x}")]
+    BadBlockChecksum(u16),
+    #[error("archive checksum mismatch")]
+    BadArchiveChecksum,
+    #[error("not supported for this archive: {0}")]
+    Unsupported(&'static str),
+    #[error("raw image is bigger than the drive")]
+    ImageTooLarge,
+    #[error("couldn't decrypt archive, wrong key or corrupted file")]
+    DecryptionFailed,
+    #[error("unknown required chunk {}", alloc::string::String::from_utf8_lossy(.0))]
+    UnknownChunk([u8; 4]),
+    #[error("bad {0}: {1}")]
+    BadText(&'static str, String),
+    #[error("drive file is locked by another program")]
+    Locked,
+    #[error("drive is write protected")]
+    WriteProtected,
+    #[error("bad sector at block {0:04x}")]
+    BadSector(u16),
+    #[error("no disk in the drive")]
+    NoMedia,
+    #[error("no drive number {0} on this controller")]
+    NoSuchDrive(usize),
+    #[error("invalid disk geometry")]
+    InvalidGeometry,
+    #[error("{component} {value} out of range, can be at most {max}")]
+    ChsOutOfRange {
+        component: &'static str, value: u16, max: u16
+    },
+    #[error("block {0:04x} is different on the drives being merged")]
+    MergeConflict(u16),
+    #[error("no snapshot called {0:?}")]
+    NoSuchSnapshot(String),
+    #[error("backup {0} in the chain doesn't follow on from the one before")]
+    BrokenChain(usize),
 }
 
 #[cfg(test)]
@@ -121,14 +806,197 @@ mod tests {
     fn get_set() {
         let mut drive = Avd::new();
         let mut data = [0; 256];
-        for i in 0..256 { // init with recognisable data
-            data[i] = i as u8
+        for (i, b) in data.iter_mut().enumerate() { // init with recognisable data
+            *b = i as u8
         }
-        drive.set_block(1234, &data);
+        drive.set_block(1234, &data).unwrap();
         assert_eq!(drive.get_block(1234), Some(data));
-        let _ = drive.save("test.avd");
-        let mut drive2 = Avd::new();
-        let _ = drive2.load("test.avd");
-        assert_eq!(drive, drive2)
+        #[cfg(feature = "std")]
+        {
+            let _ = drive.save("test.avd");
+            let mut drive2 = Avd::new();
+            let _ = drive2.load("test.avd");
+            assert_eq!(drive, drive2)
+        }
+    }
+    #[test]
+    fn save_to() {
+        let mut drive = Avd::new();
+        drive.set_block(3, &[3; 256]).unwrap();
+        drive.set_archive_version(ArchiveVersion::V1);
+        let buf = drive.to_bytes();
+        assert_eq!(buf.len(), 5 + 258 + 4);
+        #[cfg(feature = "std")]
+        {
+            let mut streamed = Vec::new();
+            drive.save_to(&mut streamed).unwrap();
+            assert_eq!(streamed, buf);
+            assert_eq!(Avd::read_from(&buf[..]).unwrap(), drive);
+        }
+        let d2 = Avd::from_bytes(&buf).unwrap();
+        assert_eq!((&d2, d2.archive_version()), (&drive, ArchiveVersion::V1));
+        assert!(Avd::from_bytes(&buf[..100]).is_err());
+    }
+    #[test]
+    fn canonical_order() {
+        let mut a = Avd::new();
+        let mut b = Avd::new();
+        for i in 0..50 {
+            a.set_block(i * 7, &[i as u8 + 1; 256]).unwrap();
+            b.set_block((49 - i) * 7, &[50 - i as u8; 256]).unwrap();
+        }
+        b.set_block(1, &[1; 256]).unwrap();
+        b.delete_block(1);
+        assert_eq!(a.to_bytes(), b.to_bytes());
+    }
+    #[test]
+    fn conversions() {
+        let mut drive = Avd::new();
+        drive.set_block(3, &[3; 256]).unwrap();
+        let expected = drive.to_bytes();
+        let bytes: Vec<u8> = drive.into();
+        assert_eq!(bytes, expected);
+        let drive: Avd = bytes[..].try_into().unwrap();
+        assert_eq!(drive.get_block(3), Some([3; 256]));
+        assert!(Avd::try_from(&b"nope"[..]).is_err());
+    }
+    #[test]
+    fn delete() {
+        let mut drive = Avd::new();
+        drive.set_block(1, &[1; 256]).unwrap();
+        drive.set_block(2, &[2; 256]).unwrap();
+        drive.set_block(3, &[3; 256]).unwrap();
+        assert_eq!(drive.delete_block(2), Some([2; 256]));
+        assert_eq!(drive.delete_block(2), None);
+        assert_eq!(drive.get_block(2), None);
+        drive.delete_range(..);
+        assert_eq!(drive, Avd::new())
+    }
+    #[test]
+    fn usage() {
+        let mut drive = Avd::new();
+        drive.set_block(0, &[1; 256]).unwrap();
+        drive.set_block(100, &[1; 256]).unwrap();
+        assert_eq!(drive.used_blocks(), 2);
+        assert!(drive.is_block_used(100) && !drive.is_block_used(99));
+        assert_eq!(drive.free_blocks(), 65534);
+        assert_eq!(drive.bytes_stored(), 512);
+        drive.clear();
+        assert_eq!(drive.used_blocks(), 0);
+        assert!(!drive.is_block_used(100));
+        assert_eq!(drive.percent_full(), 0.0);
+        let empty = drive.memory_usage();
+        assert!(empty > 16384);
+        drive.fill_blocks(..1000, 1);
+        assert!((400..500).contains(&((drive.memory_usage() - empty) / 1000)));
+    }
+    #[test]
+    fn iter() {
+        let mut drive = Avd::new();
+        drive.set_block(5, &[5; 256]).unwrap();
+        drive.set_block(7, &[7; 256]).unwrap();
+        for (_, data) in drive.blocks_mut() {
+            data[0] = 0xff
+        }
+        let v: Vec<_> = drive.blocks().map(|(i, d)| (i, d[0], d[1])).collect();
+        assert_eq!(v, [(5, 0xff, 5), (7, 0xff, 7)]);
+    }
+    #[test]
+    fn mutable_access() {
+        let mut drive = Avd::new();
+        assert_eq!(drive.get_block_mut(3), None);
+        drive.get_or_insert_block_mut(3)[10] = 1;
+        drive.get_block_mut(3).unwrap()[11] = 2;
+        let b = drive.get_block_ref(3).unwrap();
+        assert_eq!((b[9], b[10], b[11]), (0, 1, 2));
+    }
+    #[test]
+    fn modify() {
+        let mut drive = Avd::new();
+        drive.modify_block(8, |b| b[0] = 1);
+        assert_eq!(drive.used_blocks(), 1);
+        let old = drive.modify_block(8, |b| std::mem::replace(&mut b[0], 0));
+        assert_eq!(old, 1);
+        assert_eq!(drive.used_blocks(), 0);
+    }
+    #[test]
+    fn zero_elision() {
+        let mut drive = Avd::new();
+        drive.set_block(1, &[1; 256]).unwrap();
+        drive.set_block(1, &[0; 256]).unwrap();
+        assert_eq!(drive.used_blocks(), 0);
+        drive.set_zero_elision(false);
+        drive.set_block(1, &[0; 256]).unwrap();
+        assert_eq!(drive.used_blocks(), 1);
+        drive.set_block(2, &[2; 256]).unwrap();
+        drive.modify_block(2, |b| b.fill(0));
+        drive.write_bytes(3 * 256, &[0; 4]);
+        drive.fill_blocks(4..6, 0);
+        assert_eq!(drive.used_blocks(), 5);
+        assert_eq!(drive.get_block(2), Some([0; 256]));
+    }
+    #[test]
+    fn write_protect() {
+        let mut drive = Avd::builder().blocks([(1, [1; 256])]).write_protected(true).build().unwrap();
+        assert!(matches!(drive.set_block(1, &[2; 256]), Err(AvdError::WriteProtected)));
+        assert!(matches!(drive.set_blocks(2, &[[2; 256]; 2]), Err(AvdError::WriteProtected)));
+        assert!(matches!(BlockDevice::write_block(&mut drive, 3, &[3; 256]), Err(AvdError::WriteProtected)));
+        assert_eq!(drive.get_block(1), Some([1; 256]));
+        assert_eq!(drive.used_blocks(), 1);
+        drive.set_write_protected(false);
+        drive.set_block(1, &[2; 256]).unwrap();
+        assert_eq!(drive.get_block(1), Some([2; 256]));
+    }
+    #[test]
+    fn word_compare() {
+        let mut a = [0; 256];
+        assert!(is_zero(&a));
+        a[255] = 1;
+        assert!(!is_zero(&a));
+        let mut b = a;
+        assert!(blocks_eq(&a, &b));
+        b[3] = 1;
+        assert!(!blocks_eq(&a, &b));
+    }
+    #[test]
+    fn bulk_set() {
+        let mut drive = Avd::new();
+        drive.set_block(65534, &[1; 256]).unwrap();
+        drive.set_blocks(65533, &[[2; 256], [3; 256], [4; 256]]).unwrap();
+        assert_eq!(drive.used_blocks(), 3);
+        assert_eq!(drive.get_block(65533), Some([2; 256]));
+        assert_eq!(drive.get_block(65534), Some([3; 256]));
+        assert_eq!(drive.get_block(65535), Some([4; 256]));
+    }
+    #[test]
+    fn range_read() {
+        let mut drive = Avd::new();
+        drive.set_block(65535, &[1; 256]).unwrap();
+        assert_eq!(drive.get_blocks(65534..), [[0; 256], [1; 256]]);
+        assert_eq!(drive.get_blocks(..).len(), BLOCK_COUNT);
+        assert!(drive.get_blocks(10..10).is_empty());
+    }
+    #[test]
+    fn copy_move() {
+        let mut drive = Avd::new();
+        drive.set_block(1, &[1; 256]).unwrap();
+        drive.copy_block(1, 2);
+        assert_eq!(drive.get_block(2), Some([1; 256]));
+        drive.move_block(2, 3);
+        assert_eq!(drive.get_block(2), None);
+        assert_eq!(drive.get_block(3), Some([1; 256]));
+        drive.copy_block(4, 1); // copying an empty block clears the destination
+        assert_eq!(drive.get_block(1), None);
+        drive.move_block(3, 3);
+        assert_eq!(drive.get_block(3), Some([1; 256]));
+    }
+    #[test]
+    fn indexing() {
+        let mut drive = Avd::new();
+        assert_eq!(drive[5], [0; 256]);
+        assert_eq!(drive.used_blocks(), 0);
+        drive[5][0] = 0xff;
+        assert_eq!(drive[5][0], 0xff);
+        assert_eq!(drive.used_blocks(), 1);
     }
 }
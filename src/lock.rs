@@ -0,0 +1,79 @@
+//! Advisory locks on drive files, so two programs don't use the same drive at once.
+
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+use crate::{Avd, AvdError, Result};
+
+/// An exclusive lock on a drive file, held until this is dropped. Made with `Avd::lock_file` or `Avd::load_locked`.
+/// 
+/// The lock is taken on a separate `<path>.lock` file next to the drive, rather than the drive itself, so it survives the drive file being replaced and doesn't get in the way of writing it on platforms with stricter locks. The lock file is left behind afterwards. Locks are advisory: they only keep out other programs that also take them.
+#[derive(Debug)]
+pub struct DriveLock {
+    file: File,
+    path: PathBuf,
+}
+impl DriveLock {
+    fn acquire(path: &Path) -> Result<DriveLock> {
+        let mut lock_path = OsString::from(path);
+        lock_path.push(".lock");
+        let file = OpenOptions::new().write(true).create(true).truncate(false).open(&lock_path)?;
+        match file.try_lock() {
+            Ok(()) => Ok(DriveLock {
+                file, path: path.to_owned()
+            }),
+            Err(TryLockError::WouldBlock) => Err(AvdError::Locked),
+            Err(TryLockError::Error(e)) => Err(e.into())
+        }
+    }
+    /// The path of the drive file this lock is for.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+impl Drop for DriveLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+impl Avd {
+    /// Lock the drive file at `path`, without loading it. Fails with `AvdError::Locked` if someone else already has it locked. See [`DriveLock`].
+    pub fn lock_file(path: impl AsRef<Path>) -> Result<DriveLock> {
+        DriveLock::acquire(path.as_ref())
+    }
+    /// Lock the drive file at `path` and load it, keeping the lock until the returned guard is dropped. Save back to the same path while holding it.
+    pub fn load_locked(path: impl AsRef<Path>) -> Result<(Avd, DriveLock)> {
+        let lock = Avd::lock_file(&path)?;
+        Ok((Avd::from_host_drive(path)?, lock))
+    }
+    /// Save the AVD to a file, holding the file's lock while it's written. Use this when you don't already hold the lock; if you do, this will fail with `AvdError::Locked`, so just use `save`.
+    pub fn save_locked(&self, path: impl AsRef<Path>) -> Result<()> {
+        let _lock = Avd::lock_file(&path)?;
+        self.save(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn locking() {
+        let path = std::env::temp_dir().join(format!("avd-lock-{}.avd", std::process::id()));
+        let mut drive = Avd::new();
+        drive.set_block(5, &[5; 256]).unwrap();
+        drive.save_locked(&path).unwrap();
+        let (d, lock) = Avd::load_locked(&path).unwrap();
+        assert_eq!(d, drive);
+        assert_eq!(lock.path(), path);
+        assert!(matches!(Avd::load_locked(&path), Err(AvdError::Locked)));
+        assert!(matches!(drive.save_locked(&path), Err(AvdError::Locked)));
+        d.save(&path).unwrap();
+        drop(lock);
+        drive.save_locked(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut lock_path = path.into_os_string();
+        lock_path.push(".lock");
+        std::fs::remove_file(lock_path).unwrap();
+    }
+}
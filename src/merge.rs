@@ -0,0 +1,71 @@
+//! Combining two drives into one.
+
+use alloc::vec::Vec;
+use crate::{Avd, AvdError, Result};
+
+/// What `Avd::merge` does when both drives have a block, with different contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MergePolicy {
+    /// Keep this drive's block.
+    Ours,
+    /// Take the other drive's block.
+    Theirs,
+    /// Fail with `AvdError::MergeConflict`, without changing anything.
+    Error,
+}
+
+impl Avd {
+    /// Copy every block from `other` that this drive doesn't have. Blocks that both drives have with different contents are settled by `policy`. Blocks only this drive has are left alone.
+    /// 
+    /// Like importing, this is a host-side operation, so it ignores write protection (and observers aren't told). Returns how many blocks were copied in.
+    pub fn merge(&mut self, other: &Avd, policy: MergePolicy) -> Result<usize> {
+        let mut take = Vec::new();
+        for (idx, data) in other.changes_from(self) {
+            let Some(data) = data else {
+                continue // only ours
+            };
+            if self.blocks.contains_key(&idx) {
+                match policy {
+                    MergePolicy::Ours => continue,
+                    MergePolicy::Theirs => {}
+                    MergePolicy::Error => return Err(AvdError::MergeConflict(idx))
+                }
+            }
+            take.push((idx, *data));
+        }
+        for (idx, data) in &take {
+            self.put_block(*idx, data)
+        }
+        Ok(take.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn merge() {
+        let mut base = Avd::new();
+        base.set_block(0, &[1; 256]).unwrap();
+        base.set_block(1, &[1; 256]).unwrap();
+        let mut user = Avd::new();
+        user.set_block(1, &[2; 256]).unwrap();
+        user.set_block(2, &[2; 256]).unwrap();
+        let writes = alloc::sync::Arc::new(core::sync::atomic::AtomicUsize::new(0));
+        let w = writes.clone();
+        base.add_observer(move |_, access| {
+            if access == crate::Access::Write {
+                w.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+            }
+        });
+
+        assert!(matches!(base.merge(&user, MergePolicy::Error), Err(AvdError::MergeConflict(1))));
+        assert_eq!(base.used_blocks(), 2);
+        assert_eq!(base.merge(&user, MergePolicy::Ours).unwrap(), 1);
+        assert_eq!((base.get_block(1), base.get_block(2)), (Some([1; 256]), Some([2; 256])));
+        assert_eq!(base.merge(&user, MergePolicy::Theirs).unwrap(), 1);
+        assert_eq!(base.get_block(1), Some([2; 256]));
+        assert_eq!(base.merge(&user, MergePolicy::Error).unwrap(), 0);
+        assert_eq!(writes.load(core::sync::atomic::Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,136 @@
+//! Descriptive information about a drive that gets saved along with it.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::{Avd, AvdError, Result};
+
+/// A drive's label, timestamps and comment. Kept in the archive header when any of it is set.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Metadata {
+    pub label: String,
+    /// Seconds since the unix epoch, or 0 if not set.
+    pub created: u64,
+    /// Seconds since the unix epoch, or 0 if not set.
+    pub modified: u64,
+    pub comment: String,
+}
+impl Metadata {
+    pub fn is_empty(&self) -> bool {
+        *self == Metadata::default()
+    }
+    /// Encode as a big-endian `u16` label length, the label, the creation and modification times as big-endian `u64`s, then the comment taking up the rest.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut ret = Vec::new();
+        ret.extend((self.label.len() as u16).to_be_bytes());
+        ret.extend(self.label.as_bytes());
+        ret.extend(self.created.to_be_bytes());
+        ret.extend(self.modified.to_be_bytes());
+        ret.extend(self.comment.as_bytes());
+        ret
+    }
+    pub fn decode(data: &[u8]) -> Result<Metadata> {
+        let label_len = u16::from_be_bytes(data.get(..2).ok_or(AvdError::MalformedArchive)?.try_into().unwrap()) as usize; // SHOULD NEVER PANIC
+        let rest = data.get(2 + label_len..).filter(|r| r.len() >= 16).ok_or(AvdError::MalformedArchive)?;
+        let string = |b: &[u8]| String::from_utf8(b.to_vec()).map_err(|_| AvdError::MalformedArchive);
+        Ok(Metadata {
+            label: string(&data[2..2 + label_len])?,
+            created: u64::from_be_bytes(rest[..8].try_into().unwrap()), // SHOULD NEVER PANIC
+            modified: u64::from_be_bytes(rest[8..16].try_into().unwrap()), // SHOULD NEVER PANIC
+            comment: string(&rest[16..])?,
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+fn to_secs(time: Option<SystemTime>) -> u64 {
+    time.and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map_or(0, |d| d.as_secs())
+}
+#[cfg(feature = "std")]
+fn from_secs(secs: u64) -> Option<SystemTime> {
+    (secs != 0).then(|| UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+impl Avd {
+    /// The drive's label, or an empty string if it doesn't have one.
+    pub fn label(&self) -> &str {
+        &self.format.meta.label
+    }
+    /// Set the drive's label. Panics if it's longer than 65535 bytes.
+    /// 
+    /// Setting any metadata means the drive needs archive version 1 to be saved.
+    pub fn set_label(&mut self, label: impl Into<String>) {
+        let label = label.into();
+        assert!(label.len() <= u16::MAX as usize, "label too long");
+        self.format.meta.label = label
+    }
+    /// The drive's comment, or an empty string if it doesn't have one.
+    pub fn comment(&self) -> &str {
+        &self.format.meta.comment
+    }
+    /// Set a free-form comment for the drive.
+    pub fn set_comment(&mut self, comment: impl Into<String>) {
+        self.format.meta.comment = comment.into()
+    }
+    /// When the drive was created, if that's been set.
+    #[cfg(feature = "std")]
+    pub fn created(&self) -> Option<SystemTime> {
+        from_secs(self.format.meta.created)
+    }
+    /// Set when the drive was created. Times are stored to the second, and times before 1970 can't be stored at all.
+    #[cfg(feature = "std")]
+    pub fn set_created(&mut self, time: Option<SystemTime>) {
+        self.format.meta.created = to_secs(time)
+    }
+    /// When the drive was last modified, if that's been set. This isn't updated automatically.
+    #[cfg(feature = "std")]
+    pub fn modified(&self) -> Option<SystemTime> {
+        from_secs(self.format.meta.modified)
+    }
+    /// Set when the drive was last modified. Same limits as `set_created`.
+    #[cfg(feature = "std")]
+    pub fn set_modified(&mut self, time: Option<SystemTime>) {
+        self.format.meta.modified = to_secs(time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "std")]
+    use crate::ArchiveVersion;
+    fn labelled() -> Avd {
+        let mut drive = Avd::new();
+        drive.set_block(3, &[3; 256]).unwrap();
+        drive.set_label("boot disk");
+        drive.set_comment("made for testing ✓");
+        drive
+    }
+    #[test]
+    fn metadata_bytes() {
+        let loaded = Avd::from_bytes(&labelled().to_bytes()).unwrap();
+        assert_eq!((loaded.label(), loaded.comment()), ("boot disk", "made for testing ✓"));
+        assert!(Metadata::decode(&[0, 5, b'a']).is_err());
+        assert_eq!(Metadata::decode(&Metadata::default().encode()).unwrap(), Metadata::default());
+    }
+    #[cfg(feature = "std")]
+    #[test]
+    fn metadata() {
+        let mut drive = labelled();
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        drive.set_created(Some(now));
+        assert_eq!(drive.archive_version(), ArchiveVersion::V1);
+        let mut buf = Vec::new();
+        drive.save_to(&mut buf).unwrap();
+        let loaded = Avd::read_from(&buf[..]).unwrap();
+        assert_eq!(loaded, drive);
+        assert_eq!((loaded.created(), loaded.modified()), (Some(now), None));
+
+        let path = std::env::temp_dir().join(format!("avd-meta-{}.avd", std::process::id()));
+        drive.save(&path).unwrap();
+        assert_eq!(Avd::from_host_drive(&path).unwrap().label(), "boot disk");
+        assert_eq!(Avd::open_lazy(&path).unwrap().get_block(3).unwrap(), Some([3; 256]));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+//! A drive backed directly by a memory-mapped archive file.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+use memmap2::MmapMut;
+use crate::{Avd, AvdError, Result, archive::{Format, MAGIC}};
+
+/// An AVD whose blocks live in a memory-mapped archive file rather than in memory.
+/// 
+/// Archives with block checksums can be opened, and checksums are kept up to date as blocks are written, but they aren't checked when reading. Compressed archives and archives with a whole-archive checksum can't be opened. New files are created as version 0.
+/// 
+/// Writes to existing blocks go straight into the mapping, so opening is cheap and the file is always a valid archive. Call `flush` to make sure changes have hit the disk. Adding or removing blocks resizes the file, which is a lot slower than overwriting.
+#[derive(Debug)]
+pub struct MmapAvd {
+    file: File,
+    map: MmapMut,
+    format: Format,
+    /// Offset of each block's record in the file.
+    index: HashMap<u16, usize>,
+}
+impl MmapAvd {
+    /// Open an archive file, creating an empty one if it doesn't exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<MmapAvd> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        if file.metadata()?.len() == 0 {
+            file.set_len(4)?;
+        }
+        // SAFETY: the file is only changed through this mapping while we're alive. someone else changing it under us is on them
+        let mut map = unsafe { MmapMut::map_mut(&file)? };
+        if map[..] == [0; 4] {
+            map[..3].copy_from_slice(&MAGIC)
+        }
+        let format = Format::parse_header(&map)?;
+        if format.has_checksum() {
+            return Err(AvdError::Unsupported("memory-mapping an archive with a checksum trailer"))
+        }
+        if !format.is_plain() {
+            return Err(AvdError::Unsupported("memory-mapping a compressed or chunked archive"))
+        }
+        let (header_len, record_len) = (format.header_len(), format.record_len());
+        if !(map.len() - header_len).is_multiple_of(record_len) {
+            return Err(AvdError::MalformedArchive)
+        }
+        let mut index = HashMap::new();
+        let mut dupes = Vec::new();
+        for offset in (header_len..map.len()).step_by(record_len) {
+            let idx = u16::from_be_bytes([map[offset], map[offset + 1]]);
+            if let Some(old) = index.insert(idx, offset) {
+                dupes.push(old)
+            }
+        }
+        let mut d = MmapAvd {
+            file, map, format, index
+        };
+        // only the last copy of a block counts, so get rid of the rest to keep the index honest
+        dupes.sort_unstable();
+        for offset in dupes.into_iter().rev() {
+            d.remove_record(offset)?
+        }
+        Ok(d)
+    }
+    /// Get a block from the drive.
+    pub fn get_block(&self, idx: u16) -> Option<[u8; 256]> {
+        self.get_block_ref(idx).copied()
+    }
+    /// Get a reference to a block inside the mapping.
+    pub fn get_block_ref(&self, idx: u16) -> Option<&[u8; 256]> {
+        let offset = self.index.get(&idx)? + 2;
+        Some(self.map[offset..offset + 256].try_into().unwrap()) // SHOULD NEVER PANIC
+    }
+    /// Set a block inside the drive. Writing to a block that isn't already present grows the file.
+    pub fn set_block(&mut self, idx: u16, data: &[u8; 256]) -> Result<()> {
+        let len = self.format.record_len();
+        let offset = match self.index.get(&idx) {
+            Some(v) => *v,
+            None => {
+                let offset = self.map.len();
+                self.resize(offset + len)?;
+                self.index.insert(idx, offset);
+                offset
+            }
+        };
+        self.format.write_record(&mut self.map[offset..offset + len], idx, data);
+        Ok(())
+    }
+    /// Remove a block from the drive, shrinking the file.
+    pub fn delete_block(&mut self, idx: u16) -> Result<()> {
+        match self.index.remove(&idx) {
+            Some(offset) => self.remove_record(offset),
+            None => Ok(())
+        }
+    }
+    /// The number of blocks stored on the drive.
+    pub fn used_blocks(&self) -> usize {
+        self.index.len()
+    }
+    /// Flush outstanding changes to the disk.
+    pub fn flush(&self) -> Result<()> {
+        self.map.flush()?;
+        Ok(())
+    }
+    /// Copy the whole drive into memory.
+    pub fn to_avd(&self) -> Avd {
+        let mut d = Avd::new();
+        for idx in self.index.keys() {
+            d.store_block(*idx, self.get_block_ref(*idx).unwrap()) // SHOULD NEVER PANIC
+        }
+        d
+    }
+    /// Remove the record at `offset` by moving the last record into its place.
+    fn remove_record(&mut self, offset: usize) -> Result<()> {
+        let len = self.format.record_len();
+        let last = self.map.len() - len;
+        if offset != last {
+            self.map.copy_within(last..last + len, offset);
+            let moved = u16::from_be_bytes([self.map[offset], self.map[offset + 1]]);
+            self.index.insert(moved, offset);
+        }
+        self.resize(last)
+    }
+    fn resize(&mut self, len: usize) -> Result<()> {
+        self.map.flush()?;
+        self.file.set_len(len as u64)?;
+        // SAFETY: as in `open`
+        self.map = unsafe { MmapMut::map_mut(&self.file)? };
+        Ok(())
+    }
+}
+
+impl Avd {
+    /// Open an archive file as a memory-mapped drive, creating it if it doesn't exist. See [`MmapAvd`].
+    pub fn open_mmap(path: impl AsRef<Path>) -> Result<MmapAvd> {
+        MmapAvd::open(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn mmap() {
+        let path = std::env::temp_dir().join(format!("avd-mmap-{}.avd", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut m = Avd::open_mmap(&path).unwrap();
+        m.set_block(1, &[1; 256]).unwrap();
+        m.set_block(2, &[2; 256]).unwrap();
+        m.set_block(3, &[3; 256]).unwrap();
+        m.set_block(1, &[4; 256]).unwrap();
+        m.delete_block(1).unwrap();
+        m.flush().unwrap();
+        assert_eq!(m.get_block(3), Some([3; 256]));
+        let d = Avd::from_host_drive(&path).unwrap();
+        assert_eq!(d, m.to_avd());
+        assert_eq!(d.used_blocks(), 2);
+        drop(m);
+        let _ = std::fs::remove_file(path);
+    }
+}
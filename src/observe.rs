@@ -0,0 +1,120 @@
+//! Hooks for watching accesses to a drive, for debuggers and activity lights.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt;
+use crate::Avd;
+
+/// What kind of access an [`Observer`] is being told about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Access {
+    /// A block was read.
+    Read,
+    /// A block was written, or handed out for writing with `get_block_mut`.
+    Write,
+    /// A block was removed.
+    Delete,
+}
+
+/// Something that wants to hear about every access to a drive. Closures taking `(u16, Access)` work too.
+/// 
+/// Observers get called from `&self` methods like `get_block`, so they can't take `&mut self`. Use a `Cell`, atomic, or mutex for any state.
+pub trait Observer: Send + Sync {
+    /// Called with the index of the block and what's being done to it.
+    fn on_access(&self, idx: u16, access: Access);
+}
+impl<F: Fn(u16, Access) + Send + Sync> Observer for F {
+    fn on_access(&self, idx: u16, access: Access) {
+        self(idx, access)
+    }
+}
+
+/// A handle to a registered observer, for removing it again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObserverId(u64);
+
+/// The observers registered on a drive.
+#[derive(Default)]
+pub(crate) struct Observers {
+    list: Vec<(ObserverId, Box<dyn Observer>)>,
+    next: u64,
+}
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Observers({})", self.list.len())
+    }
+}
+
+impl Avd {
+    /// Register an observer, to be called on every block read, write, and removal. Returns a handle for `remove_observer`.
+    /// 
+    /// Observers see accesses through `get_block`, `set_block`, `delete_block` and everything built on them, including [`BlockDevice`](crate::BlockDevice) and DMA transfers. Bulk operations like `clear`, `delete_range`, loading, and iterating over blocks aren't reported.
+    pub fn add_observer(&mut self, o: impl Observer + 'static) -> ObserverId {
+        let id = ObserverId(self.observers.next);
+        self.observers.next += 1;
+        self.observers.list.push((id, Box::new(o)));
+        id
+    }
+    /// Unregister an observer. Returns `false` if it wasn't registered.
+    pub fn remove_observer(&mut self, id: ObserverId) -> bool {
+        let len = self.observers.list.len();
+        self.observers.list.retain(|(i, _)| *i != id);
+        self.observers.list.len() != len
+    }
+    /// Tell every observer about an access.
+    pub(crate) fn notify(&self, idx: u16, access: Access) {
+        #[cfg(feature = "stats")]
+        self.counters.count(idx, access);
+        #[cfg(feature = "std")]
+        match access {
+            Access::Read => self.metrics.read(),
+            Access::Write => self.metrics.write(),
+            Access::Delete => {}
+        }
+        for (_, o) in &self.observers.list {
+            o.on_access(idx, access)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::sync::Arc;
+    use std::sync::Mutex;
+    #[test]
+    fn observers() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut drive = Avd::new();
+        let l = log.clone();
+        let id = drive.add_observer(move |idx, access| l.lock().unwrap().push((idx, access)));
+        drive.set_block(0, &[1; 256]).unwrap();
+        drive.get_block(0);
+        drive.set_block(0, &[0; 256]).unwrap();
+        drive.delete_block(5);
+        drive.modify_block(2, |b| b[0] = 0);
+        assert_eq!(*log.lock().unwrap(), [
+            (0, Access::Write), (0, Access::Read), (0, Access::Write), (5, Access::Delete), (2, Access::Write)
+        ]);
+        assert!(drive.remove_observer(id));
+        assert!(!drive.remove_observer(id));
+        drive.get_block(0);
+        assert_eq!(log.lock().unwrap().len(), 5);
+    }
+    #[test]
+    fn writes_dont_read() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut drive = Avd::new();
+        let l = log.clone();
+        drive.add_observer(move |idx, access| l.lock().unwrap().push((idx, access)));
+        drive.write_bytes(256, &[1, 2, 3]);
+        drive.write_bytes(512, &[0; 4]);
+        assert_eq!(*log.lock().unwrap(), [(1, Access::Write)]);
+
+        let base = Arc::new(drive);
+        let mut overlay = base.overlay();
+        overlay.set_block(1, &[4; 256]).unwrap();
+        overlay.delete_block(7);
+        assert_eq!(log.lock().unwrap().len(), 1);
+    }
+}
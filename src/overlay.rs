@@ -0,0 +1,170 @@
+//! Copy-on-write drives layered over a shared base image.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use crate::{Avd, AvdError, Result, is_zero};
+
+/// A writable layer over a read-only base drive, for running lots of machines from one golden image.
+/// 
+/// Reads of blocks that haven't been touched fall through to the base. Writes and removals only go into the overlay, so the base is never changed and can be shared (through the `Arc`) by as many overlays as you like. When you're done, `commit` the changes to a drive of their own, or `discard` them.
+/// 
+/// Blocks marked bad on the base are bad in the overlay too. The base's write-protect tab is ignored, since overlays never write to it; overlays have their own.
+#[derive(Debug)]
+pub struct OverlayAvd {
+    base: Arc<Avd>,
+    /// Blocks that differ from the base. `None` means the block was removed, hiding the base's copy.
+    upper: BTreeMap<u16, Option<[u8; 256]>>,
+    write_protected: bool,
+}
+impl OverlayAvd {
+    /// Create an overlay with no changes over `base`.
+    pub fn new(base: Arc<Avd>) -> OverlayAvd {
+        OverlayAvd {
+            base, upper: BTreeMap::new(), write_protected: false
+        }
+    }
+    /// The drive underneath the overlay.
+    pub fn base(&self) -> &Arc<Avd> {
+        &self.base
+    }
+    /// Get a block, from the overlay if it's been changed there, or else from the base.
+    pub fn get_block(&self, idx: u16) -> Option<[u8; 256]> {
+        match self.upper.get(&idx) {
+            Some(b) => *b,
+            None => self.base.get_block(idx)
+        }
+    }
+    /// Check if a block is present, looking through to the base.
+    pub fn is_block_used(&self, idx: u16) -> bool {
+        match self.upper.get(&idx) {
+            Some(b) => b.is_some(),
+            None => self.base.is_block_used(idx)
+        }
+    }
+    /// Set a block in the overlay. Setting a block to all zeros removes it.
+    /// 
+    /// Fails with `AvdError::WriteProtected` if the overlay is write protected, or `AvdError::BadSector` if the block is marked bad on the base.
+    pub fn set_block(&mut self, idx: u16, data: &[u8; 256]) -> Result<()> {
+        if self.write_protected {
+            return Err(AvdError::WriteProtected)
+        }
+        self.base.check_bad(idx)?;
+        if is_zero(data) {
+            self.delete_block(idx);
+        }
+        else {
+            self.put(idx, Some(*data))
+        }
+        Ok(())
+    }
+    /// Remove a block, hiding the base's copy if it has one. Returns the old contents.
+    pub fn delete_block(&mut self, idx: u16) -> Option<[u8; 256]> {
+        let ret = match self.upper.get(&idx) {
+            Some(b) => *b,
+            None => self.base.blocks.get(&idx).copied() // not a guest read, like in `put`
+        };
+        self.put(idx, None);
+        ret
+    }
+    /// Record a change, dropping it again if it leaves the block the same as the base.
+    fn put(&mut self, idx: u16, data: Option<[u8; 256]>) {
+        // not a guest read, so the base's observers don't hear about it
+        if self.base.blocks.get(&idx) == data.as_ref() {
+            self.upper.remove(&idx);
+        }
+        else {
+            self.upper.insert(idx, data);
+        }
+    }
+    /// Check if a block differs from the base.
+    pub fn is_modified(&self, idx: u16) -> bool {
+        self.upper.contains_key(&idx)
+    }
+    /// The indices of every block that differs from the base, in order.
+    pub fn modified_blocks(&self) -> Vec<u16> {
+        self.upper.keys().copied().collect()
+    }
+    /// Set or clear the overlay's write-protect tab, like `Avd::set_write_protected`.
+    pub fn set_write_protected(&mut self, protected: bool) {
+        self.write_protected = protected
+    }
+    /// Whether the overlay is write protected.
+    pub fn is_write_protected(&self) -> bool {
+        self.write_protected
+    }
+    /// Throw away every change, so the overlay matches the base again.
+    pub fn discard(&mut self) {
+        self.upper.clear()
+    }
+    /// Write the overlay's changes to `drive`, which is usually a copy of the base. Like the host-side helpers on `Avd`, this ignores write protection.
+    pub fn apply_to(&self, drive: &mut Avd) {
+        for (idx, data) in &self.upper {
+            match data {
+                Some(data) => drive.store_block(*idx, data),
+                None => {
+                    drive.delete_block(*idx);
+                }
+            }
+        }
+    }
+    /// Merge the changes into the base, giving a normal drive with the same contents as the overlay. If no one else is holding the base, it's reused; otherwise it's copied first.
+    pub fn commit(self) -> Avd {
+        let mut drive = Arc::try_unwrap(self.base).unwrap_or_else(|base| base.duplicate());
+        for (idx, data) in self.upper {
+            match data {
+                Some(data) => drive.store_block(idx, &data),
+                None => {
+                    drive.delete_block(idx);
+                }
+            }
+        }
+        drive
+    }
+}
+
+impl Avd {
+    /// Copy the drive's blocks, archive settings and bad sectors into a new drive.
+    pub(crate) fn duplicate(&self) -> Avd {
+        let records: Vec<_> = self.blocks.iter().map(|(idx, data)| (*idx, *data)).collect();
+        let mut d = Avd::new();
+        d.replace_contents(self.format.clone(), &records);
+        d
+    }
+    /// Put a copy-on-write overlay over a shared handle to the drive. See [`OverlayAvd`].
+    pub fn overlay(self: &Arc<Avd>) -> OverlayAvd {
+        OverlayAvd::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn overlay() {
+        let mut golden = Avd::new();
+        golden.set_block(0, &[1; 256]).unwrap();
+        golden.set_block(1, &[2; 256]).unwrap();
+        let golden = Arc::new(golden);
+        let mut a = golden.overlay();
+        let mut b = golden.overlay();
+        a.set_block(0, &[9; 256]).unwrap();
+        a.delete_block(1);
+        a.set_block(2, &[3; 256]).unwrap();
+        assert_eq!((a.get_block(0), a.get_block(1), a.get_block(2)), (Some([9; 256]), None, Some([3; 256])));
+        assert_eq!(b.get_block(0), Some([1; 256]));
+        assert_eq!(a.modified_blocks(), [0, 1, 2]);
+        a.set_block(0, &[1; 256]).unwrap();
+        assert!(!a.is_modified(0));
+
+        b.set_block(5, &[5; 256]).unwrap();
+        b.discard();
+        assert!(!b.is_block_used(5));
+        drop(b);
+        let committed = a.commit();
+        assert_eq!(committed.get_block(0), Some([1; 256]));
+        assert!(!committed.is_block_used(1));
+        assert_eq!(committed.used_blocks(), 2);
+        assert_eq!(golden.used_blocks(), 2);
+    }
+}
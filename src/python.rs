@@ -0,0 +1,146 @@
+//! Python bindings, as a module called `avdrive`.
+//! 
+//! Build a wheel with maturin, adding `--features python,pyo3/extension-module`.
+
+use pyo3::prelude::*;
+use pyo3::buffer::PyBuffer;
+use pyo3::exceptions::{PyOSError, PyPermissionError, PyValueError};
+use pyo3::types::PyBytes;
+use crate::AvdError;
+
+impl From<AvdError> for PyErr {
+    fn from(e: AvdError) -> PyErr {
+        match e {
+            AvdError::FsError(e) => PyOSError::new_err(e.to_string()),
+            AvdError::WriteProtected => PyPermissionError::new_err(e.to_string()),
+            AvdError::BadSector(_) => PyOSError::new_err(e.to_string()),
+            e => PyValueError::new_err(e.to_string())
+        }
+    }
+}
+
+/// Read 256 bytes out of anything that supports the buffer protocol.
+fn block_from_buffer(py: Python<'_>, buf: &PyBuffer<u8>) -> PyResult<[u8; 256]> {
+    if buf.item_count() != 256 {
+        return Err(PyValueError::new_err(format!("blocks are 256 bytes, got {}", buf.item_count())))
+    }
+    let mut data = [0; 256];
+    buf.copy_to_slice(py, &mut data)?;
+    Ok(data)
+}
+
+/// An AVC2 virtual drive: 65536 blocks of 256 bytes.
+#[pyclass(name = "Avd", module = "avdrive")]
+struct PyAvd(crate::Avd);
+
+#[pymethods]
+impl PyAvd {
+    #[new]
+    fn new() -> PyAvd {
+        PyAvd(crate::Avd::new())
+    }
+    /// Load a drive from an archive file.
+    #[staticmethod]
+    fn load(path: &str) -> PyResult<PyAvd> {
+        Ok(PyAvd(crate::Avd::from_host_drive(path)?))
+    }
+    /// Load a drive from archive bytes, or anything else that supports the buffer protocol.
+    #[staticmethod]
+    fn from_bytes(py: Python<'_>, archive: PyBuffer<u8>) -> PyResult<PyAvd> {
+        Ok(PyAvd(crate::Avd::from_bytes(&archive.to_vec(py)?)?))
+    }
+    /// Save the drive to an archive file.
+    fn save(&self, path: &str) -> PyResult<()> {
+        Ok(self.0.save(path)?)
+    }
+    /// The drive's archive, as bytes.
+    fn to_bytes<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.0.to_bytes())
+    }
+    /// Get a block as bytes, or None if it isn't present.
+    fn get_block<'py>(&self, py: Python<'py>, idx: u16) -> Option<Bound<'py, PyBytes>> {
+        self.0.get_block_ref(idx).map(|data| PyBytes::new(py, data))
+    }
+    /// Set a block from 256 bytes of anything that supports the buffer protocol (bytes, bytearray, memoryview, array...).
+    fn set_block(&mut self, py: Python<'_>, idx: u16, data: PyBuffer<u8>) -> PyResult<()> {
+        let data = block_from_buffer(py, &data)?;
+        Ok(self.0.set_block(idx, &data)?)
+    }
+    /// Remove a block. Returns whether it was present.
+    fn delete_block(&mut self, idx: u16) -> bool {
+        self.0.delete_block(idx).is_some()
+    }
+    /// Check if a block is present.
+    fn is_block_used(&self, idx: u16) -> bool {
+        self.0.is_block_used(idx)
+    }
+    /// The number of blocks stored on the drive.
+    fn used_blocks(&self) -> usize {
+        self.0.used_blocks()
+    }
+    /// Read bytes from the drive by flat byte address.
+    fn read_bytes<'py>(&self, py: Python<'py>, addr: u32, len: usize) -> PyResult<Bound<'py, PyBytes>> {
+        if addr as usize + len > crate::DRIVE_SIZE {
+            return Err(PyValueError::new_err("read goes past the end of the drive"))
+        }
+        let mut buf = vec![0; len];
+        self.0.read_bytes(addr, &mut buf);
+        Ok(PyBytes::new(py, &buf))
+    }
+    /// Write anything that supports the buffer protocol to the drive by flat byte address.
+    fn write_bytes(&mut self, py: Python<'_>, addr: u32, data: PyBuffer<u8>) -> PyResult<()> {
+        let data = data.to_vec(py)?;
+        if addr as usize + data.len() > crate::DRIVE_SIZE {
+            return Err(PyValueError::new_err("write goes past the end of the drive"))
+        }
+        self.0.write_bytes(addr, &data);
+        Ok(())
+    }
+    fn __len__(&self) -> usize {
+        self.0.used_blocks()
+    }
+    fn __eq__(&self, other: &PyAvd) -> bool {
+        self.0 == other.0
+    }
+}
+
+#[pymodule]
+fn avdrive(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyAvd>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn python() {
+        pyo3::append_to_inittab!(avdrive);
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            py.run(cr#"
+import avdrive
+d = avdrive.Avd()
+d.set_block(5, bytearray([5]) * 256)
+d.set_block(6, memoryview(b"\x06" * 256))
+assert d.get_block(5) == b"\x05" * 256
+assert d.get_block(7) is None
+assert len(d) == 2
+d.write_bytes(5 * 256 + 1, b"hi")
+assert d.read_bytes(5 * 256, 4) == b"\x05hi\x05"
+d2 = avdrive.Avd.from_bytes(d.to_bytes())
+assert d2 == d
+assert d.delete_block(6) and not d.is_block_used(6)
+try:
+    d.set_block(1, b"short")
+    raise AssertionError("short block accepted")
+except ValueError:
+    pass
+try:
+    avdrive.Avd.from_bytes(b"nope")
+    raise AssertionError("bad archive accepted")
+except ValueError:
+    pass
+"#, None, None).unwrap();
+        });
+    }
+}
@@ -0,0 +1,183 @@
+//! Flat, non-sparse disk images, for tools that don't understand the archive format.
+
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::fs::File;
+#[cfg(feature = "std")]
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+#[cfg(feature = "std")]
+use std::path::Path;
+#[cfg(feature = "std")]
+use crate::{archive::read_full, BLOCK_COUNT};
+use crate::{Avd, AvdError, Result, is_zero, BLOCK_SIZE, DRIVE_SIZE};
+
+impl Avd {
+    /// Get the whole drive as a flat 16mb image, with absent blocks filled with zeros.
+    pub fn to_raw_image(&self) -> Vec<u8> {
+        let mut ret = vec![0; DRIVE_SIZE];
+        for (idx, data) in self.blocks() {
+            let start = idx as usize * BLOCK_SIZE;
+            ret[start..start + BLOCK_SIZE].copy_from_slice(data)
+        }
+        ret
+    }
+    /// Write the whole drive out as a flat 16mb image, with absent blocks filled with zeros. Like the other host-side exports, this isn't reported to observers.
+    #[cfg(feature = "std")]
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn write_raw(&self, mut w: impl Write) -> Result<()> {
+        static ZERO: [u8; 256] = [0; 256];
+        let mut next = 0;
+        for (idx, data) in &self.blocks {
+            for _ in next..*idx as usize {
+                w.write_all(&ZERO)?;
+            }
+            w.write_all(data)?;
+            next = *idx as usize + 1;
+        }
+        for _ in next..BLOCK_COUNT {
+            w.write_all(&ZERO)?;
+        }
+        w.flush()?;
+        Ok(())
+    }
+    /// Export the whole drive to a flat 16mb image file.
+    #[cfg(feature = "std")]
+    pub fn export_raw(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.write_raw(BufWriter::new(File::create(path)?))
+    }
+    /// Save the drive as a flat 16mb image file, like `export_raw`, but skip over absent blocks instead of writing zeros. On filesystems with sparse file support, the holes take up no disk space.
+    #[cfg(feature = "std")]
+    pub fn save_sparse(&self, path: impl AsRef<Path>) -> Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        let mut pos = 0;
+        for (idx, data) in self.blocks() {
+            let start = idx as u64 * BLOCK_SIZE as u64;
+            if start != pos {
+                w.seek(SeekFrom::Start(start))?;
+            }
+            w.write_all(data)?;
+            pos = start + BLOCK_SIZE as u64;
+        }
+        let f = w.into_inner().map_err(|e| e.into_error())?;
+        f.set_len(DRIVE_SIZE as u64)?; // makes a hole at the end too, if the last block was absent
+        Ok(())
+    }
+    /// Replace the contents of the drive with a flat image read from `r`. All-zero blocks are skipped, so the drive stays sparse.
+    /// 
+    /// Images shorter than 16mb are treated as if the rest was zeros, and a partial last block is padded with zeros. Longer images are rejected. The whole image is read before anything is replaced, so if reading fails, the drive is left as it was.
+    #[cfg(feature = "std")]
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn read_raw(&mut self, mut r: impl Read) -> Result<()> {
+        let mut records = Vec::new();
+        let mut buf = [0; 256];
+        for idx in 0..=BLOCK_COUNT {
+            let n = read_full(&mut r, &mut buf)?;
+            if n == 0 {
+                break
+            }
+            if idx == BLOCK_COUNT {
+                return Err(AvdError::ImageTooLarge)
+            }
+            buf[n..].fill(0);
+            if !is_zero(&buf) {
+                records.push((idx as u16, buf))
+            }
+        }
+        self.clear();
+        for (idx, data) in records {
+            self.store_block(idx, &data)
+        }
+        Ok(())
+    }
+    /// Replace the contents of the drive with a flat image, skipping all-zero blocks. See `read_raw` for how odd sizes are handled.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(len = image.len())))]
+    pub fn import_raw(&mut self, image: &[u8]) -> Result<()> {
+        if image.len() > DRIVE_SIZE {
+            return Err(AvdError::ImageTooLarge)
+        }
+        self.clear();
+        for (idx, chunk) in image.chunks(BLOCK_SIZE).enumerate() {
+            let mut buf = [0; 256];
+            buf[..chunk.len()].copy_from_slice(chunk);
+            if !is_zero(&buf) {
+                self.store_block(idx as u16, &buf)
+            }
+        }
+        Ok(())
+    }
+    /// Load a new AVD from a flat image file, skipping all-zero blocks. See `read_raw` for how odd sizes are handled.
+    #[cfg(feature = "std")]
+    pub fn from_raw_image(path: impl AsRef<Path>) -> Result<Avd> {
+        let mut d = Avd::new();
+        d.read_raw(BufReader::new(File::open(path)?))?;
+        Ok(d)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn raw_image() {
+        let mut drive = Avd::new();
+        drive.set_block(1, &[1; 256]).unwrap();
+        drive.set_block(65535, &[2; 256]).unwrap();
+        let image = drive.to_raw_image();
+        assert_eq!(image.len(), DRIVE_SIZE);
+        assert_eq!(image[255..258], [0, 1, 1]);
+        assert_eq!(image[DRIVE_SIZE - 1], 2);
+        #[cfg(feature = "std")]
+        {
+            let mut streamed = Vec::new();
+            drive.write_raw(&mut streamed).unwrap();
+            assert_eq!(streamed, image);
+        }
+        let mut d2 = Avd::new();
+        d2.import_raw(&image).unwrap();
+        assert_eq!(d2, drive);
+        assert_eq!(d2.used_blocks(), 2);
+
+        #[cfg(feature = "std")]
+        {
+            let path = std::env::temp_dir().join(format!("avd-sparse-{}.img", std::process::id()));
+            drive.delete_block(65535);
+            drive.set_block(3, &[3; 256]).unwrap();
+            drive.save_sparse(&path).unwrap();
+            assert_eq!(std::fs::read(&path).unwrap(), drive.to_raw_image());
+            assert_eq!(Avd::from_raw_image(&path).unwrap(), drive);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+    #[test]
+    fn odd_sizes() {
+        let mut drive = Avd::new();
+        drive.import_raw(&[0xaa; 300]).unwrap();
+        assert_eq!(drive.used_blocks(), 2);
+        let b = drive.get_block(1).unwrap();
+        assert_eq!((b[43], b[44]), (0xaa, 0));
+        assert!(matches!(drive.import_raw(&vec![0; DRIVE_SIZE + 1]), Err(AvdError::ImageTooLarge)));
+    }
+    #[cfg(feature = "std")]
+    #[test]
+    fn failed_read() {
+        struct Broken(usize);
+        impl Read for Broken {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                if self.0 == 0 {
+                    return Err(std::io::Error::other("pulled the plug"))
+                }
+                let n = buf.len().min(self.0);
+                buf[..n].fill(0x55);
+                self.0 -= n;
+                Ok(n)
+            }
+        }
+        let mut drive = Avd::new();
+        drive.set_block(7, &[7; 256]).unwrap();
+        assert!(matches!(drive.read_raw(Broken(1000)), Err(AvdError::FsError(_))));
+        assert!(matches!(drive.read_raw(&vec![1; DRIVE_SIZE + 1][..]), Err(AvdError::ImageTooLarge)));
+        assert_eq!(drive.used_blocks(), 1);
+        assert_eq!(drive.get_block(7), Some([7; 256]));
+    }
+}
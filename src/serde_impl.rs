@@ -0,0 +1,88 @@
+//! Serde support, writing a drive as a map from block index to block data.
+
+use core::fmt;
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::{self, MapAccess, SeqAccess, Visitor};
+use serde::ser::SerializeMap;
+use crate::{Avd, BLOCK_SIZE};
+
+/// Only blocks that are present get written, so an empty drive is an empty map. Data is written as bytes, which formats like JSON turn into an array of numbers.
+impl Serialize for Avd {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let mut map = s.serialize_map(Some(self.used_blocks()))?;
+        for (idx, data) in self.blocks() {
+            map.serialize_entry(&idx, &BlockBytes(*data))?
+        }
+        map.end()
+    }
+}
+impl<'de> Deserialize<'de> for Avd {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Avd, D::Error> {
+        d.deserialize_map(AvdVisitor)
+    }
+}
+
+struct AvdVisitor;
+impl<'de> Visitor<'de> for AvdVisitor {
+    type Value = Avd;
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a map of block indices to blocks")
+    }
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Avd, A::Error> {
+        let mut d = Avd::new();
+        while let Some((idx, BlockBytes(data))) = map.next_entry::<u16, BlockBytes>()? {
+            d.store_block(idx, &data)
+        }
+        Ok(d)
+    }
+}
+
+/// One block's data, as bytes rather than a 256-element tuple.
+struct BlockBytes([u8; 256]);
+impl Serialize for BlockBytes {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_bytes(&self.0)
+    }
+}
+impl<'de> Deserialize<'de> for BlockBytes {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<BlockBytes, D::Error> {
+        d.deserialize_bytes(BlockVisitor)
+    }
+}
+
+struct BlockVisitor;
+impl<'de> Visitor<'de> for BlockVisitor {
+    type Value = BlockBytes;
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("256 bytes of block data")
+    }
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<BlockBytes, E> {
+        v.try_into().map(BlockBytes).map_err(|_| E::invalid_length(v.len(), &self))
+    }
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<BlockBytes, A::Error> {
+        let mut data = [0; 256];
+        for (i, b) in data.iter_mut().enumerate() {
+            *b = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(i, &self))?
+        }
+        if seq.next_element::<u8>()?.is_some() {
+            return Err(de::Error::invalid_length(BLOCK_SIZE + 1, &self))
+        }
+        Ok(BlockBytes(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn serde() {
+        let mut drive = Avd::new();
+        drive.set_block(12, &[1; 256]).unwrap();
+        drive.set_block(40000, &[2; 256]).unwrap();
+        let json = serde_json::to_string(&drive).unwrap();
+        assert!(json.starts_with("{\"12\":[1,1,"));
+        assert_eq!(serde_json::from_str::<Avd>(&json).unwrap(), drive);
+        assert_eq!(serde_json::to_string(&Avd::new()).unwrap(), "{}");
+        assert!(serde_json::from_str::<Avd>("{\"1\":[1,2,3]}").is_err());
+    }
+}
@@ -0,0 +1,160 @@
+//! A shared drive split across many locks, so readers on different threads don't get in each other's way.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use crate::{Avd, AvdError, Result, archive::Format, is_zero};
+
+/// The number of shards blocks are spread over. Neighbouring blocks land in different shards, so sequential reads and writes move from lock to lock.
+const SHARDS: usize = 64;
+
+/// A lock on one shard's blocks, kept on its own cache line so busy shards don't slow down their neighbours.
+#[derive(Debug, Default)]
+#[repr(align(64))]
+struct Shard(RwLock<BTreeMap<u16, [u8; 256]>>);
+
+/// A handle to an AVD shared between threads, like [`crate::SharedAvd`], but with the blocks split over 64 separate locks.
+/// 
+/// Accesses to blocks in different shards never touch the same lock, so two CPUs reading the drive at once don't serialise on it, and a write only holds up accesses to its own shard. The catch is that there's no way to lock the whole drive for a group of operations. `to_avd` and `save` do take every shard at once, so they always see a consistent drive.
+#[derive(Debug, Clone)]
+pub struct ShardedAvd {
+    shards: Arc<[Shard]>,
+    format: Format,
+    elide_zeros: bool,
+    write_protected: bool,
+    backups: u32,
+}
+impl ShardedAvd {
+    /// Spread a drive over the shards. The drive's settings are kept for when it's turned back into an [`Avd`].
+    pub fn new(drive: Avd) -> ShardedAvd {
+        let d = ShardedAvd {
+            shards: (0..SHARDS).map(|_| Shard::default()).collect(),
+            format: drive.format.clone(),
+            elide_zeros: drive.elide_zeros,
+            write_protected: drive.write_protected,
+            backups: drive.backups
+        };
+        for (idx, data) in drive {
+            d.shard_mut(idx).insert(idx, data);
+        }
+        d
+    }
+    /// Get a block from the drive. This only takes a shared lock on the block's shard.
+    pub fn get_block(&self, idx: u16) -> Option<[u8; 256]> {
+        self.shard(idx).get(&idx).copied()
+    }
+    /// Set a block inside the drive. Fails if the drive was write protected, or the block was marked bad, when it was shared.
+    pub fn set_block(&self, idx: u16, data: &[u8; 256]) -> Result<()> {
+        if self.write_protected {
+            return Err(AvdError::WriteProtected)
+        }
+        self.check_bad(idx)?;
+        let mut shard = self.shard_mut(idx);
+        if self.elide_zeros && is_zero(data) {
+            shard.remove(&idx);
+        }
+        else {
+            shard.insert(idx, *data);
+        }
+        Ok(())
+    }
+    /// Remove a block from the drive, returning its data if it was present.
+    pub fn delete_block(&self, idx: u16) -> Option<[u8; 256]> {
+        self.shard_mut(idx).remove(&idx)
+    }
+    /// Change a block in place, without another thread getting in between the read and the write. See `Avd::modify_block`.
+    pub fn modify_block<R>(&self, idx: u16, f: impl FnOnce(&mut [u8; 256]) -> R) -> R {
+        let mut shard = self.shard_mut(idx);
+        let data = shard.entry(idx).or_insert([0; 256]);
+        let ret = f(data);
+        if self.elide_zeros && is_zero(data) {
+            shard.remove(&idx);
+        }
+        ret
+    }
+    /// Check if a block is present.
+    pub fn is_block_used(&self, idx: u16) -> bool {
+        self.shard(idx).contains_key(&idx)
+    }
+    /// The number of blocks stored on the drive. Other threads can change this while it's being counted.
+    pub fn used_blocks(&self) -> usize {
+        self.shards.iter().map(|s| lock_read(s).len()).sum()
+    }
+    /// Copy the whole drive into a normal AVD.
+    pub fn to_avd(&self) -> Avd {
+        let guards: Vec<_> = self.shards.iter().map(lock_read).collect();
+        let mut d = Avd::new();
+        d.format = self.format.clone();
+        d.elide_zeros = self.elide_zeros;
+        d.write_protected = self.write_protected;
+        d.backups = self.backups;
+        d.extend(guards.iter().flat_map(|g| g.iter().map(|(idx, data)| (*idx, *data))));
+        d
+    }
+    /// Save the drive to a file, by way of `to_avd`.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.to_avd().save(path)
+    }
+    /// Fail if `idx` was marked bad when the drive was shared.
+    pub(crate) fn check_bad(&self, idx: u16) -> Result<()> {
+        match self.format.bad.contains(&idx) {
+            true => Err(AvdError::BadSector(idx)),
+            false => Ok(())
+        }
+    }
+    fn shard(&self, idx: u16) -> RwLockReadGuard<'_, BTreeMap<u16, [u8; 256]>> {
+        lock_read(&self.shards[idx as usize % SHARDS])
+    }
+    fn shard_mut(&self, idx: u16) -> RwLockWriteGuard<'_, BTreeMap<u16, [u8; 256]>> {
+        self.shards[idx as usize % SHARDS].0.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+fn lock_read(shard: &Shard) -> RwLockReadGuard<'_, BTreeMap<u16, [u8; 256]>> {
+    shard.0.read().unwrap_or_else(|e| e.into_inner())
+}
+impl From<Avd> for ShardedAvd {
+    fn from(drive: Avd) -> ShardedAvd {
+        ShardedAvd::new(drive)
+    }
+}
+
+impl Avd {
+    /// Turn the drive into a handle that can be shared between threads, with reads spread over many locks. See [`ShardedAvd`].
+    pub fn into_sharded(self) -> ShardedAvd {
+        ShardedAvd::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn sharded() {
+        let mut drive = Avd::new();
+        drive.set_label("sharded");
+        for i in 0..200 {
+            drive.set_block(i, &[i as u8 + 1; 256]).unwrap();
+        }
+        let d = drive.into_sharded();
+        let threads: Vec<_> = (0..4u16).map(|t| {
+            let d = d.clone();
+            std::thread::spawn(move || {
+                for i in 0..200 {
+                    assert_eq!(d.get_block(i), Some([i as u8 + 1; 256]));
+                }
+                d.set_block(1000 + t, &[1; 256]).unwrap();
+                d.modify_block(2000, |b| b[0] += 1);
+            })
+        }).collect();
+        for t in threads {
+            t.join().unwrap()
+        }
+        d.set_block(0, &[0; 256]).unwrap();
+        assert_eq!(d.used_blocks(), 204);
+        assert_eq!(d.get_block(2000).unwrap()[0], 4);
+        let back = d.to_avd();
+        assert_eq!(back.label(), "sharded");
+        assert!(!back.is_block_used(0));
+        assert_eq!(back.get_block(1003), Some([1; 256]));
+    }
+}
@@ -0,0 +1,110 @@
+//! A drive that can be shared between threads.
+
+use std::path::Path;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use crate::{Avd, Result};
+
+/// A handle to an AVD that can be cloned and sent between threads, so an emulator core and an inspector can use the same drive.
+/// 
+/// Reads take a shared lock and writes take an exclusive one, each only for the length of the call. For anything that needs several operations to happen together, use `read` or `write` to hold the lock yourself. A thread panicking while holding the lock doesn't poison the drive for everyone else.
+/// 
+/// Readers still share one lock; if lots of threads are reading at once, [`crate::ShardedAvd`] spreads them out.
+#[derive(Debug, Clone, Default)]
+pub struct SharedAvd {
+    inner: Arc<RwLock<Avd>>,
+}
+impl SharedAvd {
+    /// Wrap a drive so it can be shared.
+    pub fn new(drive: Avd) -> SharedAvd {
+        SharedAvd {
+            inner: Arc::new(RwLock::new(drive))
+        }
+    }
+    /// Lock the drive for reading. Other readers can carry on, writers wait until the guard is dropped.
+    pub fn read(&self) -> RwLockReadGuard<'_, Avd> {
+        self.inner.read().unwrap_or_else(|e| e.into_inner())
+    }
+    /// Lock the drive for writing. Everyone else waits until the guard is dropped.
+    pub fn write(&self) -> RwLockWriteGuard<'_, Avd> {
+        self.inner.write().unwrap_or_else(|e| e.into_inner())
+    }
+    /// Get a block from the drive.
+    pub fn get_block(&self, idx: u16) -> Option<[u8; 256]> {
+        self.read().get_block(idx)
+    }
+    /// Set a block inside the drive. Fails if the drive is write protected.
+    pub fn set_block(&self, idx: u16, data: &[u8; 256]) -> Result<()> {
+        self.write().set_block(idx, data)
+    }
+    /// Remove a block from the drive, returning its data if it was present.
+    pub fn delete_block(&self, idx: u16) -> Option<[u8; 256]> {
+        self.write().delete_block(idx)
+    }
+    /// Change a block in place, without another thread getting in between the read and the write. See `Avd::modify_block`.
+    pub fn modify_block<R>(&self, idx: u16, f: impl FnOnce(&mut [u8; 256]) -> R) -> R {
+        self.write().modify_block(idx, f)
+    }
+    /// Check if a block is present.
+    pub fn is_block_used(&self, idx: u16) -> bool {
+        self.read().is_block_used(idx)
+    }
+    /// The number of blocks stored on the drive.
+    pub fn used_blocks(&self) -> usize {
+        self.read().used_blocks()
+    }
+    /// Save the drive to a file. Only readers are let in while it's being written.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.read().save(path)
+    }
+    /// Load a file into the drive, overwriting everything on it.
+    pub fn load(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.write().load(path)
+    }
+    /// Take the drive back out, if this is the last handle to it. Otherwise the handle is given back.
+    pub fn try_unwrap(self) -> core::result::Result<Avd, SharedAvd> {
+        match Arc::try_unwrap(self.inner) {
+            Ok(lock) => Ok(lock.into_inner().unwrap_or_else(|e| e.into_inner())),
+            Err(inner) => Err(SharedAvd { inner })
+        }
+    }
+}
+impl From<Avd> for SharedAvd {
+    fn from(drive: Avd) -> SharedAvd {
+        SharedAvd::new(drive)
+    }
+}
+
+impl Avd {
+    /// Turn the drive into a handle that can be shared between threads. See [`SharedAvd`].
+    pub fn into_shared(self) -> SharedAvd {
+        SharedAvd::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn shared() {
+        let drive = Avd::new().into_shared();
+        let threads: Vec<_> = (0..4u8).map(|t| {
+            let d = drive.clone();
+            std::thread::spawn(move || {
+                for i in 0..100 {
+                    d.set_block(t as u16 * 100 + i, &[t + 1; 256]).unwrap();
+                    d.modify_block(1000, |b| b[0] = b[0].wrapping_add(1));
+                }
+            })
+        }).collect();
+        for t in threads {
+            t.join().unwrap()
+        }
+        assert_eq!(drive.used_blocks(), 401);
+        assert_eq!(drive.get_block(1000).unwrap()[0], 144); // 400 wrapping at 256
+        assert_eq!(drive.get_block(350), Some([4; 256]));
+        let d2 = drive.clone();
+        let drive = drive.try_unwrap().unwrap_err();
+        drop(d2);
+        assert_eq!(drive.try_unwrap().unwrap().used_blocks(), 401);
+    }
+}
@@ -0,0 +1,146 @@
+//! Capturing a drive's state and putting it back later, for emulator save states, and named snapshots kept in the archive.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::mem;
+use crate::{Avd, AvdError, FrozenAvd, Result, archive::Format};
+
+/// A drive's state at some point, made with `Avd::snapshot`.
+/// 
+/// Holds the blocks (as a [`FrozenAvd`]), the archive settings, bad sectors, the write-protect tab and the head position. Clones share the same blocks, so keeping a snapshot around in several places is cheap.
+#[derive(Debug, Clone)]
+pub struct AvdSnapshot {
+    blocks: Arc<FrozenAvd>,
+    format: Format,
+    write_protected: bool,
+    head: u16,
+}
+impl AvdSnapshot {
+    /// The blocks in the snapshot.
+    pub fn blocks(&self) -> &FrozenAvd {
+        &self.blocks
+    }
+}
+
+impl Avd {
+    /// Capture the drive's state, to put it back later with `restore`.
+    /// 
+    /// This copies the stored blocks into one compact array, so it costs about as much as copying `bytes_stored` bytes. Absent blocks cost nothing.
+    pub fn snapshot(&self) -> AvdSnapshot {
+        AvdSnapshot {
+            blocks: Arc::new(self.freeze()), format: self.format.clone(), write_protected: self.write_protected, head: self.head
+        }
+    }
+    /// Put the drive back the way it was when `snapshot` was taken. Only blocks that differ from the snapshot are rewritten, and they're marked as changed for incremental saves.
+    /// 
+    /// Like loading, this replaces the whole drive and isn't reported to observers. Named snapshots are left as they are.
+    pub fn restore(&mut self, snapshot: &AvdSnapshot) {
+        self.restore_blocks(&snapshot.blocks);
+        let snapshots = mem::take(&mut self.format.snapshots);
+        self.format = snapshot.format.clone();
+        self.format.snapshots = snapshots;
+        self.write_protected = snapshot.write_protected;
+        self.head = snapshot.head
+    }
+    /// Rewrite the blocks that differ from `blocks`.
+    fn restore_blocks(&mut self, blocks: &FrozenAvd) {
+        let stale: Vec<u16> = self.blocks.keys().copied().filter(|idx| !blocks.is_block_used(*idx)).collect();
+        for idx in stale {
+            self.remove(idx);
+        }
+        for (idx, data) in blocks.blocks() {
+            if self.blocks.get(&idx) != Some(data) {
+                self.insert(idx, *data)
+            }
+        }
+    }
+    /// Keep a copy of the drive's blocks under `name`, replacing any snapshot that already has that name. Panics if the name is longer than 65535 bytes.
+    /// 
+    /// Named snapshots are saved in the archive along with the drive, so one file can hold a base image and any number of snapshots of it. Each is stored as its differences from the drive's blocks, so snapshots that are close to the drive take up little space. Having any means the drive needs archive version 2 to be saved.
+    pub fn create_snapshot(&mut self, name: impl Into<String>) {
+        let name = name.into();
+        assert!(name.len() <= u16::MAX as usize, "snapshot name too long");
+        self.format.snapshots.insert(name, Arc::new(self.freeze()));
+    }
+    /// The names of the named snapshots, in order.
+    pub fn snapshot_names(&self) -> Vec<&str> {
+        self.format.snapshots.keys().map(String::as_str).collect()
+    }
+    /// The blocks in the snapshot called `name`, if there is one.
+    pub fn named_snapshot(&self, name: &str) -> Option<&FrozenAvd> {
+        self.format.snapshots.get(name).map(|s| &**s)
+    }
+    /// Put the drive's blocks back the way they were when the snapshot called `name` was made, like `restore`. The snapshot is kept, so it can be restored again.
+    /// 
+    /// Fails with `AvdError::NoSuchSnapshot` if there isn't one by that name.
+    pub fn restore_snapshot(&mut self, name: &str) -> Result<()> {
+        let snap = self.format.snapshots.get(name).cloned().ok_or_else(|| AvdError::NoSuchSnapshot(name.into()))?;
+        self.restore_blocks(&snap);
+        Ok(())
+    }
+    /// Delete the snapshot called `name`, returning whether there was one.
+    pub fn delete_snapshot(&mut self, name: &str) -> bool {
+        self.format.snapshots.remove(name).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn snapshot() {
+        let mut drive = Avd::new();
+        drive.set_block(1, &[1; 256]).unwrap();
+        drive.set_block(2, &[2; 256]).unwrap();
+        drive.mark_bad(9);
+        let snap = drive.snapshot();
+        let copy = snap.clone();
+        drive.set_block(1, &[5; 256]).unwrap();
+        drive.delete_block(2);
+        drive.set_block(3, &[3; 256]).unwrap();
+        drive.clear_bad(9);
+        drive.set_write_protected(true);
+        drive.restore(&copy);
+        assert_eq!(drive.get_block(1), Some([1; 256]));
+        assert_eq!(drive.get_block(2), Some([2; 256]));
+        assert!(!drive.is_block_used(3));
+        assert!(drive.is_bad(9) && !drive.is_write_protected());
+        assert_eq!(drive.used_blocks(), snap.blocks().used_blocks());
+    }
+    #[test]
+    fn named() {
+        let mut drive = Avd::new();
+        drive.set_block(1, &[1; 256]).unwrap();
+        drive.set_block(2, &[2; 256]).unwrap();
+        drive.create_snapshot("install");
+        drive.set_block(1, &[5; 256]).unwrap();
+        drive.delete_block(2);
+        drive.set_block(3, &[3; 256]).unwrap();
+        drive.create_snapshot("boot");
+        drive.set_block(4, &[4; 256]).unwrap();
+        assert_eq!(drive.archive_version(), crate::ArchiveVersion::V2);
+        let bytes = drive.to_bytes();
+        let copies = [
+            Avd::from_bytes(&bytes).unwrap(),
+            #[cfg(feature = "std")]
+            Avd::read_from(&bytes[..]).unwrap(),
+        ];
+        for mut loaded in copies {
+            assert_eq!(loaded, drive);
+            assert_eq!(loaded.snapshot_names(), ["boot", "install"]);
+            loaded.restore_snapshot("install").unwrap();
+            assert_eq!((loaded.get_block(1), loaded.get_block(2)), (Some([1; 256]), Some([2; 256])));
+            assert_eq!(loaded.used_blocks(), 2);
+            loaded.restore_snapshot("boot").unwrap();
+            assert_eq!(loaded.used_blocks(), 2);
+            assert_eq!(loaded.named_snapshot("boot").unwrap().get_block(3), Some([3; 256]));
+        }
+        let state = drive.snapshot();
+        assert!(drive.delete_snapshot("boot"));
+        assert!(!drive.delete_snapshot("boot"));
+        assert!(matches!(drive.restore_snapshot("boot"), Err(AvdError::NoSuchSnapshot(n)) if n == "boot"));
+        drive.restore(&state);
+        assert_eq!(drive.snapshot_names(), ["install"]);
+    }
+}
@@ -0,0 +1,134 @@
+//! Archives split across several files, for places with file size limits.
+
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use crate::{Avd, Result};
+
+/// The path of volume `n` (counting from 1) of a split archive: `path` with `.001`, `.002` and so on tacked on.
+fn volume_path(path: &Path, n: u32) -> PathBuf {
+    let mut p = OsString::from(path);
+    p.push(format!(".{:03}", n));
+    p.into()
+}
+
+impl Avd {
+    /// Save the AVD as an archive split into volumes of at most `max_size` bytes each, named `path.001`, `path.002` and so on. Returns the number of volumes written.
+    /// 
+    /// Any higher-numbered volumes left over from an earlier, bigger save are removed. Panics if `max_size` is 0.
+    pub fn save_split(&self, path: impl AsRef<Path>, max_size: u64) -> Result<u32> {
+        assert!(max_size > 0, "volumes have to hold at least one byte");
+        let path = path.as_ref();
+        let mut w = SplitWriter {
+            path, max_size, volumes: 0, current: None, written: 0
+        };
+        self.save_to(&mut w)?;
+        let volumes = w.volumes; // there's always a header, so always at least one
+        drop(w);
+        let mut n = volumes + 1;
+        while fs::remove_file(volume_path(path, n)).is_ok() {
+            n += 1
+        }
+        Ok(volumes)
+    }
+    /// Load a split archive saved by `save_split`, reading `path.001`, `path.002` and so on until there are no more. Like `load`, this overwrites the entire drive.
+    pub fn load_split(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let first = BufReader::new(File::open(volume_path(path, 1))?);
+        self.load_from(SplitReader {
+            path, next: 2, current: Some(first)
+        })
+    }
+    /// Load a new AVD from a split archive. See `load_split`.
+    pub fn from_split(path: impl AsRef<Path>) -> Result<Avd> {
+        let mut d = Avd::new();
+        d.load_split(path)?;
+        Ok(d)
+    }
+}
+
+/// Writes to a series of volumes, starting a new one whenever the current one is full.
+struct SplitWriter<'a> {
+    path: &'a Path,
+    max_size: u64,
+    volumes: u32,
+    current: Option<BufWriter<File>>,
+    written: u64,
+}
+impl Write for SplitWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0)
+        }
+        if self.current.is_none() || self.written == self.max_size {
+            if let Some(mut f) = self.current.take() {
+                f.flush()?
+            }
+            self.volumes += 1;
+            self.current = Some(BufWriter::new(File::create(volume_path(self.path, self.volumes))?));
+            self.written = 0;
+        }
+        let room = (self.max_size - self.written).min(buf.len() as u64) as usize;
+        let n = self.current.as_mut().unwrap().write(&buf[..room])?; // SHOULD NEVER PANIC
+        self.written += n as u64;
+        Ok(n)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some(f) => f.flush(),
+            None => Ok(())
+        }
+    }
+}
+
+/// Reads a series of volumes one after the other, stopping at the first one that doesn't exist.
+struct SplitReader<'a> {
+    path: &'a Path,
+    next: u32,
+    current: Option<BufReader<File>>,
+}
+impl Read for SplitReader<'_> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while let Some(f) = &mut self.current {
+            match f.read(out)? {
+                0 if !out.is_empty() => {
+                    self.current = match File::open(volume_path(self.path, self.next)) {
+                        Ok(f) => Some(BufReader::new(f)),
+                        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                        Err(e) => return Err(e)
+                    };
+                    self.next += 1;
+                }
+                n => return Ok(n)
+            }
+        }
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn split() {
+        let path = std::env::temp_dir().join(format!("avd-split-{}.avd", std::process::id()));
+        let mut drive = Avd::new();
+        for i in 0..10 {
+            drive.set_block(i, &[i as u8 + 1; 256]).unwrap();
+        }
+        // 4 + 10 * 258 = 2584 bytes
+        assert_eq!(drive.save_split(&path, 1000).unwrap(), 3);
+        assert_eq!(fs::metadata(volume_path(&path, 1)).unwrap().len(), 1000);
+        assert_eq!(fs::metadata(volume_path(&path, 3)).unwrap().len(), 584);
+        assert_eq!(Avd::from_split(&path).unwrap(), drive);
+        // fewer volumes this time, the old third one has to go
+        drive.delete_range(5..);
+        assert_eq!(drive.save_split(&path, 1000).unwrap(), 2);
+        assert!(!volume_path(&path, 3).exists());
+        assert_eq!(Avd::from_split(&path).unwrap(), drive);
+        for n in 1..=2 {
+            fs::remove_file(volume_path(&path, n)).unwrap();
+        }
+    }
+}
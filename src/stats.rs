@@ -0,0 +1,92 @@
+//! Per-block access counters, for profiling what a guest does with its drive.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use crate::{Access, Avd};
+
+/// How many times a block has been read and written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlockStats {
+    /// Reads.
+    pub reads: u64,
+    /// Writes, including removals.
+    pub writes: u64,
+}
+
+/// A snapshot of the access counters for a whole drive, from `Avd::access_stats`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccessStats {
+    /// Reads across the whole drive.
+    pub reads: u64,
+    /// Writes across the whole drive.
+    pub writes: u64,
+    /// Counts for each block that's been accessed at all.
+    pub blocks: BTreeMap<u16, BlockStats>,
+}
+impl AccessStats {
+    /// Get the counts for one block. Blocks that haven't been touched come back as zeros.
+    pub fn get(&self, idx: u16) -> BlockStats {
+        self.blocks.get(&idx).copied().unwrap_or_default()
+    }
+    /// The `n` blocks with the most accesses (reads plus writes), busiest first. Ties go to the lower index.
+    pub fn hottest(&self, n: usize) -> Vec<(u16, BlockStats)> {
+        let mut v: Vec<_> = self.blocks.iter().map(|(i, s)| (*i, *s)).collect();
+        v.sort_by_key(|(i, s)| (core::cmp::Reverse(s.reads + s.writes), *i));
+        v.truncate(n);
+        v
+    }
+}
+
+/// The counters inside a drive. Reads happen through `&self`, so they need a lock.
+#[derive(Debug, Default)]
+pub(crate) struct Counters(Mutex<BTreeMap<u16, BlockStats>>);
+impl Counters {
+    pub fn count(&self, idx: u16, access: Access) {
+        let mut c = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let s = c.entry(idx).or_default();
+        match access {
+            Access::Read => s.reads += 1,
+            Access::Write | Access::Delete => s.writes += 1
+        }
+    }
+}
+
+impl Avd {
+    /// Get the read and write counts for every block since the drive was created or the counters were last reset.
+    /// 
+    /// Counts cover the same accesses that observers see, see `add_observer`.
+    pub fn access_stats(&self) -> AccessStats {
+        let blocks = self.counters.0.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        AccessStats {
+            reads: blocks.values().map(|s| s.reads).sum(),
+            writes: blocks.values().map(|s| s.writes).sum(),
+            blocks
+        }
+    }
+    /// Set all the access counters back to zero.
+    pub fn reset_access_stats(&mut self) {
+        self.counters.0.get_mut().unwrap_or_else(|e| e.into_inner()).clear()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn stats() {
+        let mut drive = Avd::new();
+        drive.set_block(1, &[1; 256]).unwrap();
+        drive.set_block(1, &[2; 256]).unwrap();
+        drive.get_block(1);
+        for _ in 0..4 {
+            drive.get_block(7);
+        }
+        let stats = drive.access_stats();
+        assert_eq!((stats.reads, stats.writes), (5, 2));
+        assert_eq!(stats.get(1), BlockStats { reads: 1, writes: 2 });
+        assert_eq!(stats.get(2), BlockStats::default());
+        assert_eq!(stats.hottest(1), [(7, BlockStats { reads: 4, writes: 0 })]);
+        drive.reset_access_stats();
+        assert_eq!(drive.access_stats(), AccessStats::default());
+    }
+}
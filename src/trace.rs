@@ -0,0 +1,106 @@
+//! A bounded log of recent accesses, for post-mortem debugging.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+use crate::{Access, Avd, Observer, ObserverId};
+
+/// One access recorded in a [`TraceLog`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// Counts up from 0 for every access the log has seen, including ones that have since been pushed out.
+    pub seq: u64,
+    /// When it happened.
+    pub time: Instant,
+    /// Which block.
+    pub idx: u16,
+    /// What was done to it.
+    pub access: Access,
+}
+
+#[derive(Debug)]
+struct Ring {
+    entries: VecDeque<TraceEntry>,
+    capacity: usize,
+    next: u64,
+}
+
+/// A ring buffer of the most recent accesses to a drive. Once it's full, the oldest entries are dropped.
+/// 
+/// This is an [`Observer`], so it can be registered with `Avd::add_observer`, or use `Avd::enable_trace`. Clones share the same buffer, so keep one around to look at the log.
+#[derive(Debug, Clone)]
+pub struct TraceLog(Arc<Mutex<Ring>>);
+impl TraceLog {
+    /// Create an empty log holding up to `capacity` entries.
+    pub fn new(capacity: usize) -> TraceLog {
+        TraceLog(Arc::new(Mutex::new(Ring {
+            entries: VecDeque::with_capacity(capacity), capacity, next: 0
+        })))
+    }
+    fn lock(&self) -> MutexGuard<'_, Ring> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+    /// Look at the log, oldest entry first, without copying it. The drive can't record anything while `f` runs.
+    pub fn with_entries<R>(&self, f: impl FnOnce(&[TraceEntry]) -> R) -> R {
+        f(self.lock().entries.make_contiguous())
+    }
+    /// Copy the log, oldest entry first.
+    pub fn snapshot(&self) -> Vec<TraceEntry> {
+        self.with_entries(|e| e.to_vec())
+    }
+    /// Take everything out of the log, oldest entry first, leaving it empty.
+    pub fn drain(&self) -> Vec<TraceEntry> {
+        self.lock().entries.drain(..).collect()
+    }
+    /// How many entries are in the log.
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+    /// Check if the log is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+impl Observer for TraceLog {
+    fn on_access(&self, idx: u16, access: Access) {
+        let mut r = self.lock();
+        if r.capacity == 0 {
+            return
+        }
+        if r.entries.len() == r.capacity {
+            r.entries.pop_front();
+        }
+        let seq = r.next;
+        r.next += 1;
+        r.entries.push_back(TraceEntry {
+            seq, time: Instant::now(), idx, access
+        })
+    }
+}
+
+impl Avd {
+    /// Start recording the last `capacity` accesses to the drive. Returns the log, and the observer id to stop it with `remove_observer`.
+    pub fn enable_trace(&mut self, capacity: usize) -> (TraceLog, ObserverId) {
+        let log = TraceLog::new(capacity);
+        let id = self.add_observer(log.clone());
+        (log, id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn trace() {
+        let mut drive = Avd::new();
+        let (log, _) = drive.enable_trace(2);
+        drive.set_block(1, &[1; 256]).unwrap();
+        drive.get_block(1);
+        drive.delete_block(1);
+        let entries = log.snapshot();
+        assert_eq!(entries.iter().map(|e| (e.seq, e.idx, e.access)).collect::<Vec<_>>(), [(1, 1, Access::Read), (2, 1, Access::Delete)]);
+        assert!(entries[0].time <= entries[1].time);
+        assert_eq!(log.drain(), entries);
+        assert!(log.is_empty());
+    }
+}
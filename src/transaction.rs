@@ -0,0 +1,105 @@
+//! Groups of block changes that happen all at once or not at all.
+
+use alloc::collections::BTreeMap;
+use crate::{Avd, AvdError, Result, is_zero};
+
+/// A batch of block changes staged against a drive, from `Avd::begin_transaction`.
+/// 
+/// Changes are kept to one side until `commit`, which applies them all in one go. Until then the drive is untouched, and reads through the transaction see the staged changes. Dropping a transaction without committing it throws the changes away, so bailing out with `?` halfway through a multi-block update leaves the drive consistent.
+#[derive(Debug)]
+#[must_use = "dropping a transaction throws its changes away"]
+pub struct Transaction<'a> {
+    drive: &'a mut Avd,
+    /// `None` means the block is being removed.
+    staged: BTreeMap<u16, Option<[u8; 256]>>,
+}
+impl Transaction<'_> {
+    /// Get a block as it will be once the transaction is committed.
+    pub fn get_block(&self, idx: u16) -> Option<[u8; 256]> {
+        match self.staged.get(&idx) {
+            Some(b) => *b,
+            None => self.drive.get_block(idx)
+        }
+    }
+    /// Stage a block write. The same checks as `Avd::set_block` happen straight away, so a commit can't fail halfway.
+    pub fn set_block(&mut self, idx: u16, data: &[u8; 256]) -> Result<()> {
+        if self.drive.write_protected {
+            return Err(AvdError::WriteProtected)
+        }
+        self.drive.check_bad(idx)?;
+        let data = if self.drive.elide_zeros && is_zero(data) { None } else { Some(*data) };
+        self.staged.insert(idx, data);
+        Ok(())
+    }
+    /// Stage a block removal.
+    pub fn delete_block(&mut self, idx: u16) {
+        self.staged.insert(idx, None);
+    }
+    /// The number of blocks with changes staged.
+    pub fn len(&self) -> usize {
+        self.staged.len()
+    }
+    /// Check if nothing has been staged.
+    pub fn is_empty(&self) -> bool {
+        self.staged.is_empty()
+    }
+    /// Apply every staged change to the drive.
+    pub fn commit(self) {
+        for (idx, data) in self.staged {
+            match data {
+                Some(data) => self.drive.store_block(idx, &data),
+                None => {
+                    self.drive.delete_block(idx);
+                }
+            }
+        }
+    }
+    /// Throw away every staged change. Dropping the transaction does the same.
+    pub fn rollback(self) {}
+}
+
+impl Avd {
+    /// Start staging a batch of changes to apply together. See [`Transaction`].
+    pub fn begin_transaction(&mut self) -> Transaction<'_> {
+        Transaction {
+            drive: self, staged: BTreeMap::new()
+        }
+    }
+    /// Run `f` with a new transaction, committing it if `f` returns `Ok` and rolling it back if it returns an error.
+    pub fn transaction<R>(&mut self, f: impl FnOnce(&mut Transaction<'_>) -> Result<R>) -> Result<R> {
+        let mut tx = self.begin_transaction();
+        let ret = f(&mut tx)?;
+        tx.commit();
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn transaction() {
+        let mut drive = Avd::new();
+        drive.set_block(0, &[1; 256]).unwrap();
+        drive.mark_bad(9);
+        let ret = drive.transaction(|tx| {
+            tx.set_block(1, &[2; 256])?;
+            tx.delete_block(0);
+            assert_eq!((tx.get_block(0), tx.get_block(1)), (None, Some([2; 256])));
+            tx.set_block(9, &[3; 256])
+        });
+        assert!(matches!(ret, Err(AvdError::BadSector(9))));
+        assert_eq!((drive.get_block(0), drive.get_block(1)), (Some([1; 256]), None));
+
+        let mut tx = drive.begin_transaction();
+        tx.set_block(1, &[2; 256]).unwrap();
+        tx.set_block(0, &[0; 256]).unwrap();
+        assert_eq!(tx.len(), 2);
+        tx.commit();
+        assert_eq!((drive.get_block(0), drive.get_block(1)), (None, Some([2; 256])));
+        let mut tx = drive.begin_transaction();
+        tx.delete_block(1);
+        tx.rollback();
+        assert!(drive.is_block_used(1));
+    }
+}
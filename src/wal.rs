@@ -0,0 +1,212 @@
+//! Write-ahead logging, so changes made since the last save survive a crash.
+//! 
+//! The log sits next to the archive, at the archive's path with `.wal` on the end. It starts with the magic bytes `AVW` and a version byte (0), followed by any number of entries, each a tag byte, a big-endian `u16` block index, for tag 1 the 256 bytes of new data, and a big-endian CRC32 of everything before it in the entry:
+//! 
+//! - tag 0: the block was removed.
+//! - tag 1: the block was written.
+//! 
+//! Entries are checked as they're replayed, and replaying stops at the first one that's cut short or doesn't match its CRC, since that's where the program died.
+
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use crate::{Avd, AvdError, Result};
+
+const WAL_HEADER: [u8; 4] = *b"AVW\x00";
+
+/// The path of the log for the archive at `path`.
+fn wal_path(path: &Path) -> PathBuf {
+    let mut p = OsString::from(path);
+    p.push(".wal");
+    p.into()
+}
+
+/// Read the archive at `path` (or start empty if there isn't one), and replay its log on top. Returns the drive, how many entries were replayed, and how much of the log was good.
+fn load_with_log(path: &Path) -> Result<(Avd, usize, usize)> {
+    let mut drive = match Avd::from_host_drive(path) {
+        Ok(d) => d,
+        Err(AvdError::FsError(e)) if e.kind() == ErrorKind::NotFound => Avd::new(),
+        Err(e) => return Err(e)
+    };
+    let log = match fs::read(wal_path(path)) {
+        Ok(l) => l,
+        Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e.into())
+    };
+    if log.len() < WAL_HEADER.len() {
+        return Ok((drive, 0, 0)) // missing, or the header itself got cut off
+    }
+    if log[..4] != WAL_HEADER {
+        return Err(AvdError::MalformedArchive)
+    }
+    let (mut pos, mut entries) = (4, 0);
+    while let Some(&tag) = log.get(pos) {
+        let len = match tag {
+            0 => 7,
+            1 => 7 + 256,
+            _ => break
+        };
+        let Some(entry) = log.get(pos..pos + len) else {
+            break
+        };
+        let (body, crc) = entry.split_at(len - 4);
+        if crc32fast::hash(body).to_be_bytes() != crc {
+            break
+        }
+        let idx = u16::from_be_bytes([body[1], body[2]]);
+        match tag {
+            0 => {
+                drive.delete_block(idx);
+            }
+            _ => drive.store_block(idx, body[3..].try_into().unwrap()) // SHOULD NEVER PANIC
+        }
+        pos += len;
+        entries += 1
+    }
+    Ok((drive, entries, pos))
+}
+
+/// A drive that logs every change to a write-ahead log before making it, so long sessions don't lose everything since the last save when the program dies.
+/// 
+/// The archive itself is only written by `checkpoint`, which saves the drive and empties the log. Opening the drive again (or calling `Avd::recover`) replays whatever's in the log on top of the archive. Each entry goes straight to the OS, so it survives the program crashing; use `sync` to make sure it survives the machine going down as well.
+#[derive(Debug)]
+pub struct WalAvd {
+    path: PathBuf,
+    log: File,
+    drive: Avd,
+    /// The number of entries in the log.
+    entries: usize,
+}
+impl WalAvd {
+    /// Open the archive at `path` with a write-ahead log, replaying anything left in the log from last time. If there's no archive, the drive starts out empty and the archive is created at the first `checkpoint`.
+    pub fn open(path: impl AsRef<Path>) -> Result<WalAvd> {
+        let path = path.as_ref().to_owned();
+        let (drive, entries, good) = load_with_log(&path)?;
+        let log = OpenOptions::new().append(true).create(true).open(wal_path(&path))?;
+        if good == 0 {
+            log.set_len(0)?;
+            (&log).write_all(&WAL_HEADER)?;
+        }
+        else {
+            log.set_len(good as u64)?; // throw away a torn entry at the end
+        }
+        Ok(WalAvd {
+            path, log, drive, entries
+        })
+    }
+    /// Get a block from the drive.
+    pub fn get_block(&self, idx: u16) -> Option<[u8; 256]> {
+        self.drive.get_block(idx)
+    }
+    /// Set a block on the drive, logging it first. Fails without logging anything if the drive is write protected or the block is bad.
+    pub fn set_block(&mut self, idx: u16, data: &[u8; 256]) -> Result<()> {
+        if self.drive.is_write_protected() {
+            return Err(AvdError::WriteProtected)
+        }
+        self.drive.check_bad(idx)?;
+        let mut entry = vec![1];
+        entry.extend(idx.to_be_bytes());
+        entry.extend(data);
+        self.append(entry)?;
+        self.drive.set_block(idx, data)
+    }
+    /// Remove a block from the drive, logging it first.
+    pub fn delete_block(&mut self, idx: u16) -> Result<()> {
+        if self.drive.is_block_used(idx) {
+            let mut entry = vec![0];
+            entry.extend(idx.to_be_bytes());
+            self.append(entry)?;
+            self.drive.delete_block(idx);
+        }
+        Ok(())
+    }
+    fn append(&mut self, mut entry: Vec<u8>) -> Result<()> {
+        let crc = crc32fast::hash(&entry);
+        entry.extend(crc.to_be_bytes());
+        self.log.write_all(&entry)?;
+        self.entries += 1;
+        Ok(())
+    }
+    /// The contents of the drive.
+    pub fn drive(&self) -> &Avd {
+        &self.drive
+    }
+    /// Set or clear the drive's write-protect tab, like `Avd::set_write_protected`.
+    pub fn set_write_protected(&mut self, protected: bool) {
+        self.drive.set_write_protected(protected)
+    }
+    /// Whether the drive is write protected.
+    pub fn is_write_protected(&self) -> bool {
+        self.drive.is_write_protected()
+    }
+    /// The number of entries in the log. This goes up with every change, until the next `checkpoint`.
+    pub fn wal_len(&self) -> usize {
+        self.entries
+    }
+    /// Make sure everything logged so far has hit the disk.
+    pub fn sync(&self) -> Result<()> {
+        self.log.sync_data()?;
+        Ok(())
+    }
+    /// Save the drive to the archive, then empty the log. The save is atomic, and the log is only emptied once it's done, so a crash at any point loses nothing.
+    pub fn checkpoint(&mut self) -> Result<()> {
+        self.drive.save(&self.path)?;
+        self.log.set_len(0)?;
+        self.log.write_all(&WAL_HEADER)?;
+        self.log.sync_data()?;
+        self.entries = 0;
+        Ok(())
+    }
+    /// Turn this into a normal AVD. The archive and log are left as they are.
+    pub fn into_avd(self) -> Avd {
+        self.drive
+    }
+}
+
+impl Avd {
+    /// Open an archive file with a write-ahead log next to it. See [`WalAvd`].
+    pub fn open_wal(path: impl AsRef<Path>) -> Result<WalAvd> {
+        WalAvd::open(path)
+    }
+    /// Load the archive at `path` and replay its write-ahead log on top, to get back the drive as it was when the program died. Nothing on disk is changed; open it with `open_wal` to carry on logging.
+    pub fn recover(path: impl AsRef<Path>) -> Result<Avd> {
+        Ok(load_with_log(path.as_ref())?.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn wal() {
+        let path = std::env::temp_dir().join(format!("avd-wal-{}.avd", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(wal_path(&path));
+        let mut w = Avd::open_wal(&path).unwrap();
+        w.set_block(1, &[1; 256]).unwrap();
+        w.checkpoint().unwrap();
+        w.set_block(2, &[2; 256]).unwrap();
+        w.delete_block(1).unwrap();
+        assert_eq!(w.wal_len(), 2);
+        drop(w); // "crash"
+        assert_eq!(Avd::from_host_drive(&path).unwrap().get_block(1), Some([1; 256]));
+        let recovered = Avd::recover(&path).unwrap();
+        assert_eq!((recovered.get_block(1), recovered.get_block(2)), (None, Some([2; 256])));
+
+        // a torn entry at the end gets dropped
+        OpenOptions::new().append(true).open(wal_path(&path)).unwrap().write_all(&[1, 0, 5, 5]).unwrap();
+        let mut w = Avd::open_wal(&path).unwrap();
+        assert_eq!(w.wal_len(), 2);
+        assert_eq!(*w.drive(), recovered);
+        w.set_write_protected(true);
+        assert!(matches!(w.set_block(3, &[3; 256]), Err(AvdError::WriteProtected)));
+        assert_eq!(w.wal_len(), 2);
+        w.set_write_protected(false);
+        w.checkpoint().unwrap();
+        assert_eq!(fs::metadata(wal_path(&path)).unwrap().len(), 4);
+        assert_eq!(Avd::from_host_drive(&path).unwrap(), recovered);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(wal_path(&path)).unwrap();
+    }
+}
@@ -0,0 +1,97 @@
+//! Noticing when a drive's file is changed by something else.
+
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use crate::{Avd, Result, SharedAvd};
+
+/// Watches a drive file for changes until it's dropped. Made with `Avd::watch_file` or `SharedAvd::reload_on_change`.
+/// 
+/// The file's directory is watched, not the file itself, so the watch keeps working when the file is replaced by a rename, like `save` does. That means saving the file yourself counts as a change too.
+#[derive(Debug)]
+pub struct FileWatcher {
+    _watcher: RecommendedWatcher,
+    path: PathBuf,
+}
+impl FileWatcher {
+    fn new(path: &Path, mut on_change: impl FnMut() + Send + 'static) -> Result<FileWatcher> {
+        let name: OsString = path.file_name().ok_or_else(|| io::Error::other("can't watch a path without a file name"))?.to_owned();
+        let dir = match path.parent() {
+            Some(d) if !d.as_os_str().is_empty() => d,
+            _ => Path::new(".")
+        };
+        let mut watcher = notify::recommended_watcher(move |ev: notify::Result<Event>| {
+            if let Ok(ev) = ev {
+                let relevant = ev.kind.is_create() || ev.kind.is_modify();
+                if relevant && ev.paths.iter().any(|p| p.file_name() == Some(&name)) {
+                    on_change()
+                }
+            }
+        }).map_err(io::Error::other)?;
+        watcher.watch(dir, RecursiveMode::NonRecursive).map_err(io::Error::other)?;
+        Ok(FileWatcher {
+            _watcher: watcher, path: path.to_owned()
+        })
+    }
+    /// The file being watched.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Avd {
+    /// Call `on_change` (on another thread) whenever the file at `path` is created or modified, until the returned watcher is dropped. See [`FileWatcher`].
+    /// 
+    /// A single change to the file often shows up as several calls, and the file might be half written during the first ones. To get changes over a channel instead, send on it from `on_change`.
+    pub fn watch_file(path: impl AsRef<Path>, on_change: impl FnMut() + Send + 'static) -> Result<FileWatcher> {
+        FileWatcher::new(path.as_ref(), on_change)
+    }
+}
+
+impl SharedAvd {
+    /// Reload the drive from `path` whenever the file changes, until the returned watcher is dropped. Changes that haven't been saved are thrown away!
+    /// 
+    /// If the file can't be loaded (say, it's only half written), the drive is left alone and the next change is tried instead. This is meant for editing a drive's file while the emulator isn't using it; anything written to the drive just after saving it can be lost when the save itself is noticed.
+    pub fn reload_on_change(&self, path: impl AsRef<Path>) -> Result<FileWatcher> {
+        let (drive, file) = (self.clone(), path.as_ref().to_owned());
+        FileWatcher::new(path.as_ref(), move || {
+            let _ = drive.load(&file);
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+    #[test]
+    fn watch() {
+        let dir = std::env::temp_dir().join(format!("avd-watch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("disk.avd");
+        Avd::new().save(&path).unwrap();
+        let (tx, rx) = mpsc::channel();
+        let _w = Avd::watch_file(&path, move || {
+            let _ = tx.send(());
+        }).unwrap();
+        let shared = Avd::new().into_shared();
+        let _r = shared.reload_on_change(&path).unwrap();
+        std::fs::write(dir.join("other.avd"), b"AVD\0").unwrap();
+        assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+
+        let mut edited = Avd::new();
+        edited.set_block(7, &[7; 256]).unwrap();
+        edited.save(&path).unwrap();
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        for _ in 0..100 {
+            if shared.is_block_used(7) {
+                break
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(shared.get_block(7), Some([7; 256]));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,92 @@
+//! Data breakpoints on single blocks, built on observers.
+
+#[cfg(feature = "std")]
+use std::sync::mpsc::{Receiver, channel};
+use crate::{Access, Avd, ObserverId};
+
+/// Which accesses a watchpoint fires on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WatchKind {
+    /// Reads.
+    Read,
+    /// Writes and removals.
+    Write,
+    /// Anything.
+    Either,
+}
+impl WatchKind {
+    /// Check if an access should set off this kind of watchpoint.
+    pub fn matches(self, access: Access) -> bool {
+        match self {
+            WatchKind::Read => access == Access::Read,
+            WatchKind::Write => access != Access::Read,
+            WatchKind::Either => true
+        }
+    }
+}
+
+/// A handle to a watchpoint, for removing it again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WatchpointId(ObserverId);
+
+impl Avd {
+    /// Call `on_hit` whenever block `idx` is accessed in a way that matches `kind`. Returns a handle for `remove_watchpoint`.
+    /// 
+    /// Watchpoints are observers that only look at one block, so they see the same accesses, see `add_observer`.
+    pub fn add_watchpoint(&mut self, idx: u16, kind: WatchKind, on_hit: impl Fn(Access) + Send + Sync + 'static) -> WatchpointId {
+        WatchpointId(self.add_observer(move |i, access| {
+            if i == idx && kind.matches(access) {
+                on_hit(access)
+            }
+        }))
+    }
+    /// Add a watchpoint that sends each hit down a channel, for debuggers running on another thread. The hits can be polled with `try_recv`.
+    #[cfg(feature = "std")]
+    pub fn watchpoint_channel(&mut self, idx: u16, kind: WatchKind) -> (WatchpointId, Receiver<(u16, Access)>) {
+        let (tx, rx) = channel();
+        let id = self.add_watchpoint(idx, kind, move |access| {
+            let _ = tx.send((idx, access));
+        });
+        (id, rx)
+    }
+    /// Remove a watchpoint. Returns `false` if it had already been removed.
+    pub fn remove_watchpoint(&mut self, id: WatchpointId) -> bool {
+        self.remove_observer(id.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn watchpoint_callback() {
+        use alloc::sync::Arc;
+        use core::sync::atomic::{AtomicUsize, Ordering};
+        let mut drive = Avd::new();
+        let hits = Arc::new(AtomicUsize::new(0));
+        let h = hits.clone();
+        let id = drive.add_watchpoint(5, WatchKind::Read, move |_| {
+            h.fetch_add(1, Ordering::Relaxed);
+        });
+        drive.set_block(5, &[5; 256]).unwrap();
+        drive.get_block(5);
+        drive.get_block(6);
+        assert_eq!(hits.load(Ordering::Relaxed), 1);
+        assert!(drive.remove_watchpoint(id) && !drive.remove_watchpoint(id));
+    }
+    #[cfg(feature = "std")]
+    #[test]
+    fn watchpoints() {
+        let mut drive = Avd::new();
+        let (id, hits) = drive.watchpoint_channel(0, WatchKind::Write);
+        drive.get_block(0);
+        drive.set_block(1, &[1; 256]).unwrap();
+        drive.set_block(0, &[1; 256]).unwrap();
+        drive.delete_block(0);
+        assert_eq!(hits.try_iter().collect::<Vec<_>>(), [(0, Access::Write), (0, Access::Delete)]);
+        assert!(drive.remove_watchpoint(id));
+        drive.set_block(0, &[1; 256]).unwrap();
+        assert!(hits.try_recv().is_err());
+        assert!(WatchKind::Either.matches(Access::Read) && !WatchKind::Read.matches(Access::Delete));
+    }
+}